@@ -0,0 +1,42 @@
+//! Compares writing the same document 10,000 times with [SMLWriter]
+//! against rendering it from an [SmlTemplate]. Run with
+//! `cargo bench -p simpleml --bench template`.
+
+use std::time::{Duration, Instant};
+
+use simpleml::{parse_with_comments, serializes_same, SMLWriter, SMLWriterOptions, SmlTemplate};
+
+const ITERATIONS: usize = 10_000;
+
+fn main() {
+    let (tree, comments) = parse_with_comments(include_str!("../example.txt")).unwrap();
+    let options = SMLWriterOptions::default()
+        .with_comments(comments)
+        .with_end_keyword(Some("End"));
+    let template = SmlTemplate::new(SMLWriter::with_options(
+        simpleml::parse(include_str!("../example.txt")).unwrap(),
+        options.clone(),
+    ))
+    .unwrap();
+    let expected = template.render().unwrap();
+
+    // serializes_same writes the whole tree with SMLWriter each time.
+    let without_template = time(|| assert!(serializes_same(&tree, &expected, &options).unwrap()));
+    let with_template = time(|| assert_eq!(expected, template.render().unwrap()));
+
+    println!(
+        "{} writes: {:?} without the template, {:?} with it ({:.1}x faster)",
+        ITERATIONS,
+        without_template,
+        with_template,
+        without_template.as_secs_f64() / with_template.as_secs_f64()
+    );
+}
+
+fn time(mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    start.elapsed()
+}
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// parse must return an error instead of panicking, whatever the input.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source_text) = std::str::from_utf8(data) {
+        let _ = simpleml::parse(source_text);
+    }
+});
@@ -0,0 +1,690 @@
+use std::{error::Error, fmt::Display};
+use tree_iterators_rs::prelude::TreeNode;
+
+use crate::{SMLAttribute, SMLElement};
+
+/// Converts the tree to compact JSON with no whitespace. Each element
+/// becomes an object with "name", "attributes" (an array of objects with
+/// "name" and "values"), and "children", always in that order. Null
+/// values become JSON null. For example:
+///
+/// ```json
+/// {"name":"Root","attributes":[{"name":"Size","values":["1",null]}],"children":[]}
+/// ```
+pub fn to_json<StrAsRef>(tree: &TreeNode<SMLElement<StrAsRef>>) -> String
+where
+    StrAsRef: AsRef<str>,
+{
+    let mut result = String::new();
+    write_element(tree, None, 0, &mut result);
+    result
+}
+
+/// Equivalent to [to_json], but produces indented, human-readable JSON
+/// of the same shape. Nested objects are indented by indent spaces per
+/// level, and value arrays are kept on a single line.
+pub fn to_json_pretty<StrAsRef>(tree: &TreeNode<SMLElement<StrAsRef>>, indent: usize) -> String
+where
+    StrAsRef: AsRef<str>,
+{
+    let mut result = String::new();
+    write_element(tree, Some(indent), 0, &mut result);
+    result
+}
+
+/// Rebuilds a tree from JSON in the shape [to_json] produces. Every
+/// element object must have a string "name". "attributes" and "children"
+/// may be left out, in which case they are empty, and any other keys are
+/// ignored. Attribute values must be strings or null, and null becomes
+/// None.
+///
+/// Arrays and objects may only be nested [MAX_JSON_DEPTH] deep, so that
+/// untrusted JSON can't overflow the stack. Since each element is an
+/// object inside of its parent's "children" array, this allows trees up
+/// to half as deep.
+pub fn from_json(json: &str) -> Result<TreeNode<SMLElement<String>>, JsonError> {
+    let mut parser = JsonParser {
+        json,
+        position: 0,
+        depth: 0,
+    };
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.position != json.len() {
+        return Err(parser.error(JsonErrorType::Syntax));
+    }
+    element_from_json(value)
+}
+
+fn element_from_json(json: Json) -> Result<TreeNode<SMLElement<String>>, JsonError> {
+    let position = json.position;
+    let mut name = None;
+    let mut attributes = Vec::new();
+    let mut children = Vec::new();
+    for (key, value) in json.into_object()? {
+        match key.as_str() {
+            "name" => name = Some(value.into_string()?),
+            "attributes" => {
+                for attribute in value.into_array()? {
+                    attributes.push(attribute_from_json(attribute)?);
+                }
+            }
+            "children" => {
+                for child in value.into_array()? {
+                    children.push(element_from_json(child)?);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match name {
+        None => Err(JsonError {
+            err_type: JsonErrorType::MissingName,
+            position,
+        }),
+        Some(name) => Ok(TreeNode {
+            value: SMLElement { name, attributes },
+            children,
+        }),
+    }
+}
+
+fn attribute_from_json(json: Json) -> Result<SMLAttribute<String>, JsonError> {
+    let position = json.position;
+    let mut name = None;
+    let mut values = Vec::new();
+    for (key, value) in json.into_object()? {
+        match key.as_str() {
+            "name" => name = Some(value.into_string()?),
+            "values" => {
+                for value in value.into_array()? {
+                    match value.value {
+                        JsonValue::Null => values.push(None),
+                        _ => values.push(Some(value.into_string()?)),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match name {
+        None => Err(JsonError {
+            err_type: JsonErrorType::MissingName,
+            position,
+        }),
+        Some(name) => Ok(SMLAttribute { name, values }),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonError {
+    err_type: JsonErrorType,
+    position: usize,
+}
+
+impl JsonError {
+    pub fn err_type(&self) -> JsonErrorType {
+        self.err_type
+    }
+    /// The byte offset in the JSON text where the problem was found.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+/// The deepest arrays and objects may be nested in the JSON passed to
+/// [from_json].
+pub const MAX_JSON_DEPTH: usize = 512;
+
+impl Error for JsonError {}
+impl Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "(byte: {}) ", self.position)?;
+        match self.err_type {
+            JsonErrorType::Syntax => write!(f, "Invalid JSON")?,
+            JsonErrorType::MissingName => write!(f, "Missing Name")?,
+            JsonErrorType::UnexpectedType => write!(f, "Unexpected Type")?,
+            JsonErrorType::MaxDepthExceeded => write!(f, "Max Depth Exceeded")?,
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonErrorType {
+    /// The text wasn't valid JSON.
+    Syntax,
+    /// An element or attribute object had no "name".
+    MissingName,
+    /// A value had the wrong JSON type for its place in the shape (ex. a
+    /// number as an attribute value).
+    UnexpectedType,
+    /// Arrays and objects were nested deeper than [MAX_JSON_DEPTH].
+    MaxDepthExceeded,
+}
+
+/// A parsed JSON value along with the byte offset it started at.
+struct Json {
+    position: usize,
+    value: JsonValue,
+}
+
+enum JsonValue {
+    Null,
+    /// Booleans and numbers are only ever rejected, so their contents
+    /// aren't kept.
+    Other,
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn unexpected_type(&self) -> JsonError {
+        JsonError {
+            err_type: JsonErrorType::UnexpectedType,
+            position: self.position,
+        }
+    }
+
+    fn into_string(self) -> Result<String, JsonError> {
+        match self.value {
+            JsonValue::String(string) => Ok(string),
+            _ => Err(self.unexpected_type()),
+        }
+    }
+
+    fn into_array(self) -> Result<Vec<Json>, JsonError> {
+        match self.value {
+            JsonValue::Array(array) => Ok(array),
+            _ => Err(self.unexpected_type()),
+        }
+    }
+
+    fn into_object(self) -> Result<Vec<(String, Json)>, JsonError> {
+        match self.value {
+            JsonValue::Object(object) => Ok(object),
+            _ => Err(self.unexpected_type()),
+        }
+    }
+}
+
+/// A minimal recursive descent JSON parser, so that [from_json] doesn't
+/// need any dependencies.
+struct JsonParser<'a> {
+    json: &'a str,
+    position: usize,
+    /// The number of arrays and objects currently open.
+    depth: usize,
+}
+
+impl JsonParser<'_> {
+    fn error(&self, err_type: JsonErrorType) -> JsonError {
+        JsonError {
+            err_type,
+            position: self.position,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.json[self.position..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.position += ch.len_utf8();
+        Some(ch)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ' | '\t' | '\n' | '\r') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), JsonError> {
+        self.skip_whitespace();
+        if self.peek() == Some(expected) {
+            self.position += expected.len_utf8();
+            Ok(())
+        } else {
+            Err(self.error(JsonErrorType::Syntax))
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Json, JsonError> {
+        self.skip_whitespace();
+        let position = self.position;
+        let value = match self.peek() {
+            Some('{') => self.parse_nested(Self::parse_object)?,
+            Some('[') => self.parse_nested(Self::parse_array)?,
+            Some('"') => JsonValue::String(self.parse_string()?),
+            Some('n') => self.parse_literal("null", JsonValue::Null)?,
+            Some('t') => self.parse_literal("true", JsonValue::Other)?,
+            Some('f') => self.parse_literal("false", JsonValue::Other)?,
+            Some('-' | '0'..='9') => self.parse_number()?,
+            _ => return Err(self.error(JsonErrorType::Syntax)),
+        };
+        Ok(Json { position, value })
+    }
+
+    fn parse_literal(&mut self, literal: &str, value: JsonValue) -> Result<JsonValue, JsonError> {
+        if self.json[self.position..].starts_with(literal) {
+            self.position += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error(JsonErrorType::Syntax))
+        }
+    }
+
+    /// Parses an array or object, failing if it's nested too deeply.
+    fn parse_nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<JsonValue, JsonError>,
+    ) -> Result<JsonValue, JsonError> {
+        if self.depth == MAX_JSON_DEPTH {
+            return Err(self.error(JsonErrorType::MaxDepthExceeded));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    /// Parses a number in the form JSON allows: an optional '-', an integer
+    /// part without leading zeros, then an optional fraction and exponent.
+    fn parse_number(&mut self) -> Result<JsonValue, JsonError> {
+        let start = self.position;
+        if self.peek() == Some('-') {
+            self.position += 1;
+        }
+        match self.peek() {
+            Some('0') => self.position += 1,
+            Some('1'..='9') => self.skip_digits(),
+            _ => return Err(self.error(JsonErrorType::Syntax)),
+        }
+        if self.peek() == Some('.') {
+            self.position += 1;
+            self.expect_digits()?;
+        }
+        if let Some('e' | 'E') = self.peek() {
+            self.position += 1;
+            if let Some('+' | '-') = self.peek() {
+                self.position += 1;
+            }
+            self.expect_digits()?;
+        }
+
+        // A number can't run straight into more digits or number characters
+        // (ex. the leading zero in "01").
+        match self.peek() {
+            Some('+' | '-' | '.' | 'e' | 'E' | '0'..='9') => {
+                self.position = start;
+                Err(self.error(JsonErrorType::Syntax))
+            }
+            _ => Ok(JsonValue::Other),
+        }
+    }
+
+    fn skip_digits(&mut self) {
+        while let Some('0'..='9') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn expect_digits(&mut self) -> Result<(), JsonError> {
+        if !matches!(self.peek(), Some('0'..='9')) {
+            return Err(self.error(JsonErrorType::Syntax));
+        }
+        self.skip_digits();
+        Ok(())
+    }
+
+    fn parse_array(&mut self) -> Result<JsonValue, JsonError> {
+        self.expect('[')?;
+        let mut array = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(JsonValue::Array(array));
+        }
+        loop {
+            array.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => {}
+                Some(']') => return Ok(JsonValue::Array(array)),
+                _ => return Err(self.error(JsonErrorType::Syntax)),
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<JsonValue, JsonError> {
+        self.expect('{')?;
+        let mut object = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(JsonValue::Object(object));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.expect(':')?;
+            object.push((key, self.parse_value()?));
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => {}
+                Some('}') => return Ok(JsonValue::Object(object)),
+                _ => return Err(self.error(JsonErrorType::Syntax)),
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, JsonError> {
+        self.expect('"')?;
+        let mut result = String::new();
+        loop {
+            match self.next() {
+                None => return Err(self.error(JsonErrorType::Syntax)),
+                Some('"') => return Ok(result),
+                Some('\\') => match self.next() {
+                    Some('"') => result.push('"'),
+                    Some('\\') => result.push('\\'),
+                    Some('/') => result.push('/'),
+                    Some('b') => result.push('\u{0008}'),
+                    Some('f') => result.push('\u{000C}'),
+                    Some('n') => result.push('\n'),
+                    Some('r') => result.push('\r'),
+                    Some('t') => result.push('\t'),
+                    Some('u') => result.push(self.parse_unicode_escape()?),
+                    _ => return Err(self.error(JsonErrorType::Syntax)),
+                },
+                Some(ch) if ch < ' ' => return Err(self.error(JsonErrorType::Syntax)),
+                Some(ch) => result.push(ch),
+            }
+        }
+    }
+
+    /// Parses the hex digits of a \u escape, including the second half of
+    /// a surrogate pair if there is one.
+    fn parse_unicode_escape(&mut self) -> Result<char, JsonError> {
+        let high = self.parse_hex4()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.error(JsonErrorType::Syntax));
+        }
+
+        if !self.json[self.position..].starts_with("\\u") {
+            return Err(self.error(JsonErrorType::Syntax));
+        }
+        self.position += 2;
+        let low = self.parse_hex4()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err(self.error(JsonErrorType::Syntax));
+        }
+        char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
+            .ok_or_else(|| self.error(JsonErrorType::Syntax))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, JsonError> {
+        let digits = self
+            .json
+            .get(self.position..self.position + 4)
+            .filter(|digits| digits.chars().all(|ch| ch.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error(JsonErrorType::Syntax))?;
+        self.position += 4;
+        Ok(u32::from_str_radix(digits, 16).unwrap())
+    }
+}
+
+/// Starts a new entry within an object or array at the given depth. When
+/// indent is None, the output is compact and has no whitespace at all.
+fn push_entry_start(indent: Option<usize>, depth: usize, buf: &mut String) {
+    if let Some(indent) = indent {
+        buf.push('\n');
+        buf.extend(std::iter::repeat_n(' ', indent * depth));
+    }
+}
+
+fn write_element<StrAsRef>(
+    tree: &TreeNode<SMLElement<StrAsRef>>,
+    indent: Option<usize>,
+    depth: usize,
+    buf: &mut String,
+) where
+    StrAsRef: AsRef<str>,
+{
+    buf.push('{');
+    push_entry_start(indent, depth + 1, buf);
+    push_key("name", indent, buf);
+    push_string(tree.value.name.as_ref(), buf);
+
+    buf.push(',');
+    push_entry_start(indent, depth + 1, buf);
+    push_key("attributes", indent, buf);
+    buf.push('[');
+    for (i, attribute) in tree.value.attributes.iter().enumerate() {
+        if i != 0 {
+            buf.push(',');
+        }
+        push_entry_start(indent, depth + 2, buf);
+        buf.push('{');
+        push_entry_start(indent, depth + 3, buf);
+        push_key("name", indent, buf);
+        push_string(attribute.name.as_ref(), buf);
+        buf.push(',');
+        push_entry_start(indent, depth + 3, buf);
+        push_key("values", indent, buf);
+        buf.push('[');
+        for (j, value) in attribute.values.iter().enumerate() {
+            if j != 0 {
+                buf.push_str(if indent.is_some() { ", " } else { "," });
+            }
+            match value {
+                None => buf.push_str("null"),
+                Some(value) => push_string(value.as_ref(), buf),
+            }
+        }
+        buf.push(']');
+        push_entry_start(indent, depth + 2, buf);
+        buf.push('}');
+    }
+    if !tree.value.attributes.is_empty() {
+        push_entry_start(indent, depth + 1, buf);
+    }
+    buf.push(']');
+
+    buf.push(',');
+    push_entry_start(indent, depth + 1, buf);
+    push_key("children", indent, buf);
+    buf.push('[');
+    for (i, child) in tree.children.iter().enumerate() {
+        if i != 0 {
+            buf.push(',');
+        }
+        push_entry_start(indent, depth + 2, buf);
+        write_element(child, indent, depth + 2, buf);
+    }
+    if !tree.children.is_empty() {
+        push_entry_start(indent, depth + 1, buf);
+    }
+    buf.push(']');
+
+    push_entry_start(indent, depth, buf);
+    buf.push('}');
+}
+
+fn push_key(key: &str, indent: Option<usize>, buf: &mut String) {
+    push_string(key, buf);
+    buf.push_str(if indent.is_some() { ": " } else { ":" });
+}
+
+/// Pushes the string as a quoted JSON string, escaping it as needed.
+fn push_string(value: &str, buf: &mut String) {
+    buf.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            '\u{0008}' => buf.push_str("\\b"),
+            '\u{000C}' => buf.push_str("\\f"),
+            ch if ch < ' ' => buf.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => buf.push(ch),
+        }
+    }
+    buf.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_json, to_json, to_json_pretty, JsonErrorType};
+    use crate::SMLWriter;
+
+    #[test]
+    fn to_json_matches_documented_shape() {
+        let tree = crate::parse("Root\n    Size 1 -\n    Child\n    End\nEnd").unwrap();
+        assert_eq!(
+            r#"{"name":"Root","attributes":[{"name":"Size","values":["1",null]}],"children":[{"name":"Child","attributes":[],"children":[]}]}"#,
+            to_json(&tree)
+        );
+    }
+
+    #[test]
+    fn to_json_round_trips_example() {
+        let input = include_str!("../example.txt");
+        let json = to_json(&crate::parse(input).unwrap());
+
+        let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let expected = SMLWriter::new(crate::parse(input).unwrap())
+            .to_string()
+            .unwrap();
+        let round_tripped = SMLWriter::new(from_json(&json).unwrap())
+            .to_string()
+            .unwrap();
+        assert_eq!(expected, round_tripped);
+
+        let tree = crate::parse(input).unwrap();
+        let pretty: serde_json::Value = serde_json::from_str(&to_json_pretty(&tree, 4)).unwrap();
+        assert_eq!(pretty, parsed);
+    }
+
+    #[test]
+    fn to_json_pretty_is_valid_indented_json() {
+        let tree = crate::parse(include_str!("../example.txt")).unwrap();
+        let json = to_json_pretty(&tree, 2);
+
+        let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!("Configuration", parsed["name"]);
+        assert_eq!(
+            serde_json::json!({ "name": "Resolution", "values": ["1280", "720"] }),
+            parsed["children"][0]["attributes"][0]
+        );
+        assert_eq!(
+            "Hero 123",
+            parsed["children"][2]["attributes"][0]["values"][0]
+        );
+
+        assert!(json.starts_with("{\n  \"name\": \"Configuration\",\n  \"attributes\": [],\n"));
+        assert!(json.contains(
+            "\n      \"attributes\": [\n        {\n          \"name\": \"Resolution\",\n          \"values\": [\"1280\", \"720\"]\n        },"
+        ));
+    }
+
+    #[test]
+    fn to_json_pretty_escapes_strings_and_nulls() {
+        let tree = crate::parse("Root\n    \"Quo\"\"te\" - \"a\"/\"b\" \"\\\t\"\nEnd").unwrap();
+        let json = to_json_pretty(&tree, 4);
+
+        let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            serde_json::json!({ "name": "Quo\"te", "values": [null, "a\nb", "\\\t"] }),
+            parsed["attributes"][0]
+        );
+    }
+
+    #[test]
+    fn from_json_rebuilds_tree() {
+        let input = include_str!("../example.txt");
+        let tree = from_json(&to_json_pretty(&crate::parse(input).unwrap(), 2)).unwrap();
+        assert_eq!(
+            SMLWriter::new(crate::parse(input).unwrap())
+                .to_string()
+                .unwrap(),
+            SMLWriter::new(tree).to_string().unwrap()
+        );
+
+        let json = r#" {"name": "R\u00f6ot", "extra": [1, true],
+            "attributes": [{"name": "Values", "values": ["a\"b", null, "\ud83d\ude00"]}]} "#;
+        let tree = from_json(json).unwrap();
+        assert_eq!("Röot", tree.value.name);
+        assert!(tree.children.is_empty());
+        assert_eq!(
+            vec![Some("a\"b".to_string()), None, Some("😀".to_string())],
+            tree.value.attributes[0].values
+        );
+    }
+
+    #[test]
+    fn from_json_rejects_invalid_shapes() {
+        let err = from_json(r#"{"attributes": []}"#).unwrap_err();
+        assert_eq!(JsonErrorType::MissingName, err.err_type());
+        assert_eq!(0, err.position());
+
+        let err = from_json(r#"{"name": "Root", "children": [{"attributes": []}]}"#).unwrap_err();
+        assert_eq!(JsonErrorType::MissingName, err.err_type());
+        assert_eq!(30, err.position());
+
+        let err = from_json(r#"{"name": "Root", "attributes": [{"name": "A", "values": [1]}]}"#)
+            .unwrap_err();
+        assert_eq!(JsonErrorType::UnexpectedType, err.err_type());
+
+        let err = from_json(r#"{"name": "Root",}"#).unwrap_err();
+        assert_eq!(JsonErrorType::Syntax, err.err_type());
+        let err = from_json(r#"{"name": "Root"} x"#).unwrap_err();
+        assert_eq!(JsonErrorType::Syntax, err.err_type());
+    }
+
+    #[test]
+    fn from_json_limits_nesting() {
+        let err = from_json(&"[".repeat(200_000)).unwrap_err();
+        assert_eq!(JsonErrorType::MaxDepthExceeded, err.err_type());
+        assert_eq!(super::MAX_JSON_DEPTH, err.position());
+
+        let mut tree = crate::SMLElementBuilder::new("Element");
+        for _ in 0..super::MAX_JSON_DEPTH / 2 - 1 {
+            tree = crate::SMLElementBuilder::new("Element").child(tree);
+        }
+        let json = to_json(&tree.build());
+        assert_eq!(json, to_json(&from_json(&json).unwrap()));
+    }
+
+    #[test]
+    fn from_json_rejects_invalid_numbers() {
+        let parse_with_number = |number: &str| {
+            from_json(&format!(
+                r#"{{"name": "Root", "extra": {}, "children": []}}"#,
+                number
+            ))
+            .map(|_| ())
+            .map_err(|err| err.err_type())
+        };
+        for number in ["0", "-0", "12", "1.5", "-1.5e10", "2E-3", "0.0e+1"] {
+            assert_eq!(Ok(()), parse_with_number(number), "{}", number);
+        }
+        for number in [
+            "01", "+1", "-", "1.", ".5", "1e", "1e+", "--1", "1.2.3", "0x1",
+        ] {
+            assert_eq!(
+                Err(JsonErrorType::Syntax),
+                parse_with_number(number),
+                "{}",
+                number
+            );
+        }
+    }
+}
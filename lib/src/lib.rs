@@ -1,11 +1,34 @@
 #![doc = include_str!("../README.md")]
 
-use std::{borrow::Cow, error::Error, fmt::Display};
-use tree_iterators_rs::prelude::{OwnedTreeNode, TreeNode};
-use whitespacesv::{ColumnAlignment, WSVError, WSVWriter};
+mod json;
+mod schema;
+#[cfg(any(test, feature = "testing"))]
+mod testing;
+mod xml;
+pub use json::*;
+pub use schema::*;
+#[cfg(feature = "testing")]
+pub use testing::*;
+pub use xml::*;
+
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    error::Error,
+    fmt::Display,
+    io::Read,
+    num::{ParseFloatError, ParseIntError},
+    ops::{ControlFlow, Range},
+    str::ParseBoolError,
+    sync::Arc,
+};
+use tree_iterators_rs::prelude::{BorrowedTreeNode, MutBorrowedTreeNode, TreeNode};
+use whitespacesv::{ColumnAlignment, WSVError, WSVLineIterator};
 
 /// Equivalent to [parse](https://docs.rs/simpleml/latest/simpleml/fn.parse.html),
-/// but returns Strings instead of Cows for better ease of use.
+/// but returns Strings instead of Cows for better ease of use. The tree
+/// doesn't borrow from source_text, so it can outlive the text (ex. to
+/// store a tree parsed from a temporary file buffer).
 pub fn parse_owned(source_text: &str) -> Result<TreeNode<SMLElement<String>>, ParseError> {
     let borrowed = parse(source_text)?;
     Ok(to_owned(borrowed))
@@ -27,924 +50,7774 @@ fn to_owned(tree: TreeNode<SMLElement<Cow<'_, str>>>) -> TreeNode<SMLElement<Str
 /// For details about how to use TreeNode, see [tree_iterators_rs](https://crates.io/crates/tree_iterators_rs)
 /// and the documentation related to that crate.
 pub fn parse(source_text: &str) -> Result<TreeNode<SMLElement<Cow<'_, str>>>, ParseError> {
+    parse_with_options(source_text, &ParseOptions::default())
+}
+
+/// Equivalent to [parse](https://docs.rs/simpleml/latest/simpleml/fn.parse.html),
+/// but allows configuring the parser's behavior through [ParseOptions].
+pub fn parse_with_options<'a>(
+    source_text: &'a str,
+    options: &ParseOptions,
+) -> Result<TreeNode<SMLElement<Cow<'a, str>>>, ParseError> {
+    let locator = ErrorLocator {
+        source_text,
+        tab_width: options.tab_width,
+    };
+
     let wsv_result = whitespacesv::parse(source_text);
-    let wsv = match wsv_result {
+    let mut wsv = match wsv_result {
         Err(err) => return Err(ParseError::WSV(err)),
         Ok(wsv) => wsv,
     };
 
-    let end_keyword = match wsv.iter().rev().find(|line| !line.is_empty()) {
-        None => {
-            return Err(ParseError::SML(SMLError {
-                err_type: SMLErrorType::EndKeywordNotDetected,
-                line_num: wsv.len(),
-            }))
-        }
-        Some(last_line) => match last_line.get(0).unwrap() {
-            None => None,
-            Some(val) => Some(val.to_lowercase()),
-        },
+    if let Some(continuation_char) = options.continuation_char {
+        join_continued_lines(&mut wsv, source_text, continuation_char);
+    }
+
+    build_tree(wsv, &locator, options)
+}
+
+/// Equivalent to [parse](https://docs.rs/simpleml/latest/simpleml/fn.parse.html),
+/// but fails with [SMLErrorType::MaxDepthExceeded] or
+/// [SMLErrorType::MaxElementsExceeded] as soon as the document goes past
+/// one of the limits. This keeps untrusted documents from exhausting
+/// memory with deeply nested or very many elements.
+pub fn parse_with_limits(
+    source_text: &str,
+    limits: ParseLimits,
+) -> Result<TreeNode<SMLElement<Cow<'_, str>>>, ParseError> {
+    parse_with_options(source_text, &ParseOptions::default().limits(limits))
+}
+
+/// Equivalent to [parse](https://docs.rs/simpleml/latest/simpleml/fn.parse.html),
+/// but continues past recoverable errors and returns every error found,
+/// in order, instead of stopping at the first one. This is intended for
+/// tools that report diagnostics, like linters and editors.
+///
+/// Null element names, null attribute names, and elements or attributes
+/// outside of the root element are recoverable. An element with a null
+/// name is kept with an empty name so that the lines after it still line
+/// up, and the other lines are skipped. Any other error stops parsing,
+/// is added to the end of the errors, and no tree is returned. Otherwise,
+/// the tree is returned even if errors were recovered from.
+#[allow(clippy::type_complexity)]
+pub fn parse_collect_errors(
+    source_text: &str,
+) -> (Option<TreeNode<SMLElement<Cow<'_, str>>>>, Vec<ParseError>) {
+    let locator = ErrorLocator {
+        source_text,
+        tab_width: ParseOptions::default().tab_width,
     };
 
-    let mut lines_iter = wsv.into_iter().enumerate();
-    let root_element_name;
-    loop {
-        let first_line = lines_iter.next();
-        match first_line {
-            None => panic!("Found an empty file, but this should've returned an SMLError::EndKeywordNotDetected"), 
-            Some((line_num, mut first_line)) => {
-                if first_line.is_empty() { continue; }
-                if first_line.len() > 1 { return Err(ParseError::SML(SMLError {
-                    err_type: SMLErrorType::InvalidRootElementStart,
-                    line_num,
-                })) }
-                match std::mem::take(first_line.get_mut(0).unwrap()) {
-                    None => return Err(ParseError::SML(SMLError {
-                        err_type: SMLErrorType::NullValueAsElementName,
-                        line_num,
-                    })),
-                    Some(root) => {
-                        root_element_name = root;
-                        break;
-                    }
+    let wsv = match whitespacesv::parse(source_text) {
+        Err(err) => return (None, vec![ParseError::WSV(err)]),
+        Ok(wsv) => wsv,
+    };
+
+    let mut errors = Vec::new();
+    match build_tree_recovering(wsv, &locator, &ParseOptions::default(), Some(&mut errors)) {
+        Ok(tree) => (Some(tree), errors),
+        Err(err) => {
+            errors.push(err);
+            (None, errors)
+        }
+    }
+}
+
+/// Appends the values of every line that follows a line ending with the
+/// continuation character (outside of quotes) onto that line. Only
+/// attribute lines are continued, so the character is left alone on lines
+/// that would otherwise be an element or end keyword. The joined lines
+/// are left empty so that line numbers in errors still match the source
+/// text.
+fn join_continued_lines(
+    wsv: &mut [Vec<Option<Cow<'_, str>>>],
+    source_text: &str,
+    continuation_char: char,
+) {
+    let mut continuing = None;
+    for (line_num, raw_line) in source_text.split('\n').enumerate() {
+        if line_num >= wsv.len() {
+            break;
+        }
+
+        let line_values = std::mem::take(&mut wsv[line_num]);
+        let target = match continuing {
+            None => line_num,
+            Some(target) => target,
+        };
+        wsv[target].extend(line_values);
+
+        let ends_with_continuation = scan_line(raw_line)
+            .values
+            .last()
+            .map(|range| &raw_line[range.clone()])
+            .is_some_and(|raw_value| {
+                !raw_value.starts_with('"') && raw_value.ends_with(continuation_char)
+            });
+        let target_line = &mut wsv[target];
+        // An attribute needs a name and at least one value besides the marker.
+        let marker_is_value = target_line.last().is_some_and(|value| {
+            value.as_deref().is_some_and(|value| {
+                value.len() == continuation_char.len_utf8() && value.starts_with(continuation_char)
+            })
+        });
+        let is_attribute = target_line.len() - usize::from(marker_is_value) >= 2;
+        if !ends_with_continuation || !is_attribute {
+            continuing = None;
+            continue;
+        }
+
+        if let Some(Some(value)) = target_line.last_mut() {
+            match value {
+                Cow::Borrowed(str) => *str = &str[..str.len() - continuation_char.len_utf8()],
+                Cow::Owned(string) => {
+                    string.pop();
                 }
             }
+            if value.is_empty() {
+                target_line.pop();
+            }
         }
+        continuing = Some(target);
     }
+}
 
-    let root = TreeNode {
-        value: SMLElement {
-            name: root_element_name,
-            attributes: Vec::with_capacity(0),
-        },
-        children: Vec::new(),
+/// Equivalent to [parse](https://docs.rs/simpleml/latest/simpleml/fn.parse.html),
+/// but takes the source text as an iterator of lines so that line-based
+/// callers don't need to join them into a single String first. The lines
+/// should not include their line breaks. Since each line is parsed on its
+/// own, a quoted value can't span the caller's line boundaries. Errors
+/// are reported exactly as if the lines had been joined with '\n'.
+pub fn parse_lines<'a>(
+    lines: impl Iterator<Item = &'a str>,
+) -> Result<TreeNode<SMLElement<Cow<'a, str>>>, ParseError> {
+    let lines = lines.collect::<Vec<_>>();
+    // Locating an error needs the full text, so only join it on failure.
+    let joined_error = |err| match parse(&lines.join("\n")) {
+        Err(joined_err) => joined_err,
+        Ok(_) => err,
     };
-    let mut nodes_being_built = vec![root];
-    let mut result = None;
 
-    for (line_num, mut line) in lines_iter {
-        if line.is_empty() {
-            continue;
+    let mut wsv = Vec::with_capacity(lines.len());
+    for line in lines.iter() {
+        match whitespacesv::parse(line) {
+            Err(err) => return Err(joined_error(ParseError::WSV(err))),
+            Ok(parsed) if parsed.is_empty() => wsv.push(Vec::new()),
+            Ok(parsed) => wsv.extend(parsed),
         }
-        if line.len() == 1 {
-            let val;
-            let val_lowercase;
-            match line.get_mut(0) {
-                None => {
-                    if end_keyword.is_some() {
-                        return Err(ParseError::SML(SMLError {
-                            err_type: SMLErrorType::NullValueAsElementName,
-                            line_num,
-                        }));
-                    }
-                    val = None;
-                    val_lowercase = None;
-                }
-                Some(inner_val) => match inner_val {
-                    None => {
-                        val = None;
-                        val_lowercase = None;
-                    }
-                    Some(innermost_val) => {
-                        val_lowercase = Some(innermost_val.to_lowercase());
-                        val = Some(std::mem::take(innermost_val));
-                    }
-                },
-            };
+    }
 
-            if val_lowercase == end_keyword {
-                match nodes_being_built.pop() {
-                    None => {
-                        return Err(ParseError::SML(SMLError {
-                            err_type: SMLErrorType::OnlyOneRootElementAllowed,
-                            line_num,
-                        }))
-                    }
-                    Some(top) => {
-                        let nodes_being_built_len = nodes_being_built.len();
-                        if nodes_being_built_len == 0 {
-                            if result.is_some() {
-                                return Err(ParseError::SML(SMLError {
-                                    err_type: SMLErrorType::OnlyOneRootElementAllowed,
-                                    line_num,
-                                }));
-                            } else {
-                                result = Some(top);
-                                continue;
-                            }
-                        }
+    let locator = ErrorLocator {
+        source_text: "",
+        tab_width: ParseOptions::default().tab_width,
+    };
+    build_tree(wsv, &locator, &ParseOptions::default()).map_err(joined_error)
+}
 
-                        let new_top = nodes_being_built
-                            .get_mut(nodes_being_built_len - 1)
-                            .unwrap();
+/// Equivalent to [parse_owned](https://docs.rs/simpleml/latest/simpleml/fn.parse_owned.html),
+/// but reads the source text from the reader. The end keyword is only
+/// known once the last line has been read, and errors need the text of
+/// their line to report a column and byte offset, so the whole text is
+/// read before it's parsed. It's dropped as soon as the tree is built.
+/// Failures to read (including invalid UTF-8) are returned as
+/// [ParseError::IO].
+pub fn parse_reader<R: Read>(mut reader: R) -> Result<TreeNode<SMLElement<String>>, ParseError> {
+    let mut source_text = String::new();
+    reader
+        .read_to_string(&mut source_text)
+        .map_err(|err| ParseError::IO(Arc::new(err)))?;
+    parse_owned(&source_text)
+}
 
-                        new_top.children.push(top);
-                    }
-                }
-            } else {
-                nodes_being_built.push(TreeNode {
-                    value: SMLElement {
-                        name: val.expect("BUG: Null element names are prohibited."),
-                        attributes: Vec::with_capacity(0),
-                    },
-                    children: Vec::new(),
-                });
-            }
-        } else {
-            let mut values = line.into_iter();
-            let name = match values.next().unwrap() {
-                None => {
-                    return Err(ParseError::SML(SMLError {
-                        err_type: SMLErrorType::NullValueAsAttributeName,
+/// Equivalent to [parse_with_options](https://docs.rs/simpleml/latest/simpleml/fn.parse_with_options.html),
+/// but also returns any warnings enabled in the options. Warnings never
+/// change the parsed tree.
+#[allow(clippy::type_complexity)]
+pub fn parse_with_warnings<'a>(
+    source_text: &'a str,
+    options: &ParseOptions,
+) -> Result<(TreeNode<SMLElement<Cow<'a, str>>>, Vec<ParseWarning>), ParseError> {
+    let tree = parse_with_options(source_text, options)?;
+    let mut warnings = Vec::new();
+
+    if options.warn_on_keyword_ambiguity {
+        // parse already succeeded, so this can't fail.
+        let wsv = whitespacesv::parse(source_text).map_err(ParseError::WSV)?;
+        let end_keyword = detect_end_keyword_raw(&wsv)
+            .flatten()
+            .map(|val| val.to_lowercase());
+
+        // A null end keyword can't be confused with a value, since every
+        // null value is written the same way.
+        if let Some(end_keyword) = end_keyword {
+            for (line_num, line) in wsv.iter().enumerate() {
+                if line.len() > 1
+                    && line
+                        .iter()
+                        .flatten()
+                        .any(|val| is_end_keyword(Some(val), Some(&end_keyword)))
+                {
+                    warnings.push(ParseWarning {
+                        warning_type: ParseWarningType::AmbiguousEndKeyword,
                         line_num,
-                    }))
+                    });
                 }
-                Some(val) => val,
-            };
-
-            let attr_values = values.collect::<Vec<_>>();
-            let nodes_being_built_len = nodes_being_built.len();
-            if nodes_being_built_len == 0 {
-                return Err(ParseError::SML(SMLError {
-                    err_type: SMLErrorType::OnlyOneRootElementAllowed,
-                    line_num,
-                }));
             }
+        }
+    }
 
-            let current = nodes_being_built
-                .get_mut(nodes_being_built_len - 1)
-                .unwrap();
-            current.value.attributes.push(SMLAttribute {
-                name,
-                values: attr_values,
-            });
+    Ok((tree, warnings))
+}
+
+/// Builds the tree of SMLElements out of already parsed WSV lines.
+fn build_tree<'a>(
+    wsv: Vec<Vec<Option<Cow<'a, str>>>>,
+    locator: &ErrorLocator,
+    options: &ParseOptions,
+) -> Result<TreeNode<SMLElement<Cow<'a, str>>>, ParseError> {
+    build_tree_recovering(wsv, locator, options, None)
+}
+
+/// Records a recoverable error if errors are being collected, or returns
+/// it otherwise.
+fn recover(errors: &mut Option<&mut Vec<ParseError>>, err: ParseError) -> Result<(), ParseError> {
+    match errors {
+        None => Err(err),
+        Some(errors) => {
+            errors.push(err);
+            Ok(())
         }
     }
+}
+
+/// Equivalent to [build_tree], but if errors is Some, recoverable errors
+/// are pushed onto it and building continues. Null element names are
+/// replaced with empty names, and lines with null attribute names or
+/// outside of the root element are skipped.
+fn build_tree_recovering<'a>(
+    wsv: Vec<Vec<Option<Cow<'a, str>>>>,
+    locator: &ErrorLocator,
+    options: &ParseOptions,
+    errors: Option<&mut Vec<ParseError>>,
+) -> Result<TreeNode<SMLElement<Cow<'a, str>>>, ParseError> {
+    let end_keyword = match detect_end_keyword_raw(&wsv) {
+        None => return Err(locator.error(SMLErrorType::EndKeywordNotDetected, wsv.len(), 0)),
+        Some(end_keyword) => end_keyword.map(|val| val.to_lowercase()),
+    };
 
-    match result {
-        None => Err(ParseError::SML(SMLError {
-            err_type: SMLErrorType::RootNotClosed,
-            line_num: 0,
-        })),
-        Some(result) => Ok(result),
+    let mut builder = TreeBuilder::new();
+    // TreeBuilder never stops reading early.
+    let _ = read_document(
+        wsv.into_iter(),
+        end_keyword.as_deref(),
+        locator,
+        options,
+        errors,
+        &mut builder,
+    )?;
+    match builder.result {
+        Some(tree) => Ok(tree),
+        // read_document only succeeds once the root is closed.
+        None => Err(locator.error(SMLErrorType::RootNotClosed, 0, 0)),
     }
 }
 
-pub struct SMLWriter<StrAsRef>
+/// Receives the structure of a document from [read_document], one line
+/// at a time. Returning ControlFlow::Break stops reading.
+trait DocumentSink<'a> {
+    fn element_start(&mut self, name: Cow<'a, str>) -> ControlFlow<()>;
+    fn attribute(&mut self, attribute: SMLAttribute<Cow<'a, str>>) -> ControlFlow<()>;
+    fn element_end(&mut self) -> ControlFlow<()>;
+}
+
+/// Builds the tree of a document. Only the first root element to be
+/// closed is kept.
+struct TreeBuilder<StrAsRef>
 where
-    StrAsRef: AsRef<str> + From<&'static str> + ToString,
+    StrAsRef: AsRef<str>,
 {
-    indent_str: String,
-    end_keyword: Option<String>,
-    column_alignment: ColumnAlignment,
-    values: TreeNode<SMLElement<StrAsRef>>,
+    nodes_being_built: Vec<TreeNode<SMLElement<StrAsRef>>>,
+    result: Option<TreeNode<SMLElement<StrAsRef>>>,
 }
 
-impl<StrAsRef> SMLWriter<StrAsRef>
+impl<StrAsRef> TreeBuilder<StrAsRef>
 where
-    StrAsRef: AsRef<str> + From<&'static str> + ToString,
+    StrAsRef: AsRef<str>,
 {
-    pub fn new(values: TreeNode<SMLElement<StrAsRef>>) -> Self {
+    fn new() -> Self {
         Self {
-            values,
-            indent_str: "    ".to_string(), // default to 4 spaces
-            end_keyword: None,              // Use minified as the default
-            column_alignment: ColumnAlignment::default(),
+            nodes_being_built: Vec::new(),
+            result: None,
         }
     }
 
-    /// Sets the indentation string to be used in the output.
-    /// If the passed in str contains any non-whitespace characters,
-    /// this call will fail and return None.
-    pub fn indent_with(mut self, str: &str) -> Option<Self> {
-        if str.chars().any(|ch| !Self::is_whitespace(ch)) {
-            return None;
-        }
-        self.indent_str = str.to_string();
-        return Some(self);
+    fn element_start(&mut self, name: StrAsRef) {
+        self.nodes_being_built.push(TreeNode {
+            value: SMLElement {
+                name,
+                attributes: Vec::with_capacity(0),
+            },
+            children: Vec::new(),
+        });
     }
 
-    /// Sets the end keyword to be used in the output.
-    /// If the passed in string is the empty string "",
-    /// '-' will be used instead.
-    pub fn with_end_keyword(mut self, str: Option<&str>) -> Self {
-        match str {
-            None | Some("") => {
-                self.end_keyword = None;
-                return self;
-            }
-            Some(str) => {
-                debug_assert!(!str.is_empty());
-                let needs_quotes = str
-                    .chars()
-                    .any(|ch| ch == '"' || ch == '#' || ch == '\n' || Self::is_whitespace(ch))
-                    || str == "-";
+    fn attribute(&mut self, attribute: SMLAttribute<StrAsRef>) {
+        if let Some(current) = self.nodes_being_built.last_mut() {
+            current.value.attributes.push(attribute);
+        }
+    }
 
-                if !needs_quotes {
-                    self.end_keyword = Some(str.to_string());
-                } else {
-                    let mut result = String::new();
-                    result.push('"');
-                    for ch in str.chars() {
-                        match ch {
-                            '"' => result.push_str("\"\""),
-                            '\n' => result.push_str("\"/\""),
-                            ch => result.push(ch),
-                        }
+    fn element_end(&mut self) {
+        if let Some(top) = self.nodes_being_built.pop() {
+            match self.nodes_being_built.last_mut() {
+                Some(new_top) => new_top.children.push(top),
+                None => {
+                    if self.result.is_none() {
+                        self.result = Some(top);
                     }
-                    result.push('"');
-                    self.end_keyword = Some(result);
                 }
-                return self;
             }
         }
     }
+}
 
-    /// Sets the column alignment of the attributes' generated WSV.
-    /// The element alignment will be unaffected, but all attributes
-    /// and their values will be aligned this way.
-    pub fn align_columns(mut self, alignment: ColumnAlignment) -> Self {
-        self.column_alignment = alignment;
-        return self;
+impl<'a> DocumentSink<'a> for TreeBuilder<Cow<'a, str>> {
+    fn element_start(&mut self, name: Cow<'a, str>) -> ControlFlow<()> {
+        TreeBuilder::element_start(self, name);
+        ControlFlow::Continue(())
     }
 
-    /// Writes the values in this SMLWriter out to a String. This operation
-    /// can fail if any of the values would result in an SML attribute or
-    /// element where the name is the same as the "End" keyword. If that
-    /// happens, you as the caller will receive an Err() variant of Result.
-    pub fn to_string(self) -> Result<String, SMLWriterError> {
-        let mut result = String::new();
-        Self::to_string_helper(
-            self.values,
-            0,
-            &self.column_alignment,
-            &self.indent_str,
-            self.end_keyword.as_ref(),
-            &mut result,
-        )?;
-        return Ok(result);
+    fn attribute(&mut self, attribute: SMLAttribute<Cow<'a, str>>) -> ControlFlow<()> {
+        TreeBuilder::attribute(self, attribute);
+        ControlFlow::Continue(())
     }
 
-    fn to_string_helper(
-        value: TreeNode<SMLElement<StrAsRef>>,
-        depth: usize,
-        alignment: &ColumnAlignment,
-        indent_str: &str,
-        end_keyword: Option<&String>,
-        buf: &mut String,
-    ) -> Result<(), SMLWriterError> {
-        let (value, children) = value.get_value_and_children();
-        if let Some(end_keyword) = end_keyword {
-            if value.name.as_ref() == end_keyword {
-                return Err(SMLWriterError::ElementHasEndKeywordName);
-            }
-        }
+    fn element_end(&mut self) -> ControlFlow<()> {
+        TreeBuilder::element_end(self);
+        ControlFlow::Continue(())
+    }
+}
 
-        for _ in 0..depth {
-            buf.push_str(indent_str);
-        }
-        buf.push_str(value.name.as_ref());
+/// Builds the tree of [parse_borrowed], stopping at the first value that
+/// had to be unescaped and so can't borrow from the source text.
+struct BorrowedTreeBuilder<'a> {
+    builder: TreeBuilder<&'a str>,
+    /// The index on its line of the value that needs unescaping.
+    needs_unescaping: Option<usize>,
+}
 
-        if !value.attributes.is_empty() {
-            buf.push('\n');
-            for _ in 0..depth + 1 {
-                buf.push_str(indent_str);
+impl<'a> DocumentSink<'a> for BorrowedTreeBuilder<'a> {
+    fn element_start(&mut self, name: Cow<'a, str>) -> ControlFlow<()> {
+        match name {
+            Cow::Borrowed(name) => {
+                self.builder.element_start(name);
+                ControlFlow::Continue(())
             }
-        }
-
-        if let Some(end_keyword) = end_keyword {
-            for attribute in value.attributes.iter() {
-                if attribute.name.as_ref() == end_keyword {
-                    return Err(SMLWriterError::AttributeHasEndKeywordName);
-                }
+            Cow::Owned(_) => {
+                self.needs_unescaping = Some(0);
+                ControlFlow::Break(())
             }
         }
+    }
 
-        let values_for_writer = value
-            .attributes
-            .into_iter()
-            .map(|attr| std::iter::once(Some(attr.name)).chain(attr.values.into_iter()));
-
-        match alignment {
-            ColumnAlignment::Packed => {
-                for ch in WSVWriter::new(values_for_writer) {
-                    buf.push(ch);
-                    if ch == '\n' {
-                        for _ in 0..depth + 1 {
-                            buf.push_str(indent_str);
-                        }
-                    }
-                }
+    fn attribute(&mut self, attribute: SMLAttribute<Cow<'a, str>>) -> ControlFlow<()> {
+        let name = match attribute.name {
+            Cow::Borrowed(name) => name,
+            Cow::Owned(_) => {
+                self.needs_unescaping = Some(0);
+                return ControlFlow::Break(());
             }
-            ColumnAlignment::Left | ColumnAlignment::Right => {
-                for ch in WSVWriter::new(values_for_writer)
-                    .align_columns(match alignment {
-                        ColumnAlignment::Left => ColumnAlignment::Left,
-                        ColumnAlignment::Right => ColumnAlignment::Right,
-                        ColumnAlignment::Packed => {
-                            panic!("BUG: ColumnAlignment::Packed shouldn't go down this path")
-                        }
-                    })
-                    .to_string()
-                    .chars()
-                {
-                    buf.push(ch);
-                    if ch == '\n' {
-                        for _ in 0..depth + 1 {
-                            buf.push_str(indent_str);
-                        }
-                    }
+        };
+        let mut values = Vec::with_capacity(attribute.values.len());
+        for (i, value) in attribute.values.into_iter().enumerate() {
+            match value {
+                None => values.push(None),
+                Some(Cow::Borrowed(value)) => values.push(Some(value)),
+                Some(Cow::Owned(_)) => {
+                    // The attribute's name is the first value on its line.
+                    self.needs_unescaping = Some(i + 1);
+                    return ControlFlow::Break(());
                 }
             }
         }
+        self.builder.attribute(SMLAttribute { name, values });
+        ControlFlow::Continue(())
+    }
 
-        for child in children.into_iter() {
-            buf.push('\n');
-            Self::to_string_helper(child, depth + 1, alignment, indent_str, end_keyword, buf)?;
-        }
-        buf.push('\n');
-        for _ in 0..depth {
-            buf.push_str(indent_str);
-        }
-        match end_keyword {
-            None => buf.push('-'),
-            Some(end) => buf.push_str(end),
-        }
+    fn element_end(&mut self) -> ControlFlow<()> {
+        self.builder.element_end();
+        ControlFlow::Continue(())
+    }
+}
+
+/// Passes each element and attribute to the handler of [parse_events].
+struct EventSink<Handler>(Handler);
+
+impl<'a, Handler> DocumentSink<'a> for EventSink<Handler>
+where
+    Handler: FnMut(SmlEvent<'a>) -> ControlFlow<()>,
+{
+    fn element_start(&mut self, name: Cow<'a, str>) -> ControlFlow<()> {
+        (self.0)(SmlEvent::ElementStart(name))
+    }
 
-        return Ok(());
+    fn attribute(&mut self, attribute: SMLAttribute<Cow<'a, str>>) -> ControlFlow<()> {
+        (self.0)(SmlEvent::Attribute(attribute.name, attribute.values))
     }
 
-    const fn is_whitespace(ch: char) -> bool {
-        match ch {
-            '\u{0009}' | '\u{000B}' | '\u{000C}' | '\u{000D}' | '\u{0020}' | '\u{0085}'
-            | '\u{00A0}' | '\u{1680}' | '\u{2000}' | '\u{2001}' | '\u{2002}' | '\u{2003}'
-            | '\u{2004}' | '\u{2005}' | '\u{2006}' | '\u{2007}' | '\u{2008}' | '\u{2009}'
-            | '\u{200A}' | '\u{2028}' | '\u{2029}' | '\u{202F}' | '\u{205F}' | '\u{3000}' => {
-                return true;
-            }
-            _ => return false,
-        }
+    fn element_end(&mut self) -> ControlFlow<()> {
+        (self.0)(SmlEvent::ElementEnd)
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum SMLWriterError {
-    ElementHasEndKeywordName,
-    AttributeHasEndKeywordName,
+/// What a line of the source text defined, as recorded by
+/// [LineTargetSink].
+enum LineTarget {
+    /// The line had no values, so it was empty or only a comment.
+    Empty,
+    /// The line opened an element or defined an attribute.
+    Start(CommentTarget),
+    /// The line closed the element with the given preorder index.
+    End(usize),
 }
 
-impl Error for SMLWriterError {}
-impl Display for SMLWriterError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            SMLWriterError::AttributeHasEndKeywordName => {
-                write!(f, "Attribute Has End Keyword Name")?
-            }
-            SMLWriterError::ElementHasEndKeywordName => write!(f, "Element Has End Keyword Name")?,
+/// Builds the tree of a document like [TreeBuilder], while recording what
+/// the last line read defined. Lines are addressed the same way as
+/// comments, so the caller can attach its own data to each one.
+struct LineTargetSink<'a> {
+    builder: TreeBuilder<Cow<'a, str>>,
+    next_element: usize,
+    /// (element index, number of attributes seen so far)
+    open_elements: Vec<(usize, usize)>,
+    target: LineTarget,
+}
+
+impl<'a> DocumentSink<'a> for LineTargetSink<'a> {
+    fn element_start(&mut self, name: Cow<'a, str>) -> ControlFlow<()> {
+        self.target = LineTarget::Start(CommentTarget::Element(self.next_element));
+        self.open_elements.push((self.next_element, 0));
+        self.next_element += 1;
+        DocumentSink::element_start(&mut self.builder, name)
+    }
+
+    fn attribute(&mut self, attribute: SMLAttribute<Cow<'a, str>>) -> ControlFlow<()> {
+        if let Some((element, attributes_seen)) = self.open_elements.last_mut() {
+            self.target = LineTarget::Start(CommentTarget::Attribute {
+                element: *element,
+                attribute: *attributes_seen,
+            });
+            *attributes_seen += 1;
         }
-        Ok(())
+        DocumentSink::attribute(&mut self.builder, attribute)
     }
-}
 
-#[derive(Debug, Clone)]
-pub enum ParseError {
-    WSV(WSVError),
-    SML(SMLError),
+    fn element_end(&mut self) -> ControlFlow<()> {
+        if let Some((element, _)) = self.open_elements.pop() {
+            self.target = LineTarget::End(element);
+        }
+        DocumentSink::element_end(&mut self.builder)
+    }
 }
 
-impl Error for ParseError {}
-impl Display for ParseError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ParseError::SML(err) => err.fmt(f)?,
-            ParseError::WSV(err) => err.fmt(f)?,
+/// Reads the lines of a document, checking its structure and passing
+/// each element and attribute to the sink. end_keyword must already be
+/// lowercased. If errors is Some, recoverable errors are pushed onto it
+/// as described in [build_tree_recovering] and reading continues.
+fn read_document<'a>(
+    lines: impl Iterator<Item = Vec<Option<Cow<'a, str>>>>,
+    end_keyword: Option<&str>,
+    locator: &ErrorLocator,
+    options: &ParseOptions,
+    mut errors: Option<&mut Vec<ParseError>>,
+    sink: &mut impl DocumentSink<'a>,
+) -> Result<ControlFlow<()>, ParseError> {
+    let mut reader = DocumentReader::new(end_keyword);
+    for (line_num, line) in lines.enumerate() {
+        if reader
+            .read_line(line_num, line, locator, options, &mut errors, sink)?
+            .is_break()
+        {
+            return Ok(ControlFlow::Break(()));
         }
-        Ok(())
     }
+    reader.finish(locator)?;
+    Ok(ControlFlow::Continue(()))
 }
 
-#[derive(Debug, Clone)]
-pub struct SMLError {
-    err_type: SMLErrorType,
-    line_num: usize,
+/// The state of [read_document] between lines, so that lines can also be
+/// passed in one at a time.
+struct DocumentReader {
+    end_keyword: Option<String>,
+    root_started: bool,
+    /// Where the document ends, for reporting an unclosed root.
+    last_line_num: usize,
+    elements_seen: usize,
+    /// The number of attributes of each element that hasn't been closed.
+    open_elements: Vec<usize>,
+    root_closed: bool,
 }
 
-impl SMLError {
-    pub fn err_type(&self) -> SMLErrorType {
-        self.err_type
-    }
-    pub fn line_num(&self) -> usize {
-        self.line_num
+impl DocumentReader {
+    fn new(end_keyword: Option<&str>) -> Self {
+        Self {
+            end_keyword: end_keyword.map(str::to_string),
+            root_started: false,
+            last_line_num: 0,
+            elements_seen: 0,
+            open_elements: Vec::new(),
+            root_closed: false,
+        }
     }
-}
 
-impl Error for SMLError {}
-impl Display for SMLError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut result = String::new();
-        result.push_str("(line: ");
-        result.push_str(&self.line_num().to_string());
-        result.push_str(") ");
-        match self.err_type() {
-            SMLErrorType::EndKeywordNotDetected => {
-                result.push_str("End Keyword Not Detected");
-            }
-            SMLErrorType::InvalidRootElementStart => {
-                result.push_str("Invalid Root Element Start");
-            }
-            SMLErrorType::NullValueAsAttributeName => {
-                result.push_str("Null Value as Attribute Name");
-            }
-            SMLErrorType::NullValueAsElementName => {
-                result.push_str("Null Value as Element Name");
+    fn read_line<'a>(
+        &mut self,
+        line_num: usize,
+        mut line: Vec<Option<Cow<'a, str>>>,
+        locator: &ErrorLocator,
+        options: &ParseOptions,
+        errors: &mut Option<&mut Vec<ParseError>>,
+        sink: &mut impl DocumentSink<'a>,
+    ) -> Result<ControlFlow<()>, ParseError> {
+        if line.is_empty() {
+            return Ok(ControlFlow::Continue(()));
+        }
+        if !self.root_started {
+            return self.read_root(line_num, line, locator, options, errors, sink);
+        }
+
+        self.last_line_num = line_num;
+        if line.len() == 1 {
+            let val;
+            let is_end;
+            match std::mem::take(&mut line[0]) {
+                None => {
+                    is_end = self.end_keyword.is_none();
+                    if !is_end {
+                        recover(
+                            errors,
+                            locator.error(SMLErrorType::NullValueAsElementName, line_num, 0),
+                        )?;
+                    }
+                    val = None;
+                }
+                Some(innermost_val) => {
+                    is_end = is_end_keyword(Some(&innermost_val), self.end_keyword.as_deref());
+                    val = Some(innermost_val);
+                }
+            };
+
+            if is_end {
+                if self.open_elements.pop().is_none() {
+                    recover(
+                        errors,
+                        locator.error(SMLErrorType::OnlyOneRootElementAllowed, line_num, 0),
+                    )?;
+                    return Ok(ControlFlow::Continue(()));
+                }
+                if self.open_elements.is_empty() {
+                    if self.root_closed {
+                        recover(
+                            errors,
+                            locator.error(SMLErrorType::OnlyOneRootElementAllowed, line_num, 0),
+                        )?;
+                    }
+                    self.root_closed = true;
+                }
+                Ok(sink.element_end())
+            } else {
+                // The root has a depth of 0, so this is the new element's depth.
+                if options
+                    .limits
+                    .max_depth
+                    .is_some_and(|max| self.open_elements.len() > max)
+                {
+                    return Err(locator.error(SMLErrorType::MaxDepthExceeded, line_num, 0));
+                }
+                self.elements_seen += 1;
+                if options
+                    .limits
+                    .max_elements
+                    .is_some_and(|max| self.elements_seen > max)
+                {
+                    return Err(locator.error(SMLErrorType::MaxElementsExceeded, line_num, 0));
+                }
+                self.open_elements.push(0);
+                Ok(sink.element_start(val.unwrap_or(Cow::Borrowed(""))))
             }
-            SMLErrorType::OnlyOneRootElementAllowed => {
-                result.push_str("Only One Root Element Allowed");
+        } else {
+            let mut values = line.into_iter();
+            // The line isn't empty, so None is a null value.
+            let name = match values.next().flatten() {
+                None => {
+                    recover(
+                        errors,
+                        locator.error(SMLErrorType::NullValueAsAttributeName, line_num, 0),
+                    )?;
+                    return Ok(ControlFlow::Continue(()));
+                }
+                Some(val) => val,
+            };
+
+            let attributes_seen = match self.open_elements.last_mut() {
+                None => {
+                    recover(
+                        errors,
+                        locator.error(SMLErrorType::OnlyOneRootElementAllowed, line_num, 0),
+                    )?;
+                    return Ok(ControlFlow::Continue(()));
+                }
+                Some(attributes_seen) => attributes_seen,
+            };
+            if let Some(max) = options.max_attributes_per_element {
+                if *attributes_seen >= max {
+                    return Err(locator.error(SMLErrorType::TooManyAttributes, line_num, 0));
+                }
             }
-            SMLErrorType::RootNotClosed => {
-                result.push_str("Root Not Closed");
+            *attributes_seen += 1;
+            Ok(sink.attribute(SMLAttribute {
+                name,
+                values: values.collect(),
+            }))
+        }
+    }
+
+    /// Reads the first non-empty line, which must open the root element.
+    fn read_root<'a>(
+        &mut self,
+        line_num: usize,
+        mut line: Vec<Option<Cow<'a, str>>>,
+        locator: &ErrorLocator,
+        options: &ParseOptions,
+        errors: &mut Option<&mut Vec<ParseError>>,
+        sink: &mut impl DocumentSink<'a>,
+    ) -> Result<ControlFlow<()>, ParseError> {
+        if line.len() > 1 {
+            return Err(locator.error(SMLErrorType::InvalidRootElementStart, line_num, 1));
+        }
+        // The line has exactly one value, so None is a null value.
+        let root_element_name = match line.pop().flatten() {
+            None => {
+                recover(
+                    errors,
+                    locator.error(SMLErrorType::NullValueAsElementName, line_num, 0),
+                )?;
+                Cow::Borrowed("")
             }
+            Some(root) => root,
+        };
+
+        self.root_started = true;
+        self.last_line_num = line_num;
+        self.elements_seen = 1;
+        if options
+            .limits
+            .max_elements
+            .is_some_and(|max| self.elements_seen > max)
+        {
+            return Err(locator.error(SMLErrorType::MaxElementsExceeded, line_num, 0));
+        }
+        self.open_elements.push(0);
+        Ok(sink.element_start(root_element_name))
+    }
+
+    /// Checks that the root was closed once every line has been read.
+    fn finish(&self, locator: &ErrorLocator) -> Result<(), ParseError> {
+        if !self.root_started {
+            return Err(locator.error(SMLErrorType::RootNotClosed, 0, 0));
+        }
+        if !self.root_closed {
+            return Err(locator.error(SMLErrorType::RootNotClosed, self.last_line_num, 0));
         }
-        write!(f, "{}", result)?;
         Ok(())
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum SMLErrorType {
-    /// This error can happen for 2 reasons:
-    /// 1. the file was empty or only contained comments
-    /// 2. the file is invalid SML.
-    EndKeywordNotDetected,
-    InvalidRootElementStart,
-    NullValueAsElementName,
-    NullValueAsAttributeName,
-    RootNotClosed,
-    OnlyOneRootElementAllowed,
+/// Parses the Simple Markup Language text without building a tree,
+/// passing each element and attribute to handler as it's read. This is
+/// useful for very large documents, since only one line is held in
+/// memory at a time. Returning ControlFlow::Break from handler stops
+/// parsing immediately, and the rest of the text isn't checked.
+///
+/// Since events are passed to handler as they're read, handler may see
+/// events from before an error is found. Errors are reported on the first
+/// line that is invalid, so a document with several problems may report a
+/// different error than [parse](https://docs.rs/simpleml/latest/simpleml/fn.parse.html).
+pub fn parse_events<'a>(
+    source_text: &'a str,
+    handler: impl FnMut(SmlEvent<'a>) -> ControlFlow<()>,
+) -> Result<(), ParseError> {
+    let locator = ErrorLocator {
+        source_text,
+        tab_width: ParseOptions::default().tab_width,
+    };
+
+    // When the text has no values or isn't valid WSV, parse reports the
+    // same error with its exact location.
+    let end_keyword = match last_line_first_value(source_text) {
+        Ok(Some((_, end_keyword))) => end_keyword.map(|val| val.to_lowercase()),
+        Ok(None) | Err(_) => return parse(source_text).map(|_| ()),
+    };
+
+    let mut wsv_error = false;
+    let lines = source_text
+        .split('\n')
+        .map_while(|line| match whitespacesv::parse(line) {
+            Err(_) => {
+                wsv_error = true;
+                None
+            }
+            Ok(wsv) => Some(wsv.into_iter().next().unwrap_or_default()),
+        });
+    let result = read_document(
+        lines,
+        end_keyword.as_deref(),
+        &locator,
+        &ParseOptions::default(),
+        None,
+        &mut EventSink(handler),
+    );
+
+    if wsv_error {
+        return parse(source_text).map(|_| ());
+    }
+    result.map(|_| ())
 }
 
-#[derive(Debug)]
-pub struct SMLElement<StrAsRef>
-where
-    StrAsRef: AsRef<str>,
-{
-    pub name: StrAsRef,
-    pub attributes: Vec<SMLAttribute<StrAsRef>>,
+/// An event passed to the handler of [parse_events].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SmlEvent<'a> {
+    /// An element with the given name was opened.
+    ElementStart(Cow<'a, str>),
+    /// The innermost open element has an attribute with the given name
+    /// and values.
+    Attribute(Cow<'a, str>, Vec<Option<Cow<'a, str>>>),
+    /// The innermost open element was closed.
+    ElementEnd,
 }
 
-impl SMLElement<Cow<'_, str>> {
-    fn to_owned(self) -> SMLElement<String> {
-        let mut attributes = Vec::with_capacity(self.attributes.len());
-        for attr in self.attributes {
-            attributes.push(attr.to_owned());
+/// A stricter variant of [parse](https://docs.rs/simpleml/latest/simpleml/fn.parse.html)
+/// for read-only scanning. Every name and value is a &str slice directly
+/// into the source text, so no strings are allocated. Values that can't
+/// be borrowed because they contain escape sequences ("" or "/") cause
+/// an [SMLErrorType::ValueNeedsUnescaping] error instead.
+pub fn parse_borrowed(source_text: &str) -> Result<TreeNode<SMLElement<&str>>, ParseError> {
+    let locator = ErrorLocator {
+        source_text,
+        tab_width: ParseOptions::default().tab_width,
+    };
+
+    let wsv = whitespacesv::parse(source_text).map_err(ParseError::WSV)?;
+    let end_keyword = match detect_end_keyword_raw(&wsv) {
+        None => return Err(locator.error(SMLErrorType::EndKeywordNotDetected, wsv.len(), 0)),
+        Some(end_keyword) => end_keyword.map(|val| val.to_lowercase()),
+    };
+
+    let mut builder = BorrowedTreeBuilder {
+        builder: TreeBuilder::new(),
+        needs_unescaping: None,
+    };
+    let mut lines_read = 0;
+    let result = read_document(
+        wsv.into_iter().inspect(|_| lines_read += 1),
+        end_keyword.as_deref(),
+        &locator,
+        &ParseOptions::default(),
+        None,
+        &mut builder,
+    );
+    if let Some(value_index) = builder.needs_unescaping {
+        // Reading stops on the line with the value.
+        return Err(locator.error(
+            SMLErrorType::ValueNeedsUnescaping,
+            lines_read - 1,
+            value_index,
+        ));
+    }
+    // Otherwise, BorrowedTreeBuilder never stops reading early.
+    let _ = result?;
+    match builder.builder.result {
+        Some(tree) => Ok(tree),
+        // read_document only succeeds once the root is closed.
+        None => Err(locator.error(SMLErrorType::RootNotClosed, 0, 0)),
+    }
+}
+
+/// Finds every fenced code block in the Markdown text whose info string
+/// is "sml" (ex. ```sml) and parses its contents. Other code blocks are
+/// ignored. Results are returned in the order the blocks appear, and a
+/// block that is never closed runs to the end of the text.
+pub fn parse_markdown_blocks(md: &str) -> Vec<Result<TreeNode<SMLElement<String>>, ParseError>> {
+    let mut results = Vec::new();
+    let mut lines = md.lines();
+    while let Some(line) = lines.next() {
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        if indent > 3 {
+            continue;
         }
 
-        SMLElement {
-            name: match self.name {
-                Cow::Borrowed(str) => str.to_string(),
-                Cow::Owned(string) => string,
-            },
-            attributes,
+        let fence_line = &line[indent..];
+        let fence_char = match fence_line.chars().next() {
+            Some(ch @ ('`' | '~')) => ch,
+            _ => continue,
+        };
+        let fence_len = fence_line.len() - fence_line.trim_start_matches(fence_char).len();
+        if fence_len < 3 {
+            continue;
+        }
+
+        let info = fence_line[fence_len..].trim();
+        let is_sml = info.split_whitespace().next() == Some("sml");
+
+        let mut contents = Vec::new();
+        for line in lines.by_ref() {
+            let trimmed = line.trim_start_matches(' ');
+            let closing_len = trimmed.len() - trimmed.trim_start_matches(fence_char).len();
+            if line.len() - trimmed.len() <= 3
+                && closing_len >= fence_len
+                && trimmed[closing_len..].trim().is_empty()
+            {
+                break;
+            }
+
+            // content lines lose up to as much indentation as the fence had.
+            let content_indent = (line.len() - trimmed.len()).min(indent);
+            contents.push(&line[content_indent..]);
+        }
+
+        if is_sml {
+            results.push(parse_owned(&contents.join("\n")));
         }
     }
+    results
 }
 
-#[derive(Debug)]
-pub struct SMLAttribute<StrAsRef>
-where
-    StrAsRef: AsRef<str>,
-{
-    pub name: StrAsRef,
-    pub values: Vec<Option<StrAsRef>>,
+/// Parses text that contains several SML documents back to back, each
+/// with its own root element and end keyword, into one tree per document.
+///
+/// Since a document's end keyword is only known once its last line is
+/// found, each document is taken to end at the latest line whose value,
+/// used as the end keyword, closes the document's root element. This
+/// means that any text [parse] accepts is parsed as a single document.
+/// Line numbers in errors refer to the original source text.
+pub fn parse_many(
+    source_text: &str,
+) -> Result<Vec<TreeNode<SMLElement<Cow<'_, str>>>>, ParseError> {
+    let locator = ErrorLocator {
+        source_text,
+        tab_width: ParseOptions::default().tab_width,
+    };
+
+    let mut wsv = match whitespacesv::parse(source_text) {
+        Err(err) => return Err(ParseError::WSV(err)),
+        Ok(wsv) => wsv,
+    };
+
+    // The byte offset of each line, so that each document can be located
+    // within its own slice of the text.
+    let mut line_starts = Vec::with_capacity(wsv.len());
+    let mut offset = 0;
+    for line in source_text.split('\n') {
+        line_starts.push(offset);
+        offset += line.len() + 1;
+    }
+
+    let ends = document_ends(&wsv);
+    let mut result = Vec::new();
+    let mut start = 0;
+    loop {
+        while start < wsv.len() && wsv[start].is_empty() {
+            start += 1;
+        }
+        if start == wsv.len() {
+            break;
+        }
+
+        // When no line closes the document, the rest of the text is built
+        // as-is so that the error is reported as parse would report it.
+        let end = ends[start].unwrap_or(wsv.len() - 1);
+
+        // Errors are located within the document's own text, then moved
+        // so that they match the source text.
+        let document_locator = ErrorLocator {
+            source_text: &source_text[line_starts[start]..],
+            tab_width: locator.tab_width,
+        };
+        let document = wsv[start..=end].iter_mut().map(std::mem::take).collect();
+        let tree = build_tree(document, &document_locator, &ParseOptions::default())
+            .map_err(|err| err.shift_location(start as isize, line_starts[start] as isize))?;
+        result.push(tree);
+        start = end + 1;
+    }
+
+    if result.is_empty() {
+        return Err(locator.error(SMLErrorType::EndKeywordNotDetected, wsv.len(), 0));
+    }
+    Ok(result)
 }
 
-impl SMLAttribute<Cow<'_, str>> {
-    fn to_owned(self) -> SMLAttribute<String> {
-        let mut values = Vec::with_capacity(self.values.len());
-        for value in self.values {
-            let new_value = match value {
-                None => None,
-                Some(cow) => match cow {
-                    Cow::Borrowed(str) => Some(str.to_string()),
-                    Cow::Owned(string) => Some(string),
-                },
-            };
+/// Finds, for every line, the last line that closes the element opened by
+/// that line when its value is used as the end keyword, in one pass.
+///
+/// Under a given end keyword, every other single value line opens an
+/// element and each line with the keyword closes one, so the lines form a
+/// sequence of brackets. The line that closes an element is the bracket
+/// matching the line that opened it, so each keyword keeps a stack of the
+/// lines that haven't been matched yet. Since the lines between two of a
+/// keyword's closers are all openers, the stack holds runs of consecutive
+/// single value lines instead of every line.
+fn document_ends(lines: &[Vec<Option<Cow<'_, str>>>]) -> Vec<Option<usize>> {
+    let mut ends = vec![None; lines.len()];
+    // The line number of each single value line.
+    let mut single_value_lines = Vec::new();
+    // For each end keyword, the unmatched runs of single value lines (as
+    // ranges of indices into single_value_lines) and the index of the
+    // keyword's last line.
+    let mut keywords = std::collections::HashMap::new();
+    for (line_num, line) in lines.iter().enumerate() {
+        if line.len() != 1 {
+            continue;
+        }
+        let index = single_value_lines.len();
+        single_value_lines.push(line_num);
 
-            values.push(new_value);
+        let end_keyword = line[0].as_ref().map(|val| val.to_lowercase());
+        let (runs, last) = keywords
+            .entry(end_keyword)
+            .or_insert_with(|| (Vec::<Range<usize>>::new(), None));
+        let run_start = last.map_or(0, |last| last + 1);
+        if run_start < index {
+            runs.push(run_start..index);
         }
-        SMLAttribute {
-            name: match self.name {
-                Cow::Borrowed(str) => str.to_string(),
-                Cow::Owned(string) => string,
-            },
-            values,
+        *last = Some(index);
+
+        if let Some(run) = runs.last_mut() {
+            let opener = single_value_lines[run.end - 1];
+            run.end -= 1;
+            if run.start == run.end {
+                runs.pop();
+            }
+            ends[opener] = ends[opener].max(Some(line_num));
         }
     }
+    ends
 }
 
-#[cfg(test)]
-mod tests {
-    use tree_iterators_rs::prelude::OwnedTreeNode;
+/// Parses a sequence of sibling elements that have no enclosing root
+/// element by wrapping all of them in a synthetic root element named
+/// root_name. Each top-level element must still be closed by the end
+/// keyword, which is detected as usual from the last non-empty line.
+/// Attribute lines outside of any element become attributes of the
+/// synthetic root. Line numbers in errors refer to the original source
+/// text.
+pub fn parse_rootless<'a>(
+    source_text: &'a str,
+    root_name: &'a str,
+) -> Result<TreeNode<SMLElement<Cow<'a, str>>>, ParseError> {
+    let mut wsv = match whitespacesv::parse(source_text) {
+        Err(err) => return Err(ParseError::WSV(err)),
+        Ok(wsv) => wsv,
+    };
 
-    use crate::{SMLAttribute, SMLElement, SMLWriter};
+    // The synthetic root and end keyword lines are blank in the text used
+    // for error locations, so every other line keeps its own text.
+    let located_text = format!("\n{}\n", source_text);
+    let locator = ErrorLocator {
+        source_text: &located_text,
+        tab_width: ParseOptions::default().tab_width,
+    };
 
-    #[test]
-    fn reads_example_correctly() {
-        let result = super::parse(include_str!("../example.txt")).unwrap();
-        for (i, element) in result.dfs_preorder().enumerate() {
-            match i {
-                0 => {
-                    assert_eq!("Configuration", &element.name);
-                    assert_eq!(0, element.attributes.len());
+    let end_keyword = match detect_end_keyword_raw(&wsv) {
+        None => {
+            return Err(locator
+                .error(SMLErrorType::EndKeywordNotDetected, wsv.len(), 0)
+                .shift_location(-1, -1))
+        }
+        Some(end_keyword) => end_keyword.cloned(),
+    };
+    wsv.insert(0, vec![Some(Cow::Borrowed(root_name))]);
+    wsv.push(vec![end_keyword]);
+
+    build_tree(wsv, &locator, &ParseOptions::default()).map_err(|err| err.shift_location(-1, -1))
+}
+
+/// Parses a fragment of SML that consists only of attribute lines (no
+/// element wrapper and no end keyword) by wrapping all of them in a
+/// synthetic root element named root_name. Every non-empty line becomes
+/// an attribute of the root, and end keyword detection is skipped entirely.
+pub fn parse_lenient_fragment<'a>(
+    source_text: &'a str,
+    root_name: &'a str,
+) -> Result<TreeNode<SMLElement<Cow<'a, str>>>, ParseError> {
+    let locator = ErrorLocator {
+        source_text,
+        tab_width: ParseOptions::default().tab_width,
+    };
+
+    let wsv = match whitespacesv::parse(source_text) {
+        Err(err) => return Err(ParseError::WSV(err)),
+        Ok(wsv) => wsv,
+    };
+
+    let mut attributes = Vec::new();
+    for (line_num, line) in wsv.into_iter().enumerate() {
+        let mut values = line.into_iter();
+        let name = match values.next() {
+            None => continue,
+            Some(None) => {
+                return Err(locator.error(SMLErrorType::NullValueAsAttributeName, line_num, 0))
+            }
+            Some(Some(name)) => name,
+        };
+
+        attributes.push(SMLAttribute {
+            name,
+            values: values.collect(),
+        });
+    }
+
+    Ok(TreeNode {
+        value: SMLElement {
+            name: Cow::Borrowed(root_name),
+            attributes,
+        },
+        children: Vec::new(),
+    })
+}
+
+/// Equivalent to [parse](https://docs.rs/simpleml/latest/simpleml/fn.parse.html),
+/// but also returns information about the source text that isn't part
+/// of the tree, like the end keyword it used. Passing
+/// [ParseMetadata::end_keyword] to [SMLWriter::with_end_keyword] writes
+/// the tree back out with the original end keyword.
+#[allow(clippy::type_complexity)]
+pub fn parse_with_metadata(
+    source_text: &str,
+) -> Result<(TreeNode<SMLElement<Cow<'_, str>>>, ParseMetadata), ParseError> {
+    let locator = ErrorLocator {
+        source_text,
+        tab_width: ParseOptions::default().tab_width,
+    };
+
+    let wsv = match whitespacesv::parse(source_text) {
+        Err(err) => return Err(ParseError::WSV(err)),
+        Ok(wsv) => wsv,
+    };
+
+    let metadata = ParseMetadata {
+        end_keyword: detect_end_keyword_raw(&wsv)
+            .flatten()
+            .map(|end_keyword| end_keyword.to_string()),
+        line_count: source_text.split('\n').count(),
+    };
+    let tree = build_tree(wsv, &locator, &ParseOptions::default())?;
+    Ok((tree, metadata))
+}
+
+/// Information about the source text of a document, as captured by
+/// [parse_with_metadata].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseMetadata {
+    end_keyword: Option<String>,
+    line_count: usize,
+}
+
+impl ParseMetadata {
+    /// Gets the end keyword exactly as it was written in the source text,
+    /// or None if the document used the null end keyword (-).
+    pub fn end_keyword(&self) -> Option<&str> {
+        self.end_keyword.as_deref()
+    }
+
+    /// Gets the total number of lines in the source text, including empty
+    /// lines. Text that ends with a line break has an empty last line.
+    pub fn line_count(&self) -> usize {
+        self.line_count
+    }
+}
+
+/// Equivalent to [parse](https://docs.rs/simpleml/latest/simpleml/fn.parse.html),
+/// but also captures the original indentation of each line, the spacing
+/// between the values on each attribute line, and whether each value was
+/// quoted. Passing the [SMLLayout] to [SMLWriter::with_layout] will
+/// reproduce the indentation of every line, and the spacing and quoting
+/// of any attribute line whose number of values hasn't changed.
+#[allow(clippy::type_complexity)]
+pub fn parse_with_layout(
+    source_text: &str,
+) -> Result<(TreeNode<SMLElement<Cow<'_, str>>>, SMLLayout), ParseError> {
+    let mut layout = SMLLayout::default();
+    let tree = parse_line_targets(source_text, |_, line, target| {
+        let values = scan_line(line).values;
+        let indent = match values.first() {
+            None => return,
+            Some(first) => line[..first.start].to_string(),
+        };
+
+        let target = match target {
+            LineTarget::Empty => return,
+            LineTarget::End(element) => {
+                layout.end_indents.insert(element, indent);
+                return;
+            }
+            LineTarget::Start(target @ CommentTarget::Element(_)) => {
+                layout.indents.insert(target, indent);
+                return;
+            }
+            LineTarget::Start(target) => target,
+        };
+
+        layout.indents.insert(target, indent);
+        let separators = values
+            .windows(2)
+            .map(|pair| line[pair[0].end..pair[1].start].to_string())
+            .collect();
+        layout.separators.insert(target, separators);
+        let quoted = values
+            .iter()
+            .map(|range| line[range.clone()].starts_with('"'))
+            .collect();
+        layout.quoted.insert(target, quoted);
+    })?;
+
+    Ok((tree, layout))
+}
+
+/// Equivalent to [parse](https://docs.rs/simpleml/latest/simpleml/fn.parse.html),
+/// but also passes each line of the source text to on_line as it's read,
+/// along with its line number and what it defined.
+fn parse_line_targets<'a>(
+    source_text: &'a str,
+    mut on_line: impl FnMut(usize, &'a str, LineTarget),
+) -> Result<TreeNode<SMLElement<Cow<'a, str>>>, ParseError> {
+    let options = ParseOptions::default();
+    let locator = ErrorLocator {
+        source_text,
+        tab_width: options.tab_width,
+    };
+
+    let wsv = match whitespacesv::parse(source_text) {
+        Err(err) => return Err(ParseError::WSV(err)),
+        Ok(wsv) => wsv,
+    };
+    let end_keyword = match detect_end_keyword_raw(&wsv) {
+        None => return Err(locator.error(SMLErrorType::EndKeywordNotDetected, wsv.len(), 0)),
+        Some(end_keyword) => end_keyword.map(|val| val.to_lowercase()),
+    };
+
+    let mut reader = DocumentReader::new(end_keyword.as_deref());
+    let mut sink = LineTargetSink {
+        builder: TreeBuilder::new(),
+        next_element: 0,
+        open_elements: Vec::new(),
+        target: LineTarget::Empty,
+    };
+    let lines = wsv.into_iter().zip(source_text.split('\n'));
+    for (line_num, (values, line)) in lines.enumerate() {
+        // TreeBuilder never stops reading early.
+        let _ = reader.read_line(line_num, values, &locator, &options, &mut None, &mut sink)?;
+        on_line(
+            line_num,
+            line,
+            std::mem::replace(&mut sink.target, LineTarget::Empty),
+        );
+    }
+    reader.finish(&locator)?;
+
+    match sink.builder.result {
+        Some(tree) => Ok(tree),
+        // finish only succeeds once the root is closed.
+        None => Err(locator.error(SMLErrorType::RootNotClosed, 0, 0)),
+    }
+}
+
+/// Equivalent to [parse](https://docs.rs/simpleml/latest/simpleml/fn.parse.html),
+/// but also captures the document's comments. Comment text excludes the
+/// leading '#'.
+///
+/// 1. The trailing comment at the end of each element and attribute line
+///    (ex. "Volume 100  # percent") is captured with
+///    [SMLComments::trailing].
+/// 2. Lines that contain only a comment are attached to the nearest
+///    following element or attribute line as [SMLComments::leading]
+///    comments, along with their line numbers. If an end keyword comes
+///    first, they are attached to the element it closes as
+///    [SMLComments::before_end] comments instead.
+///
+/// Comments after the root's end keyword and trailing comments on end
+/// keyword lines aren't captured. Passing the [SMLComments] to
+/// [SMLWriter::with_comments] will write the comments back out.
+#[allow(clippy::type_complexity)]
+pub fn parse_with_comments(
+    source_text: &str,
+) -> Result<(TreeNode<SMLElement<Cow<'_, str>>>, SMLComments), ParseError> {
+    let mut comments = SMLComments::default();
+    let mut pending = Vec::new();
+    let tree = parse_line_targets(source_text, |line_num, line, target| {
+        let comment = scan_line(line)
+            .comment
+            .map(|start| line[start + 1..].trim_end_matches(is_whitespace));
+
+        let target = match target {
+            LineTarget::Start(target) => target,
+            LineTarget::Empty => {
+                if let Some(text) = comment {
+                    pending.push(LineComment {
+                        text: text.to_string(),
+                        line_num: Some(line_num),
+                    });
                 }
-                1 => {
-                    assert_eq!("Video", &element.name);
-                    assert_eq!(3, element.attributes.len());
-                    for (j, attribute) in element.attributes.into_iter().enumerate() {
-                        match j {
-                            0 => {
-                                assert_eq!("Resolution", attribute.name);
-                                assert_eq!(2, attribute.values.len());
-                                assert_eq!(
-                                    "1280",
-                                    attribute
-                                        .values
-                                        .get(0)
-                                        .as_ref()
-                                        .unwrap()
-                                        .as_ref()
-                                        .unwrap()
-                                        .as_ref()
-                                );
-                                assert_eq!(
-                                    "720",
-                                    attribute
-                                        .values
-                                        .get(1)
-                                        .as_ref()
-                                        .unwrap()
-                                        .as_ref()
-                                        .unwrap()
-                                        .as_ref()
-                                );
-                            }
-                            1 => {
-                                assert_eq!("RefreshRate", attribute.name);
-                                assert_eq!(1, attribute.values.len());
-                                assert_eq!(
-                                    "60",
-                                    attribute
-                                        .values
-                                        .get(0)
-                                        .as_ref()
-                                        .unwrap()
-                                        .as_ref()
-                                        .unwrap()
-                                        .as_ref()
-                                );
-                            }
-                            2 => {
-                                assert_eq!("Fullscreen", attribute.name);
-                                assert_eq!(1, attribute.values.len());
-                                assert_eq!(
-                                    "true",
-                                    attribute
-                                        .values
-                                        .get(0)
-                                        .as_ref()
-                                        .unwrap()
-                                        .as_ref()
-                                        .unwrap()
-                                        .as_ref()
-                                );
-                            }
-                            _ => panic!("Should only have 3 attributes"),
-                        }
-                    }
-                }
-                2 => {
-                    assert_eq!("Audio", &element.name);
-                    assert_eq!(2, element.attributes.len());
-                    for (j, attribute) in element.attributes.into_iter().enumerate() {
-                        match j {
-                            0 => {
-                                assert_eq!("Volume", attribute.name);
-                                assert_eq!(1, attribute.values.len());
-                                assert_eq!(
-                                    "100",
-                                    attribute
-                                        .values
-                                        .get(0)
-                                        .as_ref()
-                                        .unwrap()
-                                        .as_ref()
-                                        .unwrap()
-                                        .as_ref()
-                                );
-                            }
-                            1 => {
-                                assert_eq!("Music", attribute.name);
-                                assert_eq!(1, attribute.values.len());
-                                assert_eq!(
-                                    "80",
-                                    attribute
-                                        .values
-                                        .get(0)
-                                        .as_ref()
-                                        .unwrap()
-                                        .as_ref()
-                                        .unwrap()
-                                        .as_ref()
-                                );
-                            }
-                            _ => panic!("Should only have 2 values under audio"),
-                        }
-                    }
+                return;
+            }
+            LineTarget::End(element) => {
+                if !pending.is_empty() {
+                    comments
+                        .before_end
+                        .entry(element)
+                        .or_default()
+                        .append(&mut pending);
                 }
-                3 => {
-                    assert_eq!("Player", element.name);
-                    assert_eq!(1, element.attributes.len());
-                    let attr = element.attributes.get(0).unwrap();
-                    assert_eq!("Name", attr.name);
-                    assert_eq!(1, attr.values.len());
-                    assert_eq!(
-                        "Hero 123",
-                        attr.values
-                            .get(0)
-                            .as_ref()
-                            .unwrap()
-                            .as_ref()
-                            .unwrap()
-                            .as_ref()
-                    );
+                return;
+            }
+        };
+
+        if !pending.is_empty() {
+            comments
+                .leading
+                .entry(target)
+                .or_default()
+                .append(&mut pending);
+        }
+        if let Some(text) = comment {
+            comments.set_trailing(target, text);
+        }
+    })?;
+
+    Ok((tree, comments))
+}
+
+/// Parses the source text and writes it back out, preserving the original
+/// indentation (tabs or spaces), the spacing between the values on each
+/// attribute line, and the original end keyword.
+pub fn reformat_preserving(source_text: &str) -> Result<String, ReformatError> {
+    let (tree, layout) = match parse_with_layout(source_text) {
+        Err(err) => return Err(ReformatError::Parse(err)),
+        Ok(parsed) => parsed,
+    };
+
+    let wsv = whitespacesv::parse(source_text)
+        .map_err(|err| ReformatError::Parse(ParseError::WSV(err)))?;
+    let end_keyword = detect_end_keyword_raw(&wsv)
+        .flatten()
+        .map(|val| val.to_string());
+
+    SMLWriter::new(tree)
+        .with_end_keyword(end_keyword.as_deref())
+        .with_layout(layout)
+        .to_string()
+        .map_err(ReformatError::Write)
+}
+
+/// The original spacing of a parsed document, as captured by
+/// [parse_with_layout]. Lines are addressed the same way as comments.
+/// See [CommentTarget] for details.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SMLLayout {
+    separators: BTreeMap<CommentTarget, Vec<String>>,
+    quoted: BTreeMap<CommentTarget, Vec<bool>>,
+    indents: BTreeMap<CommentTarget, String>,
+    end_indents: BTreeMap<usize, String>,
+}
+
+impl SMLLayout {
+    /// Gets the original whitespace between each of the values on the
+    /// target's line (including the attribute name).
+    pub fn separators(&self, target: CommentTarget) -> Option<&[String]> {
+        self.separators
+            .get(&target)
+            .map(|separators| separators.as_slice())
+    }
+
+    /// Gets the original leading whitespace of the target's line.
+    pub fn indent(&self, target: CommentTarget) -> Option<&str> {
+        self.indents.get(&target).map(|indent| indent.as_str())
+    }
+
+    /// Gets the original leading whitespace of the line that closes the
+    /// element with the given preorder index.
+    pub fn end_indent(&self, element: usize) -> Option<&str> {
+        self.end_indents.get(&element).map(|indent| indent.as_str())
+    }
+
+    /// Gets whether each of the values on the target's line (including
+    /// the attribute name) was quoted in the original text.
+    pub fn quoted(&self, target: CommentTarget) -> Option<&[bool]> {
+        self.quoted.get(&target).map(|quoted| quoted.as_slice())
+    }
+
+    /// Pairs each of the attribute's values with whether it was quoted in
+    /// the original text. The attribute must be the one at target. Values
+    /// that weren't part of the original line are reported as unquoted.
+    pub fn values_with_quote_flags<StrAsRef>(
+        &self,
+        target: CommentTarget,
+        attribute: &SMLAttribute<StrAsRef>,
+    ) -> Vec<(Option<String>, bool)>
+    where
+        StrAsRef: AsRef<str>,
+    {
+        let quoted = self.quoted(target).unwrap_or(&[]);
+        attribute
+            .values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| {
+                (
+                    value.as_ref().map(|value| value.as_ref().to_string()),
+                    quoted.get(i + 1).copied().unwrap_or(false),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Streams through the source text, yielding only the subtrees whose root
+/// element is named name (case-sensitive). Everything else is discarded as
+/// it is read, so at most one matching subtree is held in memory at a time.
+/// If a matching element contains another matching element, only the outer
+/// one is yielded.
+///
+/// Errors in the document are yielded when they are reached, after which
+/// the iterator ends.
+pub fn extract_elements<'a>(source_text: &'a str, name: &'a str) -> ExtractElements<'a> {
+    ExtractElements {
+        locator: ErrorLocator {
+            source_text,
+            tab_width: ParseOptions::default().tab_width,
+        },
+        lines: whitespacesv::parse_lazy(source_text.chars()).enumerate(),
+        reader: None,
+        sink: ExtractSink {
+            name,
+            building: Vec::new(),
+            extracted: None,
+        },
+        finished: false,
+    }
+}
+
+/// The iterator returned by [extract_elements].
+pub struct ExtractElements<'a> {
+    locator: ErrorLocator<'a>,
+    lines: std::iter::Enumerate<WSVLineIterator<std::str::Chars<'a>>>,
+    /// None until the end keyword has been detected.
+    reader: Option<DocumentReader>,
+    sink: ExtractSink<'a>,
+    finished: bool,
+}
+
+/// Builds the subtrees of [ExtractElements] as lines are read.
+struct ExtractSink<'a> {
+    name: &'a str,
+    building: Vec<TreeNode<SMLElement<String>>>,
+    /// The last matching subtree to be closed.
+    extracted: Option<TreeNode<SMLElement<String>>>,
+}
+
+impl<'a> DocumentSink<'a> for ExtractSink<'_> {
+    fn element_start(&mut self, name: Cow<'a, str>) -> ControlFlow<()> {
+        if !self.building.is_empty() || name == self.name {
+            self.building.push(TreeNode {
+                value: SMLElement {
+                    name: name.into_owned(),
+                    attributes: Vec::with_capacity(0),
+                },
+                children: Vec::new(),
+            });
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn attribute(&mut self, attribute: SMLAttribute<Cow<'a, str>>) -> ControlFlow<()> {
+        if let Some(top) = self.building.last_mut() {
+            top.value.attributes.push(SMLAttribute {
+                name: attribute.name.into_owned(),
+                values: attribute
+                    .values
+                    .into_iter()
+                    .map(|value| value.map(Cow::into_owned))
+                    .collect(),
+            });
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn element_end(&mut self) -> ControlFlow<()> {
+        if let Some(top) = self.building.pop() {
+            match self.building.last_mut() {
+                None => self.extracted = Some(top),
+                Some(parent) => parent.children.push(top),
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+impl ExtractElements<'_> {
+    fn fail(
+        &mut self,
+        err: ParseError,
+    ) -> Option<Result<TreeNode<SMLElement<String>>, ParseError>> {
+        self.finished = true;
+        Some(Err(err))
+    }
+
+    /// Detects the end keyword from the last non-empty line without
+    /// reading the rest of the document.
+    fn detect_end_keyword(&self) -> Result<DocumentReader, ParseError> {
+        let source_text = self.locator.source_text;
+        match last_line_first_value(source_text).map_err(ParseError::WSV)? {
+            Some((_, first)) => Ok(DocumentReader::new(
+                first.map(|val| val.to_lowercase()).as_deref(),
+            )),
+            None => Err(self.locator.error(
+                SMLErrorType::EndKeywordNotDetected,
+                source_text.split('\n').count(),
+                0,
+            )),
+        }
+    }
+}
+
+impl Iterator for ExtractElements<'_> {
+    type Item = Result<TreeNode<SMLElement<String>>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let mut reader = match self.reader.take() {
+            Some(reader) => reader,
+            None => match self.detect_end_keyword() {
+                Ok(reader) => reader,
+                Err(err) => return self.fail(err),
+            },
+        };
+        let result = loop {
+            let (line_num, line) = match self.lines.next() {
+                None => {
+                    self.finished = true;
+                    break reader.finish(&self.locator).err().map(Err);
                 }
-                _ => panic!("Should only have 4 sub-elements"),
+                Some((_, Err(err))) => break self.fail(ParseError::WSV(err)),
+                Some((line_num, Ok(line))) => (line_num, line),
+            };
+            let line = line
+                .into_iter()
+                .map(|value| value.map(Cow::Owned))
+                .collect();
+            if let Err(err) = reader.read_line(
+                line_num,
+                line,
+                &self.locator,
+                &ParseOptions::default(),
+                &mut None,
+                &mut self.sink,
+            ) {
+                break self.fail(err);
+            }
+            if let Some(extracted) = self.sink.extracted.take() {
+                break Some(Ok(extracted));
             }
+        };
+        self.reader = Some(reader);
+        result
+    }
+}
+
+/// Options to configure the behavior of
+/// [parse_with_options](https://docs.rs/simpleml/latest/simpleml/fn.parse_with_options.html).
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    tab_width: usize,
+    max_attributes_per_element: Option<usize>,
+    warn_on_keyword_ambiguity: bool,
+    continuation_char: Option<char>,
+    limits: ParseLimits,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            tab_width: 1, // count every character as one column
+            max_attributes_per_element: None,
+            warn_on_keyword_ambiguity: false,
+            continuation_char: None,
+            limits: ParseLimits::default(),
         }
     }
+}
 
-    #[test]
-    fn reads_example_correctly_owned() {
-        let result = super::parse_owned(include_str!("../example.txt")).unwrap();
-        for (i, element) in result.dfs_preorder().enumerate() {
-            match i {
-                0 => {
-                    assert_eq!("Configuration", &element.name);
-                    assert_eq!(0, element.attributes.len());
+impl ParseOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of columns a tab character occupies when computing
+    /// the column numbers of errors. Tabs advance to the next tab stop, the
+    /// same way most editors render them, so the reported column will line
+    /// up with the editor's caret. The default is 1, which counts a tab as
+    /// a single column. A width of 0 is treated as 1.
+    pub fn tab_width(mut self, width: usize) -> Self {
+        self.tab_width = width.max(1);
+        self
+    }
+
+    /// Limits the number of attributes a single element may have. Parsing
+    /// fails with [SMLErrorType::TooManyAttributes] at the first attribute
+    /// past the limit. This guards against untrusted documents exhausting
+    /// memory with a huge element. The default is unlimited.
+    pub fn max_attributes_per_element(mut self, max: usize) -> Self {
+        self.max_attributes_per_element = Some(max);
+        self
+    }
+
+    /// Enables the [ParseWarningType::AmbiguousEndKeyword] warning from
+    /// [parse_with_warnings]. The end keyword is detected from the last
+    /// non-empty line, so if that line is an ordinary value instead of a
+    /// closer, the keyword is silently misdetected. This warns about every
+    /// attribute line that contains the detected keyword as a name or
+    /// value, which is a strong sign of misdetection.
+    pub fn warn_on_keyword_ambiguity(mut self, warn: bool) -> Self {
+        self.warn_on_keyword_ambiguity = warn;
+        self
+    }
+
+    /// Sets a character that continues a line onto the next physical line
+    /// when it ends the line outside of quotes. The values of the next
+    /// line are appended to the continued line, so long attribute value
+    /// lists can be wrapped. Only attribute lines (a name followed by at
+    /// least one value) can be continued, so the character is an ordinary
+    /// part of element names and end keywords. Errors still report the
+    /// physical line. The default is None, which disables continuations.
+    pub fn continuation_char(mut self, continuation_char: Option<char>) -> Self {
+        self.continuation_char = continuation_char;
+        self
+    }
+
+    /// Sets the limits on the document's size. See [ParseLimits]. The
+    /// default is unlimited.
+    pub fn limits(mut self, limits: ParseLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+}
+
+/// Limits on the size of a document, for parsing untrusted text with
+/// [parse_with_limits]. A limit of None is unlimited, which is the default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// The deepest an element may be nested. The root element has a depth
+    /// of 0 and its children have a depth of 1.
+    pub max_depth: Option<usize>,
+    /// The most elements the document may have, including the root.
+    pub max_elements: Option<usize>,
+}
+
+/// Computes the location of errors within the source text.
+struct ErrorLocator<'a> {
+    source_text: &'a str,
+    tab_width: usize,
+}
+
+impl ErrorLocator<'_> {
+    /// Builds an SMLError pointing at the value at value_index on the
+    /// given line. If that value can't be found, the error points at
+    /// the first value on the line instead (or column 1 if there is none).
+    fn error(&self, err_type: SMLErrorType, line_num: usize, value_index: usize) -> ParseError {
+        let mut line_start = 0;
+        let mut lines = self.source_text.split('\n');
+        for line in lines.by_ref().take(line_num) {
+            line_start += line.len() + 1;
+        }
+
+        let (column, byte_offset) = match lines.next() {
+            None => (1, self.source_text.len()),
+            Some(line) => {
+                let offset = find_value_offset(line, value_index)
+                    .or_else(|| find_value_offset(line, 0))
+                    .unwrap_or(0);
+                (
+                    visual_column(&line[..offset], self.tab_width),
+                    line_start + offset,
+                )
+            }
+        };
+
+        ParseError::SML(SMLError {
+            err_type,
+            line_num,
+            column,
+            byte_offset,
+        })
+    }
+}
+
+/// Finds the byte offset of the start of the value at value_index within
+/// a single line of WSV text. Returns None if the line has fewer values.
+fn find_value_offset(line: &str, value_index: usize) -> Option<usize> {
+    scan_line(line)
+        .values
+        .get(value_index)
+        .map(|value| value.start)
+}
+
+/// The raw layout of a single line of WSV text.
+struct LineScan {
+    /// The byte ranges of each value, including any quotes.
+    values: Vec<Range<usize>>,
+    /// The byte offset of the '#' that starts the line's comment.
+    comment: Option<usize>,
+}
+
+/// Scans a single line of WSV text for the locations of its values.
+/// The line is assumed to be valid WSV.
+fn scan_line(line: &str) -> LineScan {
+    let mut chars = line.char_indices().peekable();
+    let mut values = Vec::new();
+    loop {
+        while chars.next_if(|(_, ch)| is_whitespace(*ch)).is_some() {}
+
+        let (start, ch) = match chars.next() {
+            None => {
+                return LineScan {
+                    values,
+                    comment: None,
                 }
-                1 => {
-                    assert_eq!("Video", &element.name);
-                    assert_eq!(3, element.attributes.len());
-                    for (j, attribute) in element.attributes.into_iter().enumerate() {
-                        match j {
-                            0 => {
-                                assert_eq!("Resolution", attribute.name);
-                                assert_eq!(2, attribute.values.len());
-                                assert_eq!(
-                                    "1280",
-                                    attribute.values.get(0).as_ref().unwrap().as_ref().unwrap()
-                                );
-                                assert_eq!(
-                                    "720",
-                                    attribute.values.get(1).as_ref().unwrap().as_ref().unwrap()
-                                );
-                            }
-                            1 => {
-                                assert_eq!("RefreshRate", attribute.name);
-                                assert_eq!(1, attribute.values.len());
-                                assert_eq!(
-                                    "60",
-                                    attribute.values.get(0).as_ref().unwrap().as_ref().unwrap()
-                                );
-                            }
-                            2 => {
-                                assert_eq!("Fullscreen", attribute.name);
-                                assert_eq!(1, attribute.values.len());
-                                assert_eq!(
-                                    "true",
-                                    attribute.values.get(0).as_ref().unwrap().as_ref().unwrap()
-                                );
-                            }
-                            _ => panic!("Should only have 3 attributes"),
-                        }
-                    }
+            }
+            Some((start, '#')) => {
+                return LineScan {
+                    values,
+                    comment: Some(start),
                 }
-                2 => {
-                    assert_eq!("Audio", &element.name);
-                    assert_eq!(2, element.attributes.len());
-                    for (j, attribute) in element.attributes.into_iter().enumerate() {
-                        match j {
-                            0 => {
-                                assert_eq!("Volume", attribute.name);
-                                assert_eq!(1, attribute.values.len());
-                                assert_eq!(
-                                    "100",
-                                    attribute.values.get(0).as_ref().unwrap().as_ref().unwrap()
-                                );
-                            }
-                            1 => {
-                                assert_eq!("Music", attribute.name);
-                                assert_eq!(1, attribute.values.len());
-                                assert_eq!(
-                                    "80",
-                                    attribute.values.get(0).as_ref().unwrap().as_ref().unwrap()
-                                );
-                            }
-                            _ => panic!("Should only have 2 values under audio"),
+            }
+            Some(next) => next,
+        };
+
+        if ch == '"' {
+            while let Some((_, ch)) = chars.next() {
+                if ch != '"' {
+                    continue;
+                }
+                if chars.next_if(|(_, ch)| *ch == '"').is_some() {
+                    continue;
+                }
+                if chars.next_if(|(_, ch)| *ch == '/').is_some()
+                    && chars.next_if(|(_, ch)| *ch == '"').is_some()
+                {
+                    continue;
+                }
+                break;
+            }
+        } else {
+            while chars
+                .next_if(|(_, ch)| *ch != '"' && *ch != '#' && !is_whitespace(*ch))
+                .is_some()
+            {}
+        }
+
+        let end = match chars.peek() {
+            None => line.len(),
+            Some((end, _)) => *end,
+        };
+        values.push(start..end);
+    }
+}
+
+/// Detects the end keyword of a document without parsing it, using the
+/// same rule as [parse](https://docs.rs/simpleml/latest/simpleml/fn.parse.html):
+/// the end keyword is the first value of the last non-empty line. This is
+/// much cheaper than a full parse, since only the end of the document is
+/// read. Returns None if the document uses the null end keyword (-), has
+/// no values at all, or if its last line isn't valid WSV.
+pub fn detect_end_keyword(source_text: &str) -> Option<String> {
+    match last_line_first_value(source_text) {
+        Ok(Some((_, Some(keyword)))) => Some(keyword.into_owned()),
+        _ => None,
+    }
+}
+
+/// Finds the first value of the last non-empty line, along with that
+/// line's index, by reading lines backwards from the end of the text.
+#[allow(clippy::type_complexity)]
+fn last_line_first_value(
+    source_text: &str,
+) -> Result<Option<(usize, Option<Cow<'_, str>>)>, WSVError> {
+    let line_count = source_text.split('\n').count();
+    for (i, line) in source_text.rsplit('\n').enumerate() {
+        let wsv = whitespacesv::parse(line)?;
+        if let Some(first) = wsv.into_iter().flatten().next() {
+            return Ok(Some((line_count - 1 - i, first)));
+        }
+    }
+    Ok(None)
+}
+
+/// Detects the end keyword of a document from the first value of its last
+/// non-empty line. The outer Option is None if the document has no values
+/// at all. The inner Option is None if the end keyword is the null value.
+fn detect_end_keyword_raw<'a, 'b>(
+    lines: &'b [Vec<Option<Cow<'a, str>>>],
+) -> Option<Option<&'b Cow<'a, str>>> {
+    lines
+        .iter()
+        .rev()
+        .find_map(|line| line.first())
+        .map(Option::as_ref)
+}
+
+/// Whether the value matches the end keyword, which must already be
+/// lowercased. This is equivalent to comparing value.to_lowercase()
+/// against it, but doesn't allocate a String for every line.
+fn is_end_keyword(value: Option<&str>, end_keyword: Option<&str>) -> bool {
+    match (value, end_keyword) {
+        (None, None) => true,
+        (Some(value), Some(end_keyword)) => {
+            // str::to_lowercase lowercases a word-final capital sigma
+            // differently than char::to_lowercase does, so defer to it.
+            if value.contains('\u{03A3}') {
+                value.to_lowercase() == end_keyword
+            } else {
+                value
+                    .chars()
+                    .flat_map(char::to_lowercase)
+                    .eq(end_keyword.chars())
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Computes the 1-based column that the end of the given text lands on,
+/// expanding tabs to the next multiple of tab_width.
+fn visual_column(text: &str, tab_width: usize) -> usize {
+    let mut column = 0;
+    for ch in text.chars() {
+        if ch == '\t' {
+            column = (column / tab_width + 1) * tab_width;
+        } else {
+            column += 1;
+        }
+    }
+    column + 1
+}
+
+pub struct SMLWriter<StrAsRef>
+where
+    StrAsRef: AsRef<str>,
+{
+    options: SMLWriterOptions,
+    values: TreeNode<SMLElement<StrAsRef>>,
+}
+
+/// The configuration of an SMLWriter, so that the same configuration can
+/// be reused across writers with [SMLWriter::with_options]. Start from
+/// SMLWriterOptions::default() and configure it with the same methods as
+/// [SMLWriter], or take a copy of a writer's with [SMLWriter::options].
+#[derive(Debug, Clone)]
+pub struct SMLWriterOptions {
+    indent_str: String,
+    end_keyword: Option<String>,
+    column_alignment: Alignment,
+    comments: SMLComments,
+    comment_column: Option<usize>,
+    reject_non_ascii: bool,
+    skip_empty_attributes: bool,
+    always_quote: bool,
+    always_quote_values: bool,
+    trim_values: bool,
+    align_decimal: bool,
+    footer_stats: bool,
+    header_comment: Option<String>,
+    max_output_bytes: Option<usize>,
+    max_depth: Option<usize>,
+    blank_lines_between: BlankLinePolicy,
+    compact_simple_leaves: bool,
+    minified: bool,
+    deterministic: bool,
+    trailing_newline: bool,
+    line_ending: LineEnding,
+    layout: SMLLayout,
+}
+
+impl Default for SMLWriterOptions {
+    fn default() -> Self {
+        Self {
+            indent_str: "    ".to_string(), // default to 4 spaces
+            end_keyword: None,              // Use minified as the default
+            column_alignment: Alignment(ColumnAlignment::default()),
+            comments: SMLComments::default(),
+            comment_column: None,
+            reject_non_ascii: false,
+            skip_empty_attributes: false,
+            always_quote: false,
+            always_quote_values: false,
+            trim_values: false,
+            align_decimal: false,
+            footer_stats: false,
+            header_comment: None,
+            max_output_bytes: None,
+            max_depth: None,
+            blank_lines_between: BlankLinePolicy::None,
+            compact_simple_leaves: false,
+            minified: false,
+            deterministic: false,
+            trailing_newline: false,
+            line_ending: LineEnding::Lf,
+            layout: SMLLayout::default(),
+        }
+    }
+}
+
+impl SMLWriterOptions {
+    /// Equivalent to [SMLWriter::indent_with].
+    pub fn indent_with(mut self, str: &str) -> Option<Self> {
+        if str.chars().any(|ch| !is_whitespace(ch)) {
+            return None;
+        }
+        self.indent_str = str.to_string();
+        Some(self)
+    }
+
+    /// Equivalent to [SMLWriter::indent_with_exact].
+    pub fn indent_with_exact(self, str: &str) -> Option<Self> {
+        if !str.chars().all(|ch| ch == ' ') && !str.chars().all(|ch| ch == '\t') {
+            return None;
+        }
+        self.indent_with(str)
+    }
+
+    /// Equivalent to [SMLWriter::with_end_keyword].
+    pub fn with_end_keyword(mut self, str: Option<&str>) -> Self {
+        match str {
+            None | Some("") => {
+                self.end_keyword = None;
+                self
+            }
+            Some(str) => {
+                debug_assert!(!str.is_empty());
+                let needs_quotes = str
+                    .chars()
+                    .any(|ch| ch == '"' || ch == '#' || ch == '\n' || is_whitespace(ch))
+                    || str == "-";
+
+                if !needs_quotes {
+                    self.end_keyword = Some(str.to_string());
+                } else {
+                    let mut result = String::new();
+                    result.push('"');
+                    for ch in str.chars() {
+                        match ch {
+                            '"' => result.push_str("\"\""),
+                            '\n' => result.push_str("\"/\""),
+                            ch => result.push(ch),
                         }
                     }
+                    result.push('"');
+                    self.end_keyword = Some(result);
+                }
+                self
+            }
+        }
+    }
+
+    /// Equivalent to [SMLWriter::align_columns].
+    pub fn align_columns(mut self, alignment: ColumnAlignment) -> Self {
+        self.column_alignment = Alignment(alignment);
+        self
+    }
+
+    /// Equivalent to [SMLWriter::deterministic].
+    pub fn deterministic(mut self) -> Self {
+        self.deterministic = true;
+        self
+    }
+
+    /// Equivalent to [SMLWriter::align_decimal].
+    pub fn align_decimal(mut self) -> Self {
+        self.align_decimal = true;
+        self
+    }
+
+    /// Equivalent to [SMLWriter::blank_lines_between].
+    pub fn blank_lines_between(mut self, policy: BlankLinePolicy) -> Self {
+        self.blank_lines_between = policy;
+        self
+    }
+
+    /// Equivalent to [SMLWriter::blank_line_between_siblings].
+    pub fn blank_line_between_siblings(self, depth_threshold: usize) -> Self {
+        self.blank_lines_between(BlankLinePolicy::UpToDepth(depth_threshold))
+    }
+
+    /// Equivalent to [SMLWriter::compact_simple_leaves].
+    pub fn compact_simple_leaves(mut self) -> Self {
+        self.compact_simple_leaves = true;
+        self
+    }
+
+    /// Equivalent to [SMLWriter::minified].
+    pub fn minified(mut self) -> Self {
+        self.indent_str = String::new();
+        self.column_alignment = Alignment(ColumnAlignment::Packed);
+        self.align_decimal = false;
+        self.blank_lines_between = BlankLinePolicy::None;
+        self.minified = true;
+        self
+    }
+
+    /// Equivalent to [SMLWriter::trailing_newline].
+    pub fn trailing_newline(mut self, enabled: bool) -> Self {
+        self.trailing_newline = enabled;
+        self
+    }
+
+    /// Equivalent to [SMLWriter::line_ending].
+    pub fn line_ending(mut self, ending: LineEnding) -> Self {
+        self.line_ending = ending;
+        self
+    }
+
+    /// Equivalent to [SMLWriter::with_comments].
+    pub fn with_comments(mut self, comments: SMLComments) -> Self {
+        self.comments = comments;
+        self
+    }
+
+    /// Equivalent to [SMLWriter::comment_column].
+    pub fn comment_column(mut self, col: usize) -> Self {
+        self.comment_column = Some(col);
+        self
+    }
+
+    /// Equivalent to [SMLWriter::with_layout].
+    pub fn with_layout(mut self, layout: SMLLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Equivalent to [SMLWriter::reject_non_ascii].
+    pub fn reject_non_ascii(mut self) -> Self {
+        self.reject_non_ascii = true;
+        self
+    }
+
+    /// Equivalent to [SMLWriter::always_quote].
+    pub fn always_quote(mut self) -> Self {
+        self.always_quote = true;
+        self
+    }
+
+    /// Equivalent to [SMLWriter::always_quote_values].
+    pub fn always_quote_values(mut self, enabled: bool) -> Self {
+        self.always_quote_values = enabled;
+        self
+    }
+
+    /// Equivalent to [SMLWriter::header_comment].
+    pub fn header_comment(mut self, text: &str) -> Self {
+        self.header_comment = Some(text.to_string());
+        self
+    }
+
+    /// Equivalent to [SMLWriter::trim_values].
+    pub fn trim_values(mut self, enabled: bool) -> Self {
+        self.trim_values = enabled;
+        self
+    }
+
+    /// Equivalent to [SMLWriter::with_footer_stats].
+    pub fn with_footer_stats(mut self, footer_stats: bool) -> Self {
+        self.footer_stats = footer_stats;
+        self
+    }
+
+    /// Equivalent to [SMLWriter::max_output_bytes].
+    pub fn max_output_bytes(mut self, max: usize) -> Self {
+        self.max_output_bytes = Some(max);
+        self
+    }
+
+    /// Equivalent to [SMLWriter::max_depth].
+    pub fn max_depth(mut self, max: usize) -> Self {
+        self.max_depth = Some(max);
+        self
+    }
+
+    /// Equivalent to [SMLWriter::skip_empty_attributes].
+    pub fn skip_empty_attributes(mut self, skip: bool) -> Self {
+        self.skip_empty_attributes = skip;
+        self
+    }
+}
+
+/// [ColumnAlignment] doesn't implement Clone or Debug, so the options hold
+/// it in this wrapper to be able to derive them.
+struct Alignment(ColumnAlignment);
+
+impl Clone for Alignment {
+    fn clone(&self) -> Self {
+        Alignment(match self.0 {
+            ColumnAlignment::Left => ColumnAlignment::Left,
+            ColumnAlignment::Right => ColumnAlignment::Right,
+            ColumnAlignment::Packed => ColumnAlignment::Packed,
+        })
+    }
+}
+
+impl std::fmt::Debug for Alignment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            ColumnAlignment::Left => write!(f, "Left"),
+            ColumnAlignment::Right => write!(f, "Right"),
+            ColumnAlignment::Packed => write!(f, "Packed"),
+        }
+    }
+}
+
+impl<StrAsRef> SMLWriter<StrAsRef>
+where
+    StrAsRef: AsRef<str>,
+{
+    pub fn new(values: TreeNode<SMLElement<StrAsRef>>) -> Self {
+        Self {
+            values,
+            options: SMLWriterOptions::default(),
+        }
+    }
+
+    /// Creates a writer that uses the given options.
+    pub fn with_options(values: TreeNode<SMLElement<StrAsRef>>, options: SMLWriterOptions) -> Self {
+        Self { values, options }
+    }
+
+    /// Gets the options this writer is configured with.
+    pub fn options(&self) -> &SMLWriterOptions {
+        &self.options
+    }
+
+    /// Sets the indentation string to be used in the output.
+    /// If the passed in str contains any non-whitespace characters,
+    /// this call will fail and return None. Whitespace is any of the WSV
+    /// whitespace characters listed in the crate documentation, which
+    /// includes Unicode spaces (ex. U+3000 Ideographic Space) as well as
+    /// tabs and spaces. See [SMLWriter::indent_with_exact] for a stricter
+    /// check.
+    pub fn indent_with(mut self, str: &str) -> Option<Self> {
+        self.options = self.options.indent_with(str)?;
+        Some(self)
+    }
+
+    /// Equivalent to [SMLWriter::indent_with], but the indentation string
+    /// must be made up entirely of spaces or entirely of tabs. Mixed
+    /// indentation renders differently depending on the editor's tab
+    /// width, so this call fails and returns None for it (and for any
+    /// other whitespace characters).
+    pub fn indent_with_exact(mut self, str: &str) -> Option<Self> {
+        self.options = self.options.indent_with_exact(str)?;
+        Some(self)
+    }
+
+    /// Sets the end keyword to be used in the output.
+    /// If the passed in string is the empty string "",
+    /// '-' will be used instead.
+    pub fn with_end_keyword(mut self, str: Option<&str>) -> Self {
+        self.options = self.options.with_end_keyword(str);
+        self
+    }
+
+    /// Sets the column alignment of the attributes' generated WSV.
+    /// The element alignment will be unaffected, but all attributes
+    /// and their values will be aligned this way.
+    pub fn align_columns(mut self, alignment: ColumnAlignment) -> Self {
+        self.options = self.options.align_columns(alignment);
+        self
+    }
+
+    /// Writes the same bytes for the same logical tree no matter what
+    /// order its attributes and children are in, for reproducible builds.
+    /// Attributes and child elements are sorted by their names (comparing
+    /// bytes, not locale-aware collation), with ties broken by their
+    /// contents. Comments and layout are left out, since they are attached
+    /// to positions in the original order. Every other option still
+    /// applies, whether it's set before or after this call, and none of
+    /// them depend on the platform or locale.
+    pub fn deterministic(mut self) -> Self {
+        self.options = self.options.deterministic();
+        self
+    }
+
+    /// Aligns the numeric values of each attribute column on their decimal
+    /// points (ex. 1.5, 12.25, and 100 line up at the '.'). Non-numeric
+    /// values in the same column are right aligned, and attribute names
+    /// are left aligned. This overrides [SMLWriter::align_columns].
+    pub fn align_decimal(mut self) -> Self {
+        self.options = self.options.align_decimal();
+        self
+    }
+
+    /// Sets where blank lines are inserted between sibling elements. By
+    /// default, siblings are written back to back.
+    pub fn blank_lines_between(mut self, policy: BlankLinePolicy) -> Self {
+        self.options = self.options.blank_lines_between(policy);
+        self
+    }
+
+    /// Inserts a blank line between sibling elements whose depth is at or
+    /// below depth_threshold, where the root's children have a depth of 1.
+    /// This is equivalent to [SMLWriter::blank_lines_between] with
+    /// [BlankLinePolicy::UpToDepth].
+    pub fn blank_line_between_siblings(mut self, depth_threshold: usize) -> Self {
+        self.options = self.options.blank_line_between_siblings(depth_threshold);
+        self
+    }
+
+    /// Writes simple leaves (elements with no children and a single
+    /// attribute with a single value) with their attribute at the same
+    /// indentation as the element instead of one level deeper, and drops
+    /// the trailing column padding from every attribute line. SML still
+    /// needs the element, its attribute, and its end keyword on their own
+    /// lines, so the leaf keeps its three lines.
+    pub fn compact_simple_leaves(mut self) -> Self {
+        self.options = self.options.compact_simple_leaves();
+        self
+    }
+
+    /// Writes the smallest output that keeps one element, attribute, or end
+    /// keyword per line. By default the output is pretty: each level is
+    /// indented by 4 spaces, attribute values are separated by
+    /// [SMLWriter::align_columns], and each end keyword lines up with its
+    /// element. Minified output has no indentation, a single space between
+    /// values, no trailing whitespace, and no blank lines between siblings.
+    /// ```text
+    /// Configuration
+    /// Video
+    /// Resolution 1280 720
+    /// -
+    /// -
+    /// ```
+    /// The end keyword isn't changed, so this composes with
+    /// [SMLWriter::with_end_keyword] in either order. Options set after
+    /// this call still apply.
+    pub fn minified(mut self) -> Self {
+        self.options = self.options.minified();
+        self
+    }
+
+    /// Ends the output with a single line break after the root's end
+    /// keyword (or footer), as many tools expect. By default, the output
+    /// doesn't end with a line break.
+    pub fn trailing_newline(mut self, enabled: bool) -> Self {
+        self.options = self.options.trailing_newline(enabled);
+        self
+    }
+
+    /// Sets the line break written between lines. The default is
+    /// [LineEnding::Lf].
+    pub fn line_ending(mut self, ending: LineEnding) -> Self {
+        self.options = self.options.line_ending(ending);
+        self
+    }
+
+    /// Sets the comments to be emitted alongside the values. See
+    /// [SMLComments] for details on how comments are attached.
+    pub fn with_comments(mut self, comments: SMLComments) -> Self {
+        self.options = self.options.with_comments(comments);
+        self
+    }
+
+    /// Aligns all trailing comments so that their '#' starts at the given
+    /// 1-based column. Lines that are already too long to reach the column
+    /// will get a single space before their comment instead.
+    pub fn comment_column(mut self, col: usize) -> Self {
+        self.options = self.options.comment_column(col);
+        self
+    }
+
+    /// Reproduces the original indentation, spacing, and quoting captured
+    /// by [parse_with_layout]. Lines that weren't part of the original
+    /// document use the configured indentation, and attribute lines whose
+    /// number of values has changed since parsing fall back to the
+    /// configured column alignment.
+    pub fn with_layout(mut self, layout: SMLLayout) -> Self {
+        self.options = self.options.with_layout(layout);
+        self
+    }
+
+    /// Rejects output that isn't ASCII, for legacy consumers. Neither SML nor
+    /// WSV define an escape sequence for non-ASCII characters, so when this
+    /// is set, writing will fail with [SMLWriterError::NonAsciiValue] at the
+    /// first non-ASCII character in any name, value, comment, or keyword.
+    pub fn reject_non_ascii(mut self) -> Self {
+        self.options = self.options.reject_non_ascii();
+        self
+    }
+
+    /// Quotes every attribute name and value, even when quoting isn't
+    /// required, for consumers that expect all values to be quoted. Null
+    /// values are still written as - so that they re-parse as null.
+    /// Element names and the end keyword are unaffected.
+    pub fn always_quote(mut self) -> Self {
+        self.options = self.options.always_quote();
+        self
+    }
+
+    /// Sets whether every attribute value is quoted, even when quoting
+    /// isn't required. Unlike [SMLWriter::always_quote], attribute names
+    /// are only quoted when needed. Null values are still written as -.
+    pub fn always_quote_values(mut self, enabled: bool) -> Self {
+        self.options = self.options.always_quote_values(enabled);
+        self
+    }
+
+    /// Writes the text as comment lines before the root element, such as
+    /// a "Generated by my-tool, do not edit" banner. The text is split on
+    /// line breaks and each line is written as "# " followed by the line.
+    /// Comments are ignored when parsing, so the output still round trips.
+    pub fn header_comment(mut self, text: &str) -> Self {
+        self.options = self.options.header_comment(text);
+        self
+    }
+
+    /// Trims leading and trailing whitespace from every non-null value
+    /// before it's written, using the same definition of whitespace as the
+    /// parser. Values that are empty after trimming are written as "".
+    /// Names aren't trimmed. This is off by default so that values round
+    /// trip exactly.
+    pub fn trim_values(mut self, enabled: bool) -> Self {
+        self.options = self.options.trim_values(enabled);
+        self
+    }
+
+    /// Appends a trailing comment after the root's end keyword with the
+    /// number of elements and attributes in the output, like
+    /// "# generated: 4 elements, 7 attributes". Parsers ignore it.
+    pub fn with_footer_stats(mut self, footer_stats: bool) -> Self {
+        self.options = self.options.with_footer_stats(footer_stats);
+        self
+    }
+
+    /// Limits the size of the output. Writing stops early with
+    /// [SMLWriterError::OutputTooLarge] as soon as the output grows past
+    /// max bytes, so an unexpectedly large tree is never fully written.
+    pub fn max_output_bytes(mut self, max: usize) -> Self {
+        self.options = self.options.max_output_bytes(max);
+        self
+    }
+
+    /// Limits how deeply elements may be nested, where the root has a
+    /// depth of 0. The writer recurses once per level of nesting, so a
+    /// pathologically deep tree can overflow the stack. With a limit set,
+    /// such a tree fails with [SMLWriterError::MaxDepthExceeded] before
+    /// anything is written.
+    pub fn max_depth(mut self, max: usize) -> Self {
+        self.options = self.options.max_depth(max);
+        self
+    }
+
+    /// Sets how attributes with a name but no values are written. SML has
+    /// no way to represent them: the name alone would re-parse as the start
+    /// of a child element, and "Name -" would re-parse as an attribute with
+    /// a single null value. When skip is true they are left out of the
+    /// output. When it is false (the default), writing fails with
+    /// [SMLWriterError::AttributeHasNoValues] so that they are never
+    /// silently changed.
+    pub fn skip_empty_attributes(mut self, skip: bool) -> Self {
+        self.options = self.options.skip_empty_attributes(skip);
+        self
+    }
+
+    /// Checks that the indentation string will survive a round trip for the
+    /// values in this writer. [SMLWriter::indent_with] already requires
+    /// SML whitespace, so this flags characters that SML treats as
+    /// whitespace but many editors and tools treat as line breaks (ex.
+    /// '\r', form feed, or U+2028). Those would re-parse correctly on their
+    /// own, but the document is unlikely to survive being opened and saved
+    /// by other tools. Values are always quoted when they contain
+    /// whitespace, so they can't be confused with the indent, and the only
+    /// part of the tree that matters is whether any indentation is written
+    /// at all: a lone root with no attributes or children accepts any
+    /// indent.
+    pub fn validate_indent_for(&self) -> Result<(), IndentError> {
+        if self.values.value.attributes.is_empty() && self.values.children.is_empty() {
+            return Ok(());
+        }
+
+        for ch in self.options.indent_str.chars() {
+            if matches!(
+                ch,
+                '\u{000B}' | '\u{000C}' | '\u{000D}' | '\u{0085}' | '\u{2028}' | '\u{2029}'
+            ) {
+                return Err(IndentError::LineBreakLike(ch));
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the values in this SMLWriter out to a String. This operation
+    /// can fail if any of the values would result in an SML attribute or
+    /// element where the name is the same as the "End" keyword. If that
+    /// happens, you as the caller will receive an Err() variant of Result.
+    pub fn to_string(self) -> Result<String, SMLWriterError> {
+        Self::write(self.values, &self.options)
+    }
+
+    fn write(
+        mut values: TreeNode<SMLElement<StrAsRef>>,
+        options: &SMLWriterOptions,
+    ) -> Result<String, SMLWriterError> {
+        let options = Self::prepare(&mut values, options)?;
+        let (ops, values) = DocumentCompiler::compile(&values, &options)?;
+        render_ops(&ops, &values, &options)
+    }
+
+    /// Checks the tree's depth and sorts it for [SMLWriter::deterministic],
+    /// returning the options to write it with.
+    fn prepare<'o>(
+        values: &mut TreeNode<SMLElement<StrAsRef>>,
+        options: &'o SMLWriterOptions,
+    ) -> Result<Cow<'o, SMLWriterOptions>, SMLWriterError> {
+        if let Some(max) = options.max_depth {
+            if iter_with_depth(values).any(|(depth, _)| depth > max) {
+                return Err(SMLWriterError::MaxDepthExceeded);
+            }
+        }
+        if !options.deterministic {
+            return Ok(Cow::Borrowed(options));
+        }
+        sort_for_determinism(values);
+        Ok(Cow::Owned(SMLWriterOptions {
+            comments: SMLComments::default(),
+            layout: SMLLayout::default(),
+            ..options.clone()
+        }))
+    }
+}
+
+/// A piece of the output of [SMLWriter]. Everything but the attribute
+/// lines is written ahead of time, since only the attribute lines depend
+/// on the attribute values. This lets [SmlTemplate] write only the
+/// attribute lines again on each render.
+#[derive(Debug, Clone)]
+enum WriteOp {
+    Text(String),
+    /// The attribute lines of one element, which are aligned as a table.
+    Attributes(Vec<AttributeLine>),
+}
+
+#[derive(Debug, Clone)]
+struct AttributeLine {
+    target: CommentTarget,
+    /// Everything written before the attribute's name, starting with
+    /// the line break that ends the previous line.
+    prefix: String,
+    name: String,
+    /// Where the attribute's values are in the list of values.
+    values: Range<usize>,
+    /// Whether the column padding is dropped from the end of the line.
+    trim_end: bool,
+}
+
+/// Splits the output of [SMLWriter] into [WriteOp]s, collecting the
+/// attribute values in the order they're written.
+struct DocumentCompiler<'o, 't> {
+    options: &'o SMLWriterOptions,
+    ops: Vec<WriteOp>,
+    /// The text written since the last op.
+    text: String,
+    /// The length of the text in ops, for failing as soon as the output
+    /// can't fit in max_output_bytes.
+    ops_len: usize,
+    values: Vec<Option<&'t str>>,
+    element_index: usize,
+    path: String,
+}
+
+impl<'o, 't> DocumentCompiler<'o, 't> {
+    #[allow(clippy::type_complexity)]
+    fn compile<StrAsRef>(
+        tree: &'t TreeNode<SMLElement<StrAsRef>>,
+        options: &'o SMLWriterOptions,
+    ) -> Result<(Vec<WriteOp>, Vec<Option<&'t str>>), SMLWriterError>
+    where
+        StrAsRef: AsRef<str>,
+    {
+        let footer = if options.footer_stats {
+            let mut elements = 0;
+            let mut attributes = 0;
+            for element in tree.dfs_preorder_iter() {
+                elements += 1;
+                // empty attributes are either skipped or an error.
+                attributes += element
+                    .attributes
+                    .iter()
+                    .filter(|attr| !attr.values.is_empty())
+                    .count();
+            }
+            Some(format!(
+                "# generated: {} element{}, {} attribute{}",
+                elements,
+                if elements == 1 { "" } else { "s" },
+                attributes,
+                if attributes == 1 { "" } else { "s" }
+            ))
+        } else {
+            None
+        };
+
+        let mut compiler = DocumentCompiler {
+            options,
+            ops: Vec::new(),
+            text: String::new(),
+            ops_len: 0,
+            values: Vec::new(),
+            element_index: 0,
+            path: String::new(),
+        };
+        if let Some(header) = options.header_comment.as_ref() {
+            for line in header.split('\n') {
+                let line = line.strip_suffix('\r').unwrap_or(line);
+                compiler.text.push('#');
+                if !line.is_empty() {
+                    compiler.text.push(' ');
+                    compiler.text.push_str(line);
+                }
+                options.push_newline(&mut compiler.text);
+            }
+            compiler.check_output_size()?;
+        }
+        compiler.element(tree, 0)?;
+        if let Some(footer) = footer {
+            options.push_newline(&mut compiler.text);
+            compiler.text.push_str(&footer);
+        }
+        if options.trailing_newline {
+            options.push_newline(&mut compiler.text);
+        }
+        compiler.check_output_size()?;
+        compiler.flush_text();
+        Ok((compiler.ops, compiler.values))
+    }
+
+    /// Fails once the text written so far is past max_output_bytes. The
+    /// attribute lines aren't counted, so the output may still turn out
+    /// to be too large once they are written.
+    fn check_output_size(&self) -> Result<(), SMLWriterError> {
+        self.options
+            .check_output_size(self.ops_len + self.text.len())
+    }
+
+    fn flush_text(&mut self) {
+        if !self.text.is_empty() {
+            self.ops_len += self.text.len();
+            self.ops.push(WriteOp::Text(std::mem::take(&mut self.text)));
+        }
+    }
+
+    fn element<StrAsRef>(
+        &mut self,
+        tree: &'t TreeNode<SMLElement<StrAsRef>>,
+        depth: usize,
+    ) -> Result<(), SMLWriterError>
+    where
+        StrAsRef: AsRef<str>,
+    {
+        let options = self.options;
+        let indent_str = options.indent_str.as_str();
+        let end_keyword = options.end_keyword.as_ref();
+        let current_index = self.element_index;
+        self.element_index += 1;
+
+        let (value, children) = (&tree.value, &tree.children);
+        if let Some(end_keyword) = end_keyword {
+            if value.name.as_ref() == end_keyword {
+                return Err(SMLWriterError::ElementHasEndKeywordName);
+            }
+        }
+
+        let buf = &mut self.text;
+        options.push_leading_comments(CommentTarget::Element(current_index), depth, buf);
+        options.push_indent(CommentTarget::Element(current_index), depth, buf);
+        // Names are escaped like values so that names containing line
+        // breaks, whitespace, or quotes still re-parse.
+        buf.push_str(&encode_wsv_value(Some(value.name.as_ref()), false));
+        options.push_trailing_comment(CommentTarget::Element(current_index), buf);
+        self.check_output_size()?;
+
+        let parent_path_len = self.path.len();
+        if !self.path.is_empty() {
+            self.path.push('/');
+        }
+        self.path.push_str(value.name.as_ref());
+
+        if let Some(end_keyword) = end_keyword {
+            for attribute in value.attributes.iter() {
+                if attribute.name.as_ref() == end_keyword {
+                    return Err(SMLWriterError::AttributeHasEndKeywordName);
+                }
+            }
+        }
+
+        let mut attributes = Vec::with_capacity(value.attributes.len());
+        for (i, attribute) in value.attributes.iter().enumerate() {
+            if !attribute.values.is_empty() {
+                attributes.push((i, attribute));
+            } else if !options.skip_empty_attributes {
+                return Err(SMLWriterError::AttributeHasNoValues);
+            }
+        }
+
+        let compact_leaf = options.compact_simple_leaves
+            && children.is_empty()
+            && attributes.len() == 1
+            && attributes[0].1.values.len() == 1;
+        let attribute_depth = if compact_leaf { depth } else { depth + 1 };
+        if !attributes.is_empty() {
+            let mut lines = Vec::with_capacity(attributes.len());
+            for (i, attribute) in attributes {
+                let target = CommentTarget::Attribute {
+                    element: current_index,
+                    attribute: i,
+                };
+                let mut prefix = String::new();
+                if let Some(label) = options.comments.section(&self.path, i) {
+                    options.push_newline(&mut prefix);
+                    for _ in 0..attribute_depth {
+                        prefix.push_str(indent_str);
+                    }
+                    prefix.push_str("# --- ");
+                    for ch in label.chars() {
+                        prefix.push(if ch == '\n' { ' ' } else { ch });
+                    }
+                    prefix.push_str(" ---");
+                }
+                options.push_newline(&mut prefix);
+                options.push_leading_comments(target, attribute_depth, &mut prefix);
+                options.push_indent(target, attribute_depth, &mut prefix);
+
+                let first_value = self.values.len();
+                self.values.extend(
+                    attribute
+                        .values
+                        .iter()
+                        .map(|value| value.as_ref().map(|value| value.as_ref())),
+                );
+                lines.push(AttributeLine {
+                    target,
+                    prefix,
+                    name: attribute.name.as_ref().to_string(),
+                    values: first_value..self.values.len(),
+                    trim_end: options.compact_simple_leaves || options.minified,
+                });
+            }
+            self.flush_text();
+            self.ops.push(WriteOp::Attributes(lines));
+        }
+
+        let blank_lines = match options.blank_lines_between {
+            BlankLinePolicy::None => false,
+            BlankLinePolicy::AllLevels => true,
+            BlankLinePolicy::TopLevelOnly => depth == 0,
+            BlankLinePolicy::UpToDepth(max_depth) => depth < max_depth,
+        };
+        for (i, child) in children.iter().enumerate() {
+            options.push_newline(&mut self.text);
+            if blank_lines && i != 0 {
+                options.push_newline(&mut self.text);
+            }
+            self.element(child, depth + 1)?;
+        }
+        self.path.truncate(parent_path_len);
+
+        let buf = &mut self.text;
+        options.push_newline(buf);
+        for comment in options.comments.before_end(current_index) {
+            for _ in 0..depth + 1 {
+                buf.push_str(indent_str);
+            }
+            push_line_comment(comment.text(), buf);
+            options.push_newline(buf);
+        }
+        match options.layout.end_indent(current_index) {
+            Some(indent) => buf.push_str(indent),
+            None => {
+                for _ in 0..depth {
+                    buf.push_str(indent_str);
+                }
+            }
+        }
+        match end_keyword {
+            None => buf.push('-'),
+            Some(end) => buf.push_str(end),
+        }
+        self.check_output_size()
+    }
+}
+
+/// Writes the ops out with the given attribute values, in the order
+/// they were collected by [DocumentCompiler].
+fn render_ops<Value>(
+    ops: &[WriteOp],
+    values: &[Option<Value>],
+    options: &SMLWriterOptions,
+) -> Result<String, SMLWriterError>
+where
+    Value: AsRef<str>,
+{
+    let mut buf = String::new();
+    for op in ops {
+        let lines = match op {
+            WriteOp::Text(text) => {
+                buf.push_str(text);
+                options.check_output_size(buf.len())?;
+                continue;
+            }
+            WriteOp::Attributes(lines) => lines,
+        };
+
+        let rows = lines
+            .iter()
+            .map(|line| {
+                std::iter::once(Some(line.name.as_str()))
+                    .chain(values[line.values.clone()].iter().map(|value| {
+                        value.as_ref().map(|v| {
+                            if options.trim_values {
+                                v.as_ref().trim_matches(is_whitespace)
+                            } else {
+                                v.as_ref()
+                            }
+                        })
+                    }))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let attributes_text = write_wsv_table(&rows, options);
+
+        for ((line, row), text) in lines
+            .iter()
+            .zip(rows.iter())
+            .zip(attributes_text.split('\n'))
+        {
+            buf.push_str(&line.prefix);
+            match options.layout.separators(line.target) {
+                Some(separators) if separators.len() + 1 == row.len() => {
+                    let quoted = options.layout.quoted(line.target).unwrap_or(&[]);
+                    for (j, value) in row.iter().enumerate() {
+                        if j != 0 {
+                            buf.push_str(&separators[j - 1]);
+                        }
+                        let always_quote = options.always_quote
+                            || (j != 0 && options.always_quote_values)
+                            || quoted.get(j).copied().unwrap_or(false);
+                        buf.push_str(&encode_wsv_value(*value, always_quote));
+                    }
+                }
+                // Column padding is the only trailing whitespace a line can have.
+                _ if line.trim_end => buf.push_str(text.trim_end_matches(' ')),
+                _ => buf.push_str(text),
+            }
+            options.push_trailing_comment(line.target, &mut buf);
+            options.check_output_size(buf.len())?;
+        }
+    }
+
+    if options.reject_non_ascii {
+        if let Some(position) = buf.find(|ch: char| !ch.is_ascii()) {
+            return Err(SMLWriterError::NonAsciiValue { position });
+        }
+    }
+    Ok(buf)
+}
+
+/// Sorts the attributes and children of every element by name for
+/// [SMLWriter::deterministic]. Ties are broken by contents so that the
+/// result doesn't depend on the original order.
+fn sort_for_determinism<StrAsRef>(tree: &mut TreeNode<SMLElement<StrAsRef>>)
+where
+    StrAsRef: AsRef<str>,
+{
+    tree.value.attributes.sort_by(compare_attributes);
+    for child in tree.children.iter_mut() {
+        sort_for_determinism(child);
+    }
+    // Children are already sorted, so comparing them in order compares
+    // their contents.
+    tree.children.sort_by(compare_elements);
+}
+
+fn compare_elements<StrAsRef>(
+    a: &TreeNode<SMLElement<StrAsRef>>,
+    b: &TreeNode<SMLElement<StrAsRef>>,
+) -> std::cmp::Ordering
+where
+    StrAsRef: AsRef<str>,
+{
+    a.value
+        .name
+        .as_ref()
+        .cmp(b.value.name.as_ref())
+        .then_with(|| {
+            compare_in_order(&a.value.attributes, &b.value.attributes, compare_attributes)
+        })
+        .then_with(|| compare_in_order(&a.children, &b.children, compare_elements))
+}
+
+fn compare_attributes<StrAsRef>(
+    a: &SMLAttribute<StrAsRef>,
+    b: &SMLAttribute<StrAsRef>,
+) -> std::cmp::Ordering
+where
+    StrAsRef: AsRef<str>,
+{
+    a.name.as_ref().cmp(b.name.as_ref()).then_with(|| {
+        let a_values = a.values.iter().map(|val| val.as_ref().map(|v| v.as_ref()));
+        let b_values = b.values.iter().map(|val| val.as_ref().map(|v| v.as_ref()));
+        a_values.cmp(b_values)
+    })
+}
+
+/// Compares the lists item by item, then by length.
+fn compare_in_order<T>(
+    a: &[T],
+    b: &[T],
+    compare: impl Fn(&T, &T) -> std::cmp::Ordering,
+) -> std::cmp::Ordering {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| compare(a, b))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or_else(|| a.len().cmp(&b.len()))
+}
+
+/// The line break that [SMLWriter::line_ending] writes between lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEnding {
+    /// "\n", as used on Unix-like systems.
+    #[default]
+    Lf,
+    /// "\r\n", as used on Windows.
+    CrLf,
+}
+
+/// Where [SMLWriter::blank_lines_between] inserts blank lines between
+/// sibling elements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlankLinePolicy {
+    /// No blank lines are inserted.
+    #[default]
+    None,
+    /// A blank line is inserted between siblings at every depth.
+    AllLevels,
+    /// A blank line is only inserted between the children of the root.
+    TopLevelOnly,
+    /// A blank line is inserted between siblings whose depth is at or
+    /// below the given depth, where the root's children have a depth of 1.
+    UpToDepth(usize),
+}
+
+/// Serializes the tree with the given options and checks whether the
+/// result is byte-for-byte identical to existing. This is useful for
+/// skipping writes that wouldn't change a file on disk.
+pub fn serializes_same<StrAsRef>(
+    tree: &TreeNode<SMLElement<StrAsRef>>,
+    existing: &str,
+    options: &SMLWriterOptions,
+) -> Result<bool, SMLWriterError>
+where
+    StrAsRef: AsRef<str>,
+{
+    Ok(SMLWriter::write(borrow_tree(tree), options)? == existing)
+}
+
+/// An [SMLWriter] that has done all of its work except writing the
+/// attribute values, for documents that are written many times with the
+/// same structure and options (ex. a config file rendered from a
+/// template). Element names, comments, indentation, and end keywords are
+/// written once when the template is built, and each render only formats
+/// the attribute lines, with either the original values or new ones.
+#[derive(Debug, Clone)]
+pub struct SmlTemplate {
+    options: SMLWriterOptions,
+    ops: Vec<WriteOp>,
+    values: Vec<Option<String>>,
+}
+
+impl SmlTemplate {
+    /// Builds a template from the given writer. This fails under the same
+    /// conditions as [SMLWriter::to_string], except for
+    /// [SMLWriterError::OutputTooLarge] and [SMLWriterError::NonAsciiValue],
+    /// which can depend on the values and are checked on each render.
+    pub fn new<StrAsRef>(writer: SMLWriter<StrAsRef>) -> Result<Self, SMLWriterError>
+    where
+        StrAsRef: AsRef<str>,
+    {
+        let SMLWriter {
+            mut values,
+            options,
+        } = writer;
+        let options = SMLWriter::prepare(&mut values, &options)?.into_owned();
+        let (ops, template_values) = DocumentCompiler::compile(&values, &options)?;
+        let template_values = template_values
+            .into_iter()
+            .map(|value| value.map(str::to_string))
+            .collect();
+        Ok(Self {
+            options,
+            ops,
+            values: template_values,
+        })
+    }
+
+    /// Gets the number of attribute values in the document, which is the
+    /// number of values [SmlTemplate::render_with] takes.
+    pub fn value_count(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Writes the document with its original values. This is the same
+    /// output as [SMLWriter::to_string].
+    pub fn render(&self) -> Result<String, SMLWriterError> {
+        render_ops(&self.ops, &self.values, &self.options)
+    }
+
+    /// Writes the document with its attribute values replaced by values,
+    /// in the order they're written (attribute by attribute, in preorder).
+    /// Empty attributes left out by [SMLWriter::skip_empty_attributes]
+    /// have no values. Columns are aligned to the new values. Fails with
+    /// [SMLWriterError::TemplateValueCount] if values doesn't have exactly
+    /// [SmlTemplate::value_count] values.
+    pub fn render_with(&self, values: &[Option<&str>]) -> Result<String, SMLWriterError> {
+        if values.len() != self.values.len() {
+            return Err(SMLWriterError::TemplateValueCount {
+                expected: self.values.len(),
+                found: values.len(),
+            });
+        }
+        render_ops(&self.ops, values, &self.options)
+    }
+}
+
+impl SMLWriterOptions {
+    /// Writes a line break using the configured line ending.
+    fn push_newline(&self, buf: &mut String) {
+        match self.line_ending {
+            LineEnding::Lf => buf.push('\n'),
+            LineEnding::CrLf => buf.push_str("\r\n"),
+        }
+    }
+
+    /// Writes the indentation for the target's line, preferring the
+    /// original indentation from the layout if there is one.
+    fn push_indent(&self, target: CommentTarget, depth: usize, buf: &mut String) {
+        match self.layout.indent(target) {
+            Some(indent) => buf.push_str(indent),
+            None => {
+                for _ in 0..depth {
+                    buf.push_str(&self.indent_str);
+                }
+            }
+        }
+    }
+
+    /// Fails once the output has grown past max_output_bytes.
+    fn check_output_size(&self, len: usize) -> Result<(), SMLWriterError> {
+        match self.max_output_bytes {
+            Some(max) if len > max => Err(SMLWriterError::OutputTooLarge),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes the target's leading comments, each on its own line at the
+    /// same indentation as the target. buf must be at the start of a line.
+    fn push_leading_comments(&self, target: CommentTarget, depth: usize, buf: &mut String) {
+        for comment in self.comments.leading(target) {
+            self.push_indent(target, depth, buf);
+            push_line_comment(comment.text(), buf);
+            self.push_newline(buf);
+        }
+    }
+
+    /// Appends the trailing comment for target (if there is one) to the
+    /// end of the line currently being written in buf.
+    fn push_trailing_comment(&self, target: CommentTarget, buf: &mut String) {
+        let comment = match self.comments.trailing(target) {
+            None => return,
+            Some(comment) => comment,
+        };
+
+        // Any trailing whitespace on the line is only padding, so
+        // drop it and pad out to the comment column ourselves.
+        while buf.ends_with(' ') {
+            buf.pop();
+        }
+
+        let line_len = match buf.rfind('\n') {
+            None => buf.chars().count(),
+            Some(newline) => buf[newline + 1..].chars().count(),
+        };
+        let padding = match self.comment_column {
+            Some(col) if line_len + 1 < col => col - 1 - line_len,
+            _ => 1,
+        };
+        for _ in 0..padding {
+            buf.push(' ');
+        }
+
+        push_line_comment(comment, buf);
+    }
+}
+
+/// Writes '#' followed by the comment's text. Comments can't span lines,
+/// so any line breaks are flattened into spaces.
+fn push_line_comment(comment: &str, buf: &mut String) {
+    buf.push('#');
+    for ch in comment.chars() {
+        buf.push(if ch == '\n' { ' ' } else { ch });
+    }
+}
+
+/// A set of comments to be attached to a tree of SMLElements. Since the
+/// elements themselves have no place to store comments, comments are
+/// attached by their position in the tree instead. See [CommentTarget]
+/// for details.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SMLComments {
+    trailing: BTreeMap<CommentTarget, String>,
+    leading: BTreeMap<CommentTarget, Vec<LineComment>>,
+    before_end: BTreeMap<usize, Vec<LineComment>>,
+    sections: BTreeMap<String, BTreeMap<usize, String>>,
+}
+
+impl SMLComments {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the comment to be written at the end of the target's line.
+    /// The text should not include the leading '#'.
+    pub fn set_trailing(&mut self, target: CommentTarget, text: &str) {
+        self.trailing.insert(target, text.to_string());
+    }
+
+    /// Gets the comment to be written at the end of the target's line.
+    pub fn trailing(&self, target: CommentTarget) -> Option<&str> {
+        self.trailing.get(&target).map(|comment| comment.as_str())
+    }
+
+    /// Adds a comment to be written on its own line above the target's
+    /// line. The text should not include the leading '#'.
+    pub fn push_leading(&mut self, target: CommentTarget, text: &str) {
+        self.leading.entry(target).or_default().push(LineComment {
+            text: text.to_string(),
+            line_num: None,
+        });
+    }
+
+    /// Gets the comments to be written on their own lines above the
+    /// target's line, in order.
+    pub fn leading(&self, target: CommentTarget) -> &[LineComment] {
+        self.leading
+            .get(&target)
+            .map(|comments| comments.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Adds a comment to be written on its own line just before the end
+    /// keyword of the element with the given preorder index. The text
+    /// should not include the leading '#'.
+    pub fn push_before_end(&mut self, element: usize, text: &str) {
+        self.before_end
+            .entry(element)
+            .or_default()
+            .push(LineComment {
+                text: text.to_string(),
+                line_num: None,
+            });
+    }
+
+    /// Gets the comments to be written just before the end keyword of the
+    /// element with the given preorder index, in order.
+    pub fn before_end(&self, element: usize) -> &[LineComment] {
+        self.before_end
+            .get(&element)
+            .map(|comments| comments.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Starts a labeled section before the attribute at index
+    /// first_attribute of every element at the given slash-separated path
+    /// (ex. "Configuration/Video"). The section is written as a
+    /// "# --- label ---" comment line above that attribute.
+    pub fn set_section(&mut self, path: &str, first_attribute: usize, label: &str) {
+        self.sections
+            .entry(path.to_string())
+            .or_default()
+            .insert(first_attribute, label.to_string());
+    }
+
+    /// Gets the label of the section starting at the given attribute of
+    /// the elements at path.
+    pub fn section(&self, path: &str, first_attribute: usize) -> Option<&str> {
+        self.sections
+            .get(path)
+            .and_then(|sections| sections.get(&first_attribute))
+            .map(|label| label.as_str())
+    }
+}
+
+/// A comment that takes up a whole line. See [SMLComments::leading].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineComment {
+    text: String,
+    line_num: Option<usize>,
+}
+
+impl LineComment {
+    /// The comment's text, without the leading '#'.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+    /// The 0-based line number the comment was found on by
+    /// [parse_with_comments]. Comments added by hand have no line number.
+    pub fn line_num(&self) -> Option<usize> {
+        self.line_num
+    }
+}
+
+/// Identifies the element or attribute that a comment is attached to.
+/// Elements are identified by their index in a preorder traversal of
+/// the tree, so the root is always Element(0).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CommentTarget {
+    /// The element with the given preorder index.
+    Element(usize),
+    /// The attribute at index attribute of the element with the given
+    /// preorder index.
+    Attribute { element: usize, attribute: usize },
+}
+
+/// Writes a table of values in WSV format. This matches the output of
+/// whitespacesv's WSVWriter, except that values which would otherwise be
+/// read back differently are quoted as well. That is, the empty string is
+/// written as "" instead of nothing, and a "-" value is written as "-" so
+/// that it isn't confused with a null. Null values are always written as -.
+/// Values are quoted and aligned according to the writer's options.
+fn write_wsv_table(rows: &[Vec<Option<&str>>], options: &SMLWriterOptions) -> String {
+    let encoded = rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .enumerate()
+                .map(|(i, value)| {
+                    let always_quote =
+                        options.always_quote || (i != 0 && options.always_quote_values);
+                    encode_wsv_value(*value, always_quote)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    if options.align_decimal {
+        return write_decimal_table(&encoded);
+    }
+
+    let mut max_col_widths = Vec::new();
+    for row in encoded.iter() {
+        for (i, value) in row.iter().enumerate() {
+            let width = value.chars().count();
+            match max_col_widths.get_mut(i) {
+                None => max_col_widths.push(width),
+                Some(max_width) => *max_width = width.max(*max_width),
+            }
+        }
+    }
+
+    let mut result = String::new();
+    for (line_num, row) in encoded.iter().enumerate() {
+        if line_num != 0 {
+            result.push('\n');
+        }
+
+        for (i, value) in row.iter().enumerate() {
+            let padding = max_col_widths[i] - value.chars().count();
+            match &options.column_alignment.0 {
+                ColumnAlignment::Packed => {
+                    result.push_str(value);
+                    result.push(' ');
+                }
+                ColumnAlignment::Left => {
+                    if i != 0 {
+                        result.push(' ');
+                    }
+                    result.push_str(value);
+                    for _ in 0..padding {
+                        result.push(' ');
+                    }
+                }
+                ColumnAlignment::Right => {
+                    if i != 0 {
+                        result.push(' ');
+                    }
+                    for _ in 0..padding {
+                        result.push(' ');
+                    }
+                    result.push_str(value);
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Writes a table of already encoded values with the names left aligned
+/// and the numeric values of each column aligned on their decimal points.
+/// Non-numeric values are right aligned within the column.
+fn write_decimal_table(encoded: &[Vec<Cow<'_, str>>]) -> String {
+    // (widest integer part, widest fraction part including the '.', widest
+    // non-numeric value)
+    let mut col_widths: Vec<(usize, usize, usize)> = Vec::new();
+    for row in encoded.iter() {
+        for (i, value) in row.iter().enumerate() {
+            if col_widths.len() <= i {
+                col_widths.push((0, 0, 0));
+            }
+            let widths = &mut col_widths[i];
+            match split_decimal(value) {
+                Some((int, frac)) if i != 0 => {
+                    widths.0 = widths.0.max(int.len());
+                    widths.1 = widths.1.max(frac.len());
+                }
+                _ => widths.2 = widths.2.max(value.chars().count()),
+            }
+        }
+    }
+
+    let mut result = String::new();
+    for (line_num, row) in encoded.iter().enumerate() {
+        if line_num != 0 {
+            result.push('\n');
+        }
+
+        for (i, value) in row.iter().enumerate() {
+            let (max_int, max_frac, max_other) = col_widths[i];
+            let numeric_width = max_int + max_frac;
+            let col_width = numeric_width.max(max_other);
+            if i != 0 {
+                result.push(' ');
+            }
+
+            match split_decimal(value) {
+                Some((int, frac)) if i != 0 => {
+                    let left = col_width - numeric_width + max_int - int.len();
+                    result.extend(std::iter::repeat_n(' ', left));
+                    result.push_str(value);
+                    result.extend(std::iter::repeat_n(' ', max_frac - frac.len()));
+                }
+                _ => {
+                    let padding = col_width - value.chars().count();
+                    if i == 0 {
+                        result.push_str(value);
+                        result.extend(std::iter::repeat_n(' ', padding));
+                    } else {
+                        result.extend(std::iter::repeat_n(' ', padding));
+                        result.push_str(value);
+                    }
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Splits a numeric-looking value (an optional sign, digits, and an
+/// optional fraction) into its integer part and its fraction part
+/// (including the '.'). Returns None for anything else.
+fn split_decimal(value: &str) -> Option<(&str, &str)> {
+    let digits = value.strip_prefix(['-', '+']).unwrap_or(value);
+    let (int, frac) = match digits.find('.') {
+        None => (digits, ""),
+        Some(dot) => (&digits[..dot], &digits[dot + 1..]),
+    };
+    let is_numeric = !(int.is_empty() && frac.is_empty())
+        && int.chars().all(|ch| ch.is_ascii_digit())
+        && frac.chars().all(|ch| ch.is_ascii_digit())
+        && !digits.ends_with('.');
+    if !is_numeric {
+        return None;
+    }
+
+    let split = value.len() - digits.len() + int.len();
+    Some(value.split_at(split))
+}
+
+/// Encodes a single value for WSV output, adding quotes and escape
+/// sequences as needed. If always_quote is set, non-null values are
+/// quoted even when they don't need to be.
+fn encode_wsv_value(value: Option<&str>, always_quote: bool) -> Cow<'_, str> {
+    let value = match value {
+        None => return Cow::Borrowed("-"),
+        Some(value) => value,
+    };
+
+    let needs_quotes = always_quote
+        || value.is_empty()
+        || value == "-"
+        || value
+            .chars()
+            .any(|ch| ch == '"' || ch == '#' || ch == '\n' || is_whitespace(ch));
+    if !needs_quotes {
+        return Cow::Borrowed(value);
+    }
+
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => result.push_str("\"\""),
+            '\n' => result.push_str("\"/\""),
+            ch => result.push(ch),
+        }
+    }
+    result.push('"');
+    Cow::Owned(result)
+}
+
+const fn is_whitespace(ch: char) -> bool {
+    matches!(
+        ch,
+        '\u{0009}'
+            | '\u{000B}'
+            | '\u{000C}'
+            | '\u{000D}'
+            | '\u{0020}'
+            | '\u{0085}'
+            | '\u{00A0}'
+            | '\u{1680}'
+            | '\u{2000}'
+            | '\u{2001}'
+            | '\u{2002}'
+            | '\u{2003}'
+            | '\u{2004}'
+            | '\u{2005}'
+            | '\u{2006}'
+            | '\u{2007}'
+            | '\u{2008}'
+            | '\u{2009}'
+            | '\u{200A}'
+            | '\u{2028}'
+            | '\u{2029}'
+            | '\u{202F}'
+            | '\u{205F}'
+            | '\u{3000}'
+    )
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SMLWriterError {
+    ElementHasEndKeywordName,
+    AttributeHasEndKeywordName,
+    /// An attribute had no values. See [SMLWriter::skip_empty_attributes].
+    AttributeHasNoValues,
+    /// The output grew past [SMLWriter::max_output_bytes].
+    OutputTooLarge,
+    /// Elements were nested deeper than [SMLWriter::max_depth].
+    MaxDepthExceeded,
+    /// A non-ASCII character was found while writing with
+    /// [SMLWriter::reject_non_ascii] set. The position is the
+    /// byte offset in the output where it would have been written.
+    NonAsciiValue {
+        position: usize,
+    },
+    /// [SmlTemplate::render_with] was given the wrong number of values.
+    TemplateValueCount {
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl Error for SMLWriterError {}
+impl Display for SMLWriterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SMLWriterError::AttributeHasEndKeywordName => {
+                write!(f, "Attribute Has End Keyword Name")?
+            }
+            SMLWriterError::ElementHasEndKeywordName => write!(f, "Element Has End Keyword Name")?,
+            SMLWriterError::AttributeHasNoValues => write!(f, "Attribute Has No Values")?,
+            SMLWriterError::OutputTooLarge => write!(f, "Output Too Large")?,
+            SMLWriterError::MaxDepthExceeded => write!(f, "Max Depth Exceeded")?,
+            SMLWriterError::NonAsciiValue { position } => {
+                write!(f, "(byte: {}) Non-ASCII Value", position)?
+            }
+            SMLWriterError::TemplateValueCount { expected, found } => {
+                write!(f, "Template Expected {} Values, Found {}", expected, found)?
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ReformatError {
+    Parse(ParseError),
+    Write(SMLWriterError),
+}
+
+impl Error for ReformatError {}
+impl Display for ReformatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReformatError::Parse(err) => err.fmt(f)?,
+            ReformatError::Write(err) => err.fmt(f)?,
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndentError {
+    /// The indent contains a character that SML treats as whitespace,
+    /// but that many editors and tools treat as a line break.
+    LineBreakLike(char),
+}
+
+impl Error for IndentError {}
+impl Display for IndentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IndentError::LineBreakLike(ch) => {
+                write!(f, "Indent Contains Line Break Character {:?}", ch)?
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ParseError {
+    WSV(WSVError),
+    SML(SMLError),
+    /// The source text couldn't be read. Only returned by
+    /// [parse_reader].
+    IO(Arc<std::io::Error>),
+}
+
+impl ParseError {
+    /// Moves the error's location by the given number of lines and bytes,
+    /// stopping at the start of the text.
+    fn shift_location(self, lines: isize, bytes: isize) -> Self {
+        match self {
+            ParseError::SML(mut err) => {
+                err.line_num = err.line_num.saturating_add_signed(lines);
+                err.byte_offset = err.byte_offset.saturating_add_signed(bytes);
+                ParseError::SML(err)
+            }
+            err => err,
+        }
+    }
+
+    /// Whether both errors have the same error type, ignoring location.
+    fn is_same_kind(&self, other: &ParseError) -> bool {
+        match (self, other) {
+            (ParseError::SML(a), ParseError::SML(b)) => a.err_type() == b.err_type(),
+            (ParseError::WSV(a), ParseError::WSV(b)) => a.err_type() == b.err_type(),
+            (ParseError::IO(a), ParseError::IO(b)) => a.kind() == b.kind(),
+            _ => false,
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::WSV(err) => Some(err),
+            ParseError::IO(err) => Some(err.as_ref()),
+            ParseError::SML(_) => None,
+        }
+    }
+}
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::SML(err) => err.fmt(f)?,
+            ParseError::WSV(err) => err.fmt(f)?,
+            ParseError::IO(err) => err.fmt(f)?,
+        }
+        Ok(())
+    }
+}
+
+/// A problem found by [parse_with_warnings] that doesn't prevent the
+/// document from being parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseWarning {
+    warning_type: ParseWarningType,
+    line_num: usize,
+}
+
+impl ParseWarning {
+    pub fn warning_type(&self) -> ParseWarningType {
+        self.warning_type
+    }
+    pub fn line_num(&self) -> usize {
+        self.line_num
+    }
+}
+
+impl Display for ParseWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "(line: {}) ", self.line_num)?;
+        match self.warning_type {
+            ParseWarningType::AmbiguousEndKeyword => write!(f, "Ambiguous End Keyword")?,
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseWarningType {
+    /// An attribute line contains the detected end keyword as a name or
+    /// value. See [ParseOptions::warn_on_keyword_ambiguity].
+    AmbiguousEndKeyword,
+}
+
+#[derive(Debug, Clone)]
+pub struct SMLError {
+    err_type: SMLErrorType,
+    line_num: usize,
+    column: usize,
+    byte_offset: usize,
+}
+
+impl SMLError {
+    pub fn err_type(&self) -> SMLErrorType {
+        self.err_type
+    }
+    pub fn line_num(&self) -> usize {
+        self.line_num
+    }
+    /// The 1-based column of the offending value on the line. Tabs are
+    /// expanded according to [ParseOptions::tab_width].
+    pub fn column(&self) -> usize {
+        self.column
+    }
+    /// The offset of the offending value in bytes from the start of the
+    /// source text.
+    pub fn byte_offset(&self) -> usize {
+        self.byte_offset
+    }
+}
+
+impl Error for SMLError {}
+impl Display for SMLError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut result = String::new();
+        result.push_str("(line: ");
+        result.push_str(&self.line_num().to_string());
+        result.push_str(") ");
+        match self.err_type() {
+            SMLErrorType::EndKeywordNotDetected => {
+                result.push_str("End Keyword Not Detected");
+            }
+            SMLErrorType::InvalidRootElementStart => {
+                result.push_str("Invalid Root Element Start");
+            }
+            SMLErrorType::NullValueAsAttributeName => {
+                result.push_str("Null Value as Attribute Name");
+            }
+            SMLErrorType::NullValueAsElementName => {
+                result.push_str("Null Value as Element Name");
+            }
+            SMLErrorType::OnlyOneRootElementAllowed => {
+                result.push_str("Only One Root Element Allowed");
+            }
+            SMLErrorType::RootNotClosed => {
+                result.push_str("Root Not Closed");
+            }
+            SMLErrorType::TooManyAttributes => {
+                result.push_str("Too Many Attributes");
+            }
+            SMLErrorType::ValueNeedsUnescaping => {
+                result.push_str("Value Needs Unescaping");
+            }
+            SMLErrorType::MaxDepthExceeded => {
+                result.push_str("Max Depth Exceeded");
+            }
+            SMLErrorType::MaxElementsExceeded => {
+                result.push_str("Max Elements Exceeded");
+            }
+        }
+        write!(f, "{}", result)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SMLErrorType {
+    /// This error can happen for 2 reasons:
+    /// 1. the file was empty or only contained comments
+    /// 2. the file is invalid SML.
+    EndKeywordNotDetected,
+    InvalidRootElementStart,
+    NullValueAsElementName,
+    NullValueAsAttributeName,
+    RootNotClosed,
+    OnlyOneRootElementAllowed,
+    /// An element had more attributes than
+    /// [ParseOptions::max_attributes_per_element] allows.
+    TooManyAttributes,
+    /// A value contained escape sequences, so it couldn't be borrowed by
+    /// [parse_borrowed].
+    ValueNeedsUnescaping,
+    /// An element was nested deeper than [ParseLimits::max_depth] allows.
+    MaxDepthExceeded,
+    /// The document had more elements than [ParseLimits::max_elements]
+    /// allows.
+    MaxElementsExceeded,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SMLElement<StrAsRef>
+where
+    StrAsRef: AsRef<str>,
+{
+    pub name: StrAsRef,
+    pub attributes: Vec<SMLAttribute<StrAsRef>>,
+}
+
+/// A tree of SMLElements that can be serialized with serde, since
+/// [TreeNode] can't be. Each element is written as an object with its
+/// "name", "attributes" (each with a "name" and "values"), and "children".
+/// Null values are written as null. This is the same shape [to_json]
+/// produces. Only available with the "serde" feature.
+#[cfg(feature = "serde")]
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct SerializableTree<StrAsRef>
+where
+    StrAsRef: AsRef<str>,
+{
+    #[serde(flatten)]
+    pub element: SMLElement<StrAsRef>,
+    pub children: Vec<SerializableTree<StrAsRef>>,
+}
+
+#[cfg(feature = "serde")]
+impl<StrAsRef> From<TreeNode<SMLElement<StrAsRef>>> for SerializableTree<StrAsRef>
+where
+    StrAsRef: AsRef<str>,
+{
+    fn from(tree: TreeNode<SMLElement<StrAsRef>>) -> Self {
+        Self {
+            element: tree.value,
+            children: tree.children.into_iter().map(Self::from).collect(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<StrAsRef> From<SerializableTree<StrAsRef>> for TreeNode<SMLElement<StrAsRef>>
+where
+    StrAsRef: AsRef<str>,
+{
+    fn from(tree: SerializableTree<StrAsRef>) -> Self {
+        TreeNode {
+            value: tree.element,
+            children: tree.children.into_iter().map(TreeNode::from).collect(),
+        }
+    }
+}
+
+impl<StrAsRef> SMLElement<StrAsRef>
+where
+    StrAsRef: AsRef<str>,
+{
+    /// Gets the first attribute with the given name. Names are compared
+    /// case-sensitively.
+    pub fn get_attribute(&self, name: &str) -> Option<&SMLAttribute<StrAsRef>> {
+        self.attributes
+            .iter()
+            .find(|attribute| attribute.name.as_ref() == name)
+    }
+
+    /// Equivalent to [SMLElement::get_attribute], but compares names
+    /// case-insensitively. Both names are lowercased with
+    /// [str::to_lowercase] before comparing, which is the same Unicode
+    /// case folding [parse] uses when matching element names against the
+    /// end keyword. Only attribute names are compared this way; element
+    /// names are still compared case-sensitively everywhere else (ex.
+    /// [select] and [apply_patch]). Use [find_child_ignore_case] to find a
+    /// child element by name with the same folding.
+    pub fn get_attribute_ignore_case(&self, name: &str) -> Option<&SMLAttribute<StrAsRef>> {
+        let name = name.to_lowercase();
+        self.attributes
+            .iter()
+            .find(|attribute| attribute.name.as_ref().to_lowercase() == name)
+    }
+
+    /// Gets every attribute with the given name, in order. SML allows an
+    /// element to have several attributes with the same name.
+    pub fn get_attributes<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a SMLAttribute<StrAsRef>> + 'a {
+        self.attributes
+            .iter()
+            .filter(move |attribute| attribute.name.as_ref() == name)
+    }
+
+    /// Sets the values of the first attribute with the given name, or adds
+    /// the attribute to the end if there isn't one. Any later attributes
+    /// with the same name are left as they are. Names are compared
+    /// case-sensitively.
+    pub fn set_attribute(&mut self, name: impl Into<StrAsRef>, values: Vec<Option<StrAsRef>>) {
+        let name = name.into();
+        match self
+            .attributes
+            .iter_mut()
+            .find(|attribute| attribute.name.as_ref() == name.as_ref())
+        {
+            Some(attribute) => attribute.values = values,
+            None => self.attributes.push(SMLAttribute { name, values }),
+        }
+    }
+
+    /// Removes the first attribute with the given name, returning whether
+    /// there was one. Names are compared case-sensitively.
+    pub fn remove_attribute(&mut self, name: &str) -> bool {
+        match self
+            .attributes
+            .iter()
+            .position(|attribute| attribute.name.as_ref() == name)
+        {
+            Some(i) => {
+                self.attributes.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl SMLElement<Cow<'_, str>> {
+    #[allow(clippy::wrong_self_convention)]
+    fn to_owned(self) -> SMLElement<String> {
+        let mut attributes = Vec::with_capacity(self.attributes.len());
+        for attr in self.attributes {
+            attributes.push(attr.to_owned());
+        }
+
+        SMLElement {
+            name: match self.name {
+                Cow::Borrowed(str) => str.to_string(),
+                Cow::Owned(string) => string,
+            },
+            attributes,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SMLAttribute<StrAsRef>
+where
+    StrAsRef: AsRef<str>,
+{
+    pub name: StrAsRef,
+    pub values: Vec<Option<StrAsRef>>,
+}
+
+impl<StrAsRef> SMLAttribute<StrAsRef>
+where
+    StrAsRef: AsRef<str>,
+{
+    /// Iterates over the values of this attribute that aren't null, in
+    /// order.
+    pub fn non_null_values(&self) -> impl Iterator<Item = &str> {
+        self.values.iter().flatten().map(|value| value.as_ref())
+    }
+
+    /// Gets the number of values in this attribute, including nulls.
+    pub fn value_count(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Gets the number of null values in this attribute.
+    pub fn null_count(&self) -> usize {
+        self.values.iter().filter(|value| value.is_none()).count()
+    }
+
+    /// Parses the value at index as an i64. Returns None if there is no
+    /// value at index or it's null.
+    pub fn get_i64(&self, index: usize) -> Option<Result<i64, ParseIntError>> {
+        self.get_value(index).map(str::parse)
+    }
+
+    /// Parses the value at index as an f64. Returns None if there is no
+    /// value at index or it's null.
+    pub fn get_f64(&self, index: usize) -> Option<Result<f64, ParseFloatError>> {
+        self.get_value(index).map(str::parse)
+    }
+
+    /// Parses the value at index as a bool, accepting "true" and "false"
+    /// case-insensitively. Returns None if there is no value at index or
+    /// it's null.
+    pub fn get_bool(&self, index: usize) -> Option<Result<bool, ParseBoolError>> {
+        self.get_value(index).map(|value| {
+            if value.eq_ignore_ascii_case("true") {
+                Ok(true)
+            } else if value.eq_ignore_ascii_case("false") {
+                Ok(false)
+            } else {
+                value.parse()
+            }
+        })
+    }
+
+    fn get_value(&self, index: usize) -> Option<&str> {
+        self.values.get(index)?.as_ref().map(|value| value.as_ref())
+    }
+
+    /// Sorts the values of this attribute by their string contents, with
+    /// null values placed last. Value order is usually significant in SML,
+    /// so this is only appropriate for attributes whose values are treated
+    /// as a set (ex. tags) when building a canonical form.
+    pub fn sort_values(&mut self) {
+        self.values.sort_by(|a, b| match (a.as_ref(), b.as_ref()) {
+            (None, None) => std::cmp::Ordering::Equal,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (Some(a), Some(b)) => a.as_ref().cmp(b.as_ref()),
+        });
+    }
+
+    /// Removes duplicate non-null values from this attribute, keeping the
+    /// first occurrence of each. Null values are all kept. Like
+    /// [SMLAttribute::sort_values], this is only appropriate for
+    /// attributes whose values are treated as a set.
+    pub fn dedup_values(&mut self) {
+        let mut seen = std::collections::HashSet::new();
+        self.values.retain(|value| match value {
+            None => true,
+            Some(value) => seen.insert(value.as_ref().to_string()),
+        });
+    }
+}
+
+impl SMLAttribute<String> {
+    /// Builds an attribute by splitting values according to the WSV rules.
+    /// Values are separated by whitespace, quoted values may contain
+    /// whitespace and escape sequences, and - is a null value. For example,
+    /// `a "b c" - d` becomes [Some("a"), Some("b c"), None, Some("d")].
+    /// Line breaks are treated like any other whitespace.
+    pub fn from_str_values(name: &str, values: &str) -> Result<Self, WSVError> {
+        let lines = whitespacesv::parse(values)?;
+        Ok(Self {
+            name: name.to_string(),
+            values: lines
+                .into_iter()
+                .flatten()
+                .map(|value| value.map(|value| value.into_owned()))
+                .collect(),
+        })
+    }
+}
+
+impl SMLAttribute<Cow<'_, str>> {
+    #[allow(clippy::wrong_self_convention)]
+    fn to_owned(self) -> SMLAttribute<String> {
+        let mut values = Vec::with_capacity(self.values.len());
+        for value in self.values {
+            let new_value = match value {
+                None => None,
+                Some(cow) => match cow {
+                    Cow::Borrowed(str) => Some(str.to_string()),
+                    Cow::Owned(string) => Some(string),
+                },
+            };
+
+            values.push(new_value);
+        }
+        SMLAttribute {
+            name: match self.name {
+                Cow::Borrowed(str) => str.to_string(),
+                Cow::Owned(string) => string,
+            },
+            values,
+        }
+    }
+}
+
+/// Builds a [TreeNode] of [SMLElement]s without spelling out every struct
+/// by hand. For example, the following is ready to be passed to
+/// [SMLWriter::new]:
+///
+/// ```
+/// use simpleml::SMLElementBuilder;
+///
+/// let tree = SMLElementBuilder::new("Configuration")
+///     .child(
+///         SMLElementBuilder::new("Video")
+///             .attr("Resolution", ["1280", "720"])
+///             .attr("Fullscreen", ["true"]),
+///     )
+///     .child(SMLElementBuilder::new("Player").attr_with_nulls("Name", [Some("Hero"), None]))
+///     .build();
+/// ```
+#[derive(Debug)]
+pub struct SMLElementBuilder {
+    element: SMLElement<String>,
+    children: Vec<TreeNode<SMLElement<String>>>,
+}
+
+impl SMLElementBuilder {
+    /// Creates a builder for an element with the given name and no
+    /// attributes or children.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            element: SMLElement {
+                name: name.into(),
+                attributes: Vec::new(),
+            },
+            children: Vec::new(),
+        }
+    }
+
+    /// Adds an attribute with the given non-null values, after any
+    /// attributes already added.
+    pub fn attr<Value>(
+        self,
+        name: impl Into<String>,
+        values: impl IntoIterator<Item = Value>,
+    ) -> Self
+    where
+        Value: Into<String>,
+    {
+        self.attr_with_nulls(name, values.into_iter().map(Some))
+    }
+
+    /// Equivalent to [SMLElementBuilder::attr], but None values are
+    /// written as nulls.
+    pub fn attr_with_nulls<Value>(
+        mut self,
+        name: impl Into<String>,
+        values: impl IntoIterator<Item = Option<Value>>,
+    ) -> Self
+    where
+        Value: Into<String>,
+    {
+        self.element.attributes.push(SMLAttribute {
+            name: name.into(),
+            values: values
+                .into_iter()
+                .map(|value| value.map(Into::into))
+                .collect(),
+        });
+        self
+    }
+
+    /// Adds a child element after any children already added. This
+    /// accepts either another builder or an already built tree.
+    pub fn child(mut self, child: impl Into<TreeNode<SMLElement<String>>>) -> Self {
+        self.children.push(child.into());
+        self
+    }
+
+    /// Builds the tree.
+    pub fn build(self) -> TreeNode<SMLElement<String>> {
+        TreeNode {
+            value: self.element,
+            children: self.children,
+        }
+    }
+}
+
+impl From<SMLElementBuilder> for TreeNode<SMLElement<String>> {
+    fn from(builder: SMLElementBuilder) -> Self {
+        builder.build()
+    }
+}
+
+/// For every element named parent_name, moves the first attribute of each
+/// child named child_name up into the parent's attribute list (after any
+/// existing attributes, in child order) and removes the child. Any other
+/// attributes or children of a folded child are discarded. Children that
+/// have no attributes are left in place.
+///
+/// This is useful for normalizing generated SML that splits a single list
+/// across many single-attribute children.
+pub fn fold_children_into_attributes<StrAsRef>(
+    tree: &mut TreeNode<SMLElement<StrAsRef>>,
+    parent_name: &str,
+    child_name: &str,
+) where
+    StrAsRef: AsRef<str>,
+{
+    if tree.value.name.as_ref() == parent_name {
+        let children = std::mem::take(&mut tree.children);
+        for mut child in children {
+            if child.value.name.as_ref() == child_name && !child.value.attributes.is_empty() {
+                let attribute = child.value.attributes.remove(0);
+                tree.value.attributes.push(attribute);
+            } else {
+                tree.children.push(child);
+            }
+        }
+    }
+
+    for child in tree.children.iter_mut() {
+        fold_children_into_attributes(child, parent_name, child_name);
+    }
+}
+
+/// Renders a concise, single-line summary of the tree. Each element is
+/// written as its name followed by its attribute count (if it has any)
+/// and its children wrapped in braces (if it has any). For example:
+/// `Configuration { Video(3 attrs), Audio(2 attrs), Player(1 attr) }`
+///
+/// This is much easier to read in test failures and logs than the
+/// derived Debug output.
+pub fn debug_compact<StrAsRef>(tree: &TreeNode<SMLElement<StrAsRef>>) -> String
+where
+    StrAsRef: AsRef<str>,
+{
+    let mut result = String::new();
+    debug_compact_helper(tree, &mut result);
+    result
+}
+
+fn debug_compact_helper<StrAsRef>(tree: &TreeNode<SMLElement<StrAsRef>>, buf: &mut String)
+where
+    StrAsRef: AsRef<str>,
+{
+    buf.push_str(tree.value.name.as_ref());
+    match tree.value.attributes.len() {
+        0 => {}
+        1 => buf.push_str("(1 attr)"),
+        len => {
+            buf.push('(');
+            buf.push_str(&len.to_string());
+            buf.push_str(" attrs)");
+        }
+    }
+
+    if !tree.children.is_empty() {
+        buf.push_str(" { ");
+        for (i, child) in tree.children.iter().enumerate() {
+            if i != 0 {
+                buf.push_str(", ");
+            }
+            debug_compact_helper(child, buf);
+        }
+        buf.push_str(" }");
+    }
+}
+
+/// Iterates over the elements of the tree in preorder, pairing each one
+/// with its depth. The root is at depth 0.
+pub fn iter_with_depth<StrAsRef>(
+    tree: &TreeNode<SMLElement<StrAsRef>>,
+) -> impl Iterator<Item = (usize, &SMLElement<StrAsRef>)>
+where
+    StrAsRef: AsRef<str>,
+{
+    let mut stack = vec![(0, tree)];
+    std::iter::from_fn(move || {
+        let (depth, node) = stack.pop()?;
+        stack.extend(node.children.iter().rev().map(|child| (depth + 1, child)));
+        Some((depth, &node.value))
+    })
+}
+
+/// Iterates over the elements of the tree in preorder, pairing each one
+/// with its slash-separated path from the root (ex. "Configuration/Video").
+pub fn iter_with_paths<StrAsRef>(
+    tree: &TreeNode<SMLElement<StrAsRef>>,
+) -> impl Iterator<Item = (String, &SMLElement<StrAsRef>)>
+where
+    StrAsRef: AsRef<str>,
+{
+    let mut stack = vec![(tree.value.name.as_ref().to_string(), tree)];
+    std::iter::from_fn(move || {
+        let (path, node) = stack.pop()?;
+        for child in node.children.iter().rev() {
+            let mut child_path =
+                String::with_capacity(path.len() + 1 + child.value.name.as_ref().len());
+            child_path.push_str(&path);
+            child_path.push('/');
+            child_path.push_str(child.value.name.as_ref());
+            stack.push((child_path, child));
+        }
+        Some((path, &node.value))
+    })
+}
+
+/// Checks that every child of node named child_name has the same
+/// attributes with the same number of values as the first such child.
+/// This is useful for tabular SML, where each "Row" child should have
+/// the same shape. Every attribute that is missing, extra, or has a
+/// different number of values is reported.
+pub fn check_uniform_columns<StrAsRef>(
+    node: &TreeNode<SMLElement<StrAsRef>>,
+    child_name: &str,
+) -> Result<(), Vec<Inconsistency>>
+where
+    StrAsRef: AsRef<str>,
+{
+    let mut rows = node
+        .children
+        .iter()
+        .enumerate()
+        .filter(|(_, child)| child.value.name.as_ref() == child_name);
+
+    let reference = match rows.next() {
+        None => return Ok(()),
+        Some((_, first)) => &first.value.attributes,
+    };
+
+    let mut inconsistencies = Vec::new();
+    for (child_index, row) in rows {
+        let attributes = &row.value.attributes;
+        for attribute in attributes.iter() {
+            let matches_reference = reference.iter().any(|expected| {
+                expected.name.as_ref() == attribute.name.as_ref()
+                    && expected.values.len() == attribute.values.len()
+            });
+            if !matches_reference {
+                inconsistencies.push(Inconsistency {
+                    child_index,
+                    attribute_name: attribute.name.as_ref().to_string(),
+                });
+            }
+        }
+
+        for expected in reference.iter() {
+            if !attributes
+                .iter()
+                .any(|attribute| attribute.name.as_ref() == expected.name.as_ref())
+            {
+                inconsistencies.push(Inconsistency {
+                    child_index,
+                    attribute_name: expected.name.as_ref().to_string(),
+                });
+            }
+        }
+    }
+
+    if inconsistencies.is_empty() {
+        Ok(())
+    } else {
+        Err(inconsistencies)
+    }
+}
+
+/// A child found by [check_uniform_columns] whose attribute doesn't
+/// match the shape of the first child.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inconsistency {
+    child_index: usize,
+    attribute_name: String,
+}
+
+impl Inconsistency {
+    /// The index of the offending child among all of the node's children.
+    pub fn child_index(&self) -> usize {
+        self.child_index
+    }
+    pub fn attribute_name(&self) -> &str {
+        &self.attribute_name
+    }
+}
+
+/// Finds every element that has both an attribute and a child element
+/// with the same name. Looking up that name is ambiguous, so some
+/// dialects treat it as an error. Each name is only reported once per
+/// element.
+pub fn check_name_shadowing<StrAsRef>(tree: &TreeNode<SMLElement<StrAsRef>>) -> Vec<ShadowWarning>
+where
+    StrAsRef: AsRef<str>,
+{
+    let mut warnings = Vec::new();
+    check_name_shadowing_helper(tree, tree.value.name.as_ref().to_string(), &mut warnings);
+    warnings
+}
+
+fn check_name_shadowing_helper<StrAsRef>(
+    tree: &TreeNode<SMLElement<StrAsRef>>,
+    path: String,
+    warnings: &mut Vec<ShadowWarning>,
+) where
+    StrAsRef: AsRef<str>,
+{
+    for (i, attribute) in tree.value.attributes.iter().enumerate() {
+        let name = attribute.name.as_ref();
+        let already_reported = tree.value.attributes[..i]
+            .iter()
+            .any(|previous| previous.name.as_ref() == name);
+        if !already_reported
+            && tree
+                .children
+                .iter()
+                .any(|child| child.value.name.as_ref() == name)
+        {
+            warnings.push(ShadowWarning {
+                path: path.clone(),
+                name: name.to_string(),
+            });
+        }
+    }
+
+    for child in tree.children.iter() {
+        let child_path = path.clone() + "/" + child.value.name.as_ref();
+        check_name_shadowing_helper(child, child_path, warnings);
+    }
+}
+
+/// An element found by [check_name_shadowing] that has an attribute and
+/// a child element with the same name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShadowWarning {
+    path: String,
+    name: String,
+}
+
+impl ShadowWarning {
+    /// The slash-separated path of the offending element.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    /// The name shared by the attribute and the child element.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Checks that no element has two children with the same name, which
+/// many schemas require. Each duplicated name is only reported once per
+/// parent.
+pub fn check_unique_siblings<StrAsRef>(
+    tree: &TreeNode<SMLElement<StrAsRef>>,
+) -> Result<(), Vec<DuplicateSibling>>
+where
+    StrAsRef: AsRef<str>,
+{
+    let mut duplicates = Vec::new();
+    check_unique_siblings_helper(tree, tree.value.name.as_ref().to_string(), &mut duplicates);
+    if duplicates.is_empty() {
+        Ok(())
+    } else {
+        Err(duplicates)
+    }
+}
+
+fn check_unique_siblings_helper<StrAsRef>(
+    tree: &TreeNode<SMLElement<StrAsRef>>,
+    path: String,
+    duplicates: &mut Vec<DuplicateSibling>,
+) where
+    StrAsRef: AsRef<str>,
+{
+    let mut counts = std::collections::HashMap::new();
+    for child in tree.children.iter() {
+        let name = child.value.name.as_ref();
+        let count = counts.entry(name).or_insert(0);
+        *count += 1;
+        // Only report each name once, when its first duplicate is seen.
+        if *count == 2 {
+            duplicates.push(DuplicateSibling {
+                path: path.clone(),
+                name: name.to_string(),
+            });
+        }
+    }
+
+    for child in tree.children.iter() {
+        let child_path = path.clone() + "/" + child.value.name.as_ref();
+        check_unique_siblings_helper(child, child_path, duplicates);
+    }
+}
+
+/// An element found by [check_unique_siblings] that has more than one
+/// child with the same name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateSibling {
+    path: String,
+    name: String,
+}
+
+impl DuplicateSibling {
+    /// The slash-separated path of the parent element.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    /// The name shared by the children.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Splits the tree into one standalone document per direct child of the
+/// root. Each child becomes the root of its own tree and is paired with
+/// its name as a key (ex. for sharding a large config into one file per
+/// section). The root's own attributes are not included in any of them.
+pub fn split_by_child<StrAsRef>(
+    tree: &TreeNode<SMLElement<StrAsRef>>,
+) -> Vec<(String, TreeNode<SMLElement<String>>)>
+where
+    StrAsRef: AsRef<str>,
+{
+    tree.children
+        .iter()
+        .map(|child| (child.value.name.as_ref().to_string(), clone_owned(child)))
+        .collect()
+}
+
+/// Copies the top max_depth levels of the tree, dropping every element
+/// below them (ex. a max_depth of 1 keeps only the root, with all of its
+/// attributes but none of its children). The root is always kept, so a
+/// max_depth of 0 behaves like 1. This is useful for shallow previews of
+/// large documents.
+pub fn truncate_depth<StrAsRef>(
+    tree: &TreeNode<SMLElement<StrAsRef>>,
+    max_depth: usize,
+) -> TreeNode<SMLElement<String>>
+where
+    StrAsRef: AsRef<str>,
+{
+    let mut result = TreeNode {
+        value: clone_owned_element(&tree.value),
+        children: Vec::new(),
+    };
+    if max_depth > 1 {
+        result.children = tree
+            .children
+            .iter()
+            .map(|child| truncate_depth(child, max_depth - 1))
+            .collect();
+    }
+    result
+}
+
+pub(crate) fn borrow_tree<StrAsRef>(
+    tree: &TreeNode<SMLElement<StrAsRef>>,
+) -> TreeNode<SMLElement<&str>>
+where
+    StrAsRef: AsRef<str>,
+{
+    TreeNode {
+        value: SMLElement {
+            name: tree.value.name.as_ref(),
+            attributes: tree
+                .value
+                .attributes
+                .iter()
+                .map(|attr| SMLAttribute {
+                    name: attr.name.as_ref(),
+                    values: attr
+                        .values
+                        .iter()
+                        .map(|value| value.as_ref().map(|value| value.as_ref()))
+                        .collect(),
+                })
+                .collect(),
+        },
+        children: tree.children.iter().map(borrow_tree).collect(),
+    }
+}
+
+fn clone_owned<StrAsRef>(tree: &TreeNode<SMLElement<StrAsRef>>) -> TreeNode<SMLElement<String>>
+where
+    StrAsRef: AsRef<str>,
+{
+    TreeNode {
+        value: clone_owned_element(&tree.value),
+        children: tree.children.iter().map(clone_owned).collect(),
+    }
+}
+
+fn clone_owned_element<StrAsRef>(element: &SMLElement<StrAsRef>) -> SMLElement<String>
+where
+    StrAsRef: AsRef<str>,
+{
+    SMLElement {
+        name: element.name.as_ref().to_string(),
+        attributes: element
+            .attributes
+            .iter()
+            .map(|attr| SMLAttribute {
+                name: attr.name.as_ref().to_string(),
+                values: attr
+                    .values
+                    .iter()
+                    .map(|value| value.as_ref().map(|value| value.as_ref().to_string()))
+                    .collect(),
+            })
+            .collect(),
+    }
+}
+
+/// Lists every attribute value in the tree with a JSON Pointer style path
+/// (ex. "/Configuration/Video/Resolution/1" for the second value of the
+/// Resolution attribute). Names are escaped as in RFC 6901, so '~' becomes
+/// "~0" and '/' becomes "~1". Values are listed in preorder, attribute by
+/// attribute. Sibling elements or attributes that share a name will also
+/// share a path prefix.
+pub fn value_pointers<StrAsRef>(
+    tree: &TreeNode<SMLElement<StrAsRef>>,
+) -> Vec<(String, Option<&str>)>
+where
+    StrAsRef: AsRef<str>,
+{
+    let mut result = Vec::new();
+    value_pointers_helper(tree, &mut String::new(), &mut result);
+    result
+}
+
+fn value_pointers_helper<'a, StrAsRef>(
+    tree: &'a TreeNode<SMLElement<StrAsRef>>,
+    path: &mut String,
+    result: &mut Vec<(String, Option<&'a str>)>,
+) where
+    StrAsRef: AsRef<str>,
+{
+    let parent_path_len = path.len();
+    push_pointer_segment(path, tree.value.name.as_ref());
+
+    for attribute in tree.value.attributes.iter() {
+        let element_path_len = path.len();
+        push_pointer_segment(path, attribute.name.as_ref());
+        for (i, value) in attribute.values.iter().enumerate() {
+            result.push((
+                format!("{}/{}", path, i),
+                value.as_ref().map(|value| value.as_ref()),
+            ));
+        }
+        path.truncate(element_path_len);
+    }
+
+    for child in tree.children.iter() {
+        value_pointers_helper(child, path, result);
+    }
+    path.truncate(parent_path_len);
+}
+
+fn push_pointer_segment(path: &mut String, segment: &str) {
+    path.push('/');
+    for ch in segment.chars() {
+        match ch {
+            '~' => path.push_str("~0"),
+            '/' => path.push_str("~1"),
+            ch => path.push(ch),
+        }
+    }
+}
+
+/// Sets the values addressed by pointers in the same format as
+/// [value_pointers] (ex. "/Configuration/Audio/Volume/0"). Each pointer is
+/// set to its paired value, or to null if the value is None. If the
+/// addressed attribute doesn't exist on the element, it is added. The index
+/// may address an existing value or the one just past the end, which
+/// appends it (earlier entries in the patch count, so "/0" then "/1" adds
+/// two values). Elements are matched by the first child with each name.
+///
+/// Every pointer is resolved before any of them are applied, so if any
+/// pointer doesn't resolve to an element, or its index is further past the
+/// end than that, the tree is left unchanged and an [UnresolvedPointer]
+/// error identifies the first one that failed.
+pub fn apply_patch<StrAsRef>(
+    tree: &mut TreeNode<SMLElement<StrAsRef>>,
+    patch: &[(String, Option<String>)],
+) -> Result<(), UnresolvedPointer>
+where
+    StrAsRef: AsRef<str> + From<String>,
+{
+    let mut resolved = Vec::with_capacity(patch.len());
+    // The number of values each attribute will have after the entries
+    // resolved so far are applied.
+    let mut lengths = std::collections::HashMap::new();
+    for (pointer, value) in patch.iter() {
+        let unresolved = || UnresolvedPointer {
+            pointer: pointer.clone(),
+        };
+        let (child_indices, attribute, index, len) =
+            resolve_pointer(tree, pointer).ok_or_else(unresolved)?;
+        let len = lengths
+            .entry((child_indices.clone(), attribute.clone()))
+            .or_insert(len);
+        if index > *len {
+            return Err(unresolved());
+        }
+        if index == *len {
+            *len += 1;
+        }
+        resolved.push((child_indices, attribute, index, value));
+    }
+
+    for (child_indices, attribute_name, index, value) in resolved {
+        let mut element = &mut *tree;
+        for i in child_indices {
+            element = &mut element.children[i];
+        }
+
+        let attributes = &mut element.value.attributes;
+        let attribute = match attributes
+            .iter()
+            .position(|attr| attr.name.as_ref() == attribute_name)
+        {
+            Some(position) => &mut attributes[position],
+            None => {
+                attributes.push(SMLAttribute {
+                    name: StrAsRef::from(attribute_name),
+                    values: Vec::new(),
+                });
+                attributes.last_mut().unwrap()
+            }
+        };
+
+        let value = value.clone().map(StrAsRef::from);
+        if index == attribute.values.len() {
+            attribute.values.push(value);
+        } else {
+            attribute.values[index] = value;
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolves a value pointer to the indices of the children to follow from
+/// the root, the attribute name, the value index, and the number of values
+/// the attribute currently has (0 if it doesn't exist).
+fn resolve_pointer<StrAsRef>(
+    tree: &TreeNode<SMLElement<StrAsRef>>,
+    pointer: &str,
+) -> Option<(Vec<usize>, String, usize, usize)>
+where
+    StrAsRef: AsRef<str>,
+{
+    let mut segments = pointer
+        .strip_prefix('/')?
+        .split('/')
+        .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+        .collect::<Vec<_>>();
+    if segments.len() < 3 {
+        return None;
+    }
+
+    let index = segments.pop()?.parse::<usize>().ok()?;
+    let attribute = segments.pop()?;
+    let mut segments = segments.into_iter();
+    if segments.next()? != tree.value.name.as_ref() {
+        return None;
+    }
+
+    let mut element = tree;
+    let mut child_indices = Vec::new();
+    for segment in segments {
+        let i = element
+            .children
+            .iter()
+            .position(|child| child.value.name.as_ref() == segment)?;
+        child_indices.push(i);
+        element = &element.children[i];
+    }
+
+    let len = element
+        .value
+        .attributes
+        .iter()
+        .find(|attr| attr.name.as_ref() == attribute)
+        .map_or(0, |attr| attr.values.len());
+    Some((child_indices, attribute, index, len))
+}
+
+/// A pointer passed to [apply_patch] that didn't resolve to an element
+/// in the tree, or wasn't a valid pointer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnresolvedPointer {
+    pointer: String,
+}
+
+impl UnresolvedPointer {
+    pub fn pointer(&self) -> &str {
+        &self.pointer
+    }
+}
+
+impl Error for UnresolvedPointer {}
+impl Display for UnresolvedPointer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unresolved Pointer {}", self.pointer)
+    }
+}
+
+/// Finds the first direct child of node whose element matches the
+/// predicate (ex. the child whose "id" attribute is 42).
+pub fn find_child<StrAsRef, F>(
+    node: &TreeNode<SMLElement<StrAsRef>>,
+    predicate: F,
+) -> Option<&TreeNode<SMLElement<StrAsRef>>>
+where
+    StrAsRef: AsRef<str>,
+    F: Fn(&SMLElement<StrAsRef>) -> bool,
+{
+    node.children.iter().find(|child| predicate(&child.value))
+}
+
+/// Equivalent to [find_child], but searches the whole tree (including
+/// the root) in preorder.
+pub fn find_element<StrAsRef, F>(
+    tree: &TreeNode<SMLElement<StrAsRef>>,
+    predicate: F,
+) -> Option<&TreeNode<SMLElement<StrAsRef>>>
+where
+    StrAsRef: AsRef<str>,
+    F: Fn(&SMLElement<StrAsRef>) -> bool,
+{
+    let mut stack = vec![tree];
+    while let Some(node) = stack.pop() {
+        if predicate(&node.value) {
+            return Some(node);
+        }
+        stack.extend(node.children.iter().rev());
+    }
+    None
+}
+
+/// Finds the first direct child of node with the given name, compared
+/// case-insensitively with the same folding as
+/// [SMLElement::get_attribute_ignore_case].
+pub fn find_child_ignore_case<'a, StrAsRef>(
+    node: &'a TreeNode<SMLElement<StrAsRef>>,
+    name: &str,
+) -> Option<&'a TreeNode<SMLElement<StrAsRef>>>
+where
+    StrAsRef: AsRef<str>,
+{
+    let name = name.to_lowercase();
+    find_child(node, |element| element.name.as_ref().to_lowercase() == name)
+}
+
+/// Selects the elements or attributes at path, in preorder. Element names
+/// are separated by '/', starting with the root's name (ex.
+/// "Configuration/Video" selects every Video child of the Configuration
+/// root). A path ending in "@" and an attribute name (ex.
+/// "Configuration/Video@Resolution") selects the attributes with that
+/// name on those elements instead. Names are compared case-sensitively,
+/// and every sibling with a matching name is selected. A path that
+/// doesn't match anything selects nothing.
+pub fn select<'a, StrAsRef>(
+    tree: &'a TreeNode<SMLElement<StrAsRef>>,
+    path: &str,
+) -> Vec<Selection<'a, StrAsRef>>
+where
+    StrAsRef: AsRef<str>,
+{
+    let (element_path, attribute) = match path.split_once('@') {
+        None => (path, None),
+        Some((element_path, attribute)) => (element_path, Some(attribute)),
+    };
+
+    let mut segments = element_path.split('/');
+    if segments.next() != Some(tree.value.name.as_ref()) {
+        return Vec::new();
+    }
+    let mut elements = vec![tree];
+    for segment in segments {
+        elements = elements
+            .into_iter()
+            .flat_map(|element| element.children.iter())
+            .filter(|child| child.value.name.as_ref() == segment)
+            .collect();
+    }
+
+    match attribute {
+        None => elements
+            .into_iter()
+            .map(|element| Selection::Element(&element.value))
+            .collect(),
+        Some(attribute) => elements
+            .into_iter()
+            .flat_map(|element| element.value.attributes.iter())
+            .filter(|attr| attr.name.as_ref() == attribute)
+            .map(Selection::Attribute)
+            .collect(),
+    }
+}
+
+/// An element or attribute selected by [select].
+#[derive(Debug)]
+pub enum Selection<'a, StrAsRef>
+where
+    StrAsRef: AsRef<str>,
+{
+    Element(&'a SMLElement<StrAsRef>),
+    Attribute(&'a SMLAttribute<StrAsRef>),
+}
+
+/// Iterates over the leaf elements of the tree (those with no children)
+/// in preorder. In most documents, the leaves hold the actual settings.
+/// A root with no children is itself a leaf.
+pub fn iter_leaves<StrAsRef>(
+    tree: &TreeNode<SMLElement<StrAsRef>>,
+) -> impl Iterator<Item = &TreeNode<SMLElement<StrAsRef>>>
+where
+    StrAsRef: AsRef<str>,
+{
+    let mut stack = vec![tree];
+    std::iter::from_fn(move || loop {
+        let node = stack.pop()?;
+        if node.children.is_empty() {
+            return Some(node);
+        }
+        stack.extend(node.children.iter().rev());
+    })
+}
+
+/// Pairs every element of the tree with the value the closure returns for
+/// it, without changing the tree itself. This lets tools carry derived
+/// data (validation status, source file, etc.) alongside the elements.
+/// The closure is called on the elements in preorder, with each element's
+/// depth (the root has a depth of 0) and its slash-separated path from
+/// the root (ex. "Configuration/Video"), as in [iter_with_paths].
+pub fn annotate<'a, StrAsRef, M, F>(
+    tree: &'a TreeNode<SMLElement<StrAsRef>>,
+    mut f: F,
+) -> AnnotatedTree<'a, StrAsRef, M>
+where
+    StrAsRef: AsRef<str>,
+    F: FnMut(usize, &str, &SMLElement<StrAsRef>) -> M,
+{
+    fn annotate_helper<'a, StrAsRef, M, F>(
+        tree: &'a TreeNode<SMLElement<StrAsRef>>,
+        depth: usize,
+        path: &mut String,
+        f: &mut F,
+    ) -> AnnotatedTree<'a, StrAsRef, M>
+    where
+        StrAsRef: AsRef<str>,
+        F: FnMut(usize, &str, &SMLElement<StrAsRef>) -> M,
+    {
+        let parent_path_len = path.len();
+        if depth != 0 {
+            path.push('/');
+        }
+        path.push_str(tree.value.name.as_ref());
+        let annotated = AnnotatedTree {
+            annotation: f(depth, path, &tree.value),
+            element: &tree.value,
+            children: tree
+                .children
+                .iter()
+                .map(|child| annotate_helper(child, depth + 1, path, f))
+                .collect(),
+        };
+        path.truncate(parent_path_len);
+        annotated
+    }
+
+    annotate_helper(tree, 0, &mut String::new(), &mut f)
+}
+
+/// A tree built by [annotate] that mirrors the shape of an SML tree,
+/// pairing each element with an annotation of type M.
+#[derive(Debug)]
+pub struct AnnotatedTree<'a, StrAsRef, M>
+where
+    StrAsRef: AsRef<str>,
+{
+    pub element: &'a SMLElement<StrAsRef>,
+    pub annotation: M,
+    pub children: Vec<AnnotatedTree<'a, StrAsRef, M>>,
+}
+
+impl<'a, StrAsRef, M> AnnotatedTree<'a, StrAsRef, M>
+where
+    StrAsRef: AsRef<str>,
+{
+    /// Iterates over the elements and their annotations in preorder.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a SMLElement<StrAsRef>, &M)> {
+        let mut stack = vec![self];
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children.iter().rev());
+            Some((node.element, &node.annotation))
+        })
+    }
+
+    /// Iterates over the elements and mutable references to their
+    /// annotations in preorder.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&'a SMLElement<StrAsRef>, &mut M)> {
+        let mut stack = vec![self];
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children.iter_mut().rev());
+            Some((node.element, &mut node.annotation))
+        })
+    }
+}
+
+/// Counts how many elements of each name appear in the tree. This is a
+/// quick way to get a feel for the composition of an unfamiliar document.
+pub fn element_name_histogram<StrAsRef>(
+    tree: &TreeNode<SMLElement<StrAsRef>>,
+) -> BTreeMap<String, usize>
+where
+    StrAsRef: AsRef<str>,
+{
+    let mut histogram = BTreeMap::new();
+    for element in tree.dfs_preorder_iter() {
+        match histogram.get_mut(element.name.as_ref()) {
+            None => {
+                histogram.insert(element.name.as_ref().to_string(), 1);
+            }
+            Some(count) => *count += 1,
+        }
+    }
+    histogram
+}
+
+/// Replaces every attribute value in the tree (including nulls) with the
+/// output of the given closure. The closure receives the current value and
+/// returns the new one, so values can be rewritten, nulled, or un-nulled.
+/// Values are visited in preorder, attribute by attribute.
+pub fn map_values<StrAsRef, F>(tree: &mut TreeNode<SMLElement<StrAsRef>>, mut f: F)
+where
+    StrAsRef: AsRef<str> + From<String>,
+    F: FnMut(Option<&str>) -> Option<String>,
+{
+    for element in tree.dfs_preorder_iter_mut() {
+        for attribute in element.attributes.iter_mut() {
+            for value in attribute.values.iter_mut() {
+                let new_value = f(value.as_ref().map(|val| val.as_ref()));
+                *value = new_value.map(StrAsRef::from);
+            }
+        }
+    }
+}
+
+/// Replaces every ${NAME} reference in the tree's attribute values with
+/// the value of the NAME environment variable. A default can be given
+/// with ${NAME:-default}, which is used when the variable isn't set.
+/// Names and null values are left untouched. If a variable is undefined
+/// and has no default, an [UndefinedVariable] error is returned and the
+/// tree may be partially substituted.
+pub fn substitute_env<StrAsRef>(
+    tree: &mut TreeNode<SMLElement<StrAsRef>>,
+) -> Result<(), UndefinedVariable>
+where
+    StrAsRef: AsRef<str> + From<String>,
+{
+    substitute_vars(tree, |name| std::env::var(name).ok())
+}
+
+/// Equivalent to [substitute_env], but looks variables up with the given
+/// closure instead of the process environment.
+pub fn substitute_vars<StrAsRef, F>(
+    tree: &mut TreeNode<SMLElement<StrAsRef>>,
+    mut lookup: F,
+) -> Result<(), UndefinedVariable>
+where
+    StrAsRef: AsRef<str> + From<String>,
+    F: FnMut(&str) -> Option<String>,
+{
+    for element in tree.dfs_preorder_iter_mut() {
+        for attribute in element.attributes.iter_mut() {
+            for value in attribute.values.iter_mut().flatten() {
+                if let Some(substituted) = substitute_value(value.as_ref(), &mut lookup)? {
+                    *value = StrAsRef::from(substituted);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Substitutes the variables in a single value. Returns None if the value
+/// doesn't reference any variables. An unterminated "${" is kept as is.
+fn substitute_value<F>(value: &str, lookup: &mut F) -> Result<Option<String>, UndefinedVariable>
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    if !value.contains("${") {
+        return Ok(None);
+    }
+
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            None => break,
+            Some(end) => start + end,
+        };
+
+        result.push_str(&rest[..start]);
+        let reference = &rest[start + 2..end];
+        let (name, default) = match reference.split_once(":-") {
+            None => (reference, None),
+            Some((name, default)) => (name, Some(default)),
+        };
+        match (lookup(name), default) {
+            (Some(var), _) => result.push_str(&var),
+            (None, Some(default)) => result.push_str(default),
+            (None, None) => {
+                return Err(UndefinedVariable {
+                    name: name.to_string(),
+                })
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(Some(result))
+}
+
+/// A variable referenced by a value during [substitute_env] that wasn't
+/// defined and had no default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UndefinedVariable {
+    name: String,
+}
+
+impl UndefinedVariable {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Error for UndefinedVariable {}
+impl Display for UndefinedVariable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Undefined Variable {}", self.name)
+    }
+}
+
+/// Rewrites null and empty string values according to the policy. SML
+/// treats them as distinct values, but many producers use them
+/// interchangeably, so this can standardize data before it is compared
+/// or stored.
+pub fn normalize_nulls<StrAsRef>(tree: &mut TreeNode<SMLElement<StrAsRef>>, policy: NullPolicy)
+where
+    StrAsRef: AsRef<str> + From<String>,
+{
+    if policy == NullPolicy::Keep {
+        return;
+    }
+
+    for element in tree.dfs_preorder_iter_mut() {
+        for attribute in element.attributes.iter_mut() {
+            for value in attribute.values.iter_mut() {
+                match (policy, value.as_ref()) {
+                    (NullPolicy::EmptyToNull, Some(val)) if val.as_ref().is_empty() => {
+                        *value = None
+                    }
+                    (NullPolicy::NullToEmpty, None) => *value = Some(StrAsRef::from(String::new())),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// How [normalize_nulls] treats null and empty string values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NullPolicy {
+    /// Empty strings become null.
+    EmptyToNull,
+    /// Nulls become empty strings.
+    NullToEmpty,
+    /// Nothing changes.
+    Keep,
+}
+
+/// Shrinks a failing SML document down to a minimal set of lines that
+/// still reproduces the same kind of error. This is done by repeatedly
+/// removing one line at a time and re-parsing, keeping the removal if
+/// the error type is unchanged. This is useful when filing bug reports
+/// or writing regression tests.
+///
+/// If the source text doesn't fail with the same kind of error as err,
+/// it is returned unchanged.
+pub fn minimize_failing_input(source_text: &str, err: &ParseError) -> String {
+    let reproduces = |lines: &[&str]| match parse(&lines.join("\n")) {
+        Ok(_) => false,
+        Err(new_err) => new_err.is_same_kind(err),
+    };
+
+    let mut lines = source_text.split('\n').collect::<Vec<_>>();
+    if !reproduces(&lines) {
+        return source_text.to_string();
+    }
+
+    loop {
+        let mut removed_any = false;
+        for i in (0..lines.len()).rev() {
+            if lines.len() == 1 {
+                break;
+            }
+            let mut candidate = lines.clone();
+            candidate.remove(i);
+            if reproduces(&candidate) {
+                lines = candidate;
+                removed_any = true;
+            }
+        }
+
+        if !removed_any {
+            break;
+        }
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use tree_iterators_rs::prelude::OwnedTreeNode;
+
+    use crate::{SMLAttribute, SMLElement, SMLWriter};
+
+    #[test]
+    fn reads_example_correctly() {
+        let result = super::parse(include_str!("../example.txt")).unwrap();
+        for (i, element) in result.dfs_preorder().enumerate() {
+            match i {
+                0 => {
+                    assert_eq!("Configuration", &element.name);
+                    assert_eq!(0, element.attributes.len());
+                }
+                1 => {
+                    assert_eq!("Video", &element.name);
+                    assert_eq!(3, element.attributes.len());
+                    for (j, attribute) in element.attributes.into_iter().enumerate() {
+                        match j {
+                            0 => {
+                                assert_eq!("Resolution", attribute.name);
+                                assert_eq!(2, attribute.values.len());
+                                assert_eq!(
+                                    "1280",
+                                    attribute
+                                        .values
+                                        .first()
+                                        .as_ref()
+                                        .unwrap()
+                                        .as_ref()
+                                        .unwrap()
+                                        .as_ref()
+                                );
+                                assert_eq!(
+                                    "720",
+                                    attribute
+                                        .values
+                                        .get(1)
+                                        .as_ref()
+                                        .unwrap()
+                                        .as_ref()
+                                        .unwrap()
+                                        .as_ref()
+                                );
+                            }
+                            1 => {
+                                assert_eq!("RefreshRate", attribute.name);
+                                assert_eq!(1, attribute.values.len());
+                                assert_eq!(
+                                    "60",
+                                    attribute
+                                        .values
+                                        .first()
+                                        .as_ref()
+                                        .unwrap()
+                                        .as_ref()
+                                        .unwrap()
+                                        .as_ref()
+                                );
+                            }
+                            2 => {
+                                assert_eq!("Fullscreen", attribute.name);
+                                assert_eq!(1, attribute.values.len());
+                                assert_eq!(
+                                    "true",
+                                    attribute
+                                        .values
+                                        .first()
+                                        .as_ref()
+                                        .unwrap()
+                                        .as_ref()
+                                        .unwrap()
+                                        .as_ref()
+                                );
+                            }
+                            _ => panic!("Should only have 3 attributes"),
+                        }
+                    }
+                }
+                2 => {
+                    assert_eq!("Audio", &element.name);
+                    assert_eq!(2, element.attributes.len());
+                    for (j, attribute) in element.attributes.into_iter().enumerate() {
+                        match j {
+                            0 => {
+                                assert_eq!("Volume", attribute.name);
+                                assert_eq!(1, attribute.values.len());
+                                assert_eq!(
+                                    "100",
+                                    attribute
+                                        .values
+                                        .first()
+                                        .as_ref()
+                                        .unwrap()
+                                        .as_ref()
+                                        .unwrap()
+                                        .as_ref()
+                                );
+                            }
+                            1 => {
+                                assert_eq!("Music", attribute.name);
+                                assert_eq!(1, attribute.values.len());
+                                assert_eq!(
+                                    "80",
+                                    attribute
+                                        .values
+                                        .first()
+                                        .as_ref()
+                                        .unwrap()
+                                        .as_ref()
+                                        .unwrap()
+                                        .as_ref()
+                                );
+                            }
+                            _ => panic!("Should only have 2 values under audio"),
+                        }
+                    }
+                }
+                3 => {
+                    assert_eq!("Player", element.name);
+                    assert_eq!(1, element.attributes.len());
+                    let attr = element.attributes.first().unwrap();
+                    assert_eq!("Name", attr.name);
+                    assert_eq!(1, attr.values.len());
+                    assert_eq!(
+                        "Hero 123",
+                        attr.values
+                            .first()
+                            .as_ref()
+                            .unwrap()
+                            .as_ref()
+                            .unwrap()
+                            .as_ref()
+                    );
+                }
+                _ => panic!("Should only have 4 sub-elements"),
+            }
+        }
+    }
+
+    #[test]
+    fn reads_example_correctly_owned() {
+        let result = super::parse_owned(include_str!("../example.txt")).unwrap();
+        for (i, element) in result.dfs_preorder().enumerate() {
+            match i {
+                0 => {
+                    assert_eq!("Configuration", &element.name);
+                    assert_eq!(0, element.attributes.len());
+                }
+                1 => {
+                    assert_eq!("Video", &element.name);
+                    assert_eq!(3, element.attributes.len());
+                    for (j, attribute) in element.attributes.into_iter().enumerate() {
+                        match j {
+                            0 => {
+                                assert_eq!("Resolution", attribute.name);
+                                assert_eq!(2, attribute.values.len());
+                                assert_eq!(
+                                    "1280",
+                                    attribute.values.first().as_ref().unwrap().as_ref().unwrap()
+                                );
+                                assert_eq!(
+                                    "720",
+                                    attribute.values.get(1).as_ref().unwrap().as_ref().unwrap()
+                                );
+                            }
+                            1 => {
+                                assert_eq!("RefreshRate", attribute.name);
+                                assert_eq!(1, attribute.values.len());
+                                assert_eq!(
+                                    "60",
+                                    attribute.values.first().as_ref().unwrap().as_ref().unwrap()
+                                );
+                            }
+                            2 => {
+                                assert_eq!("Fullscreen", attribute.name);
+                                assert_eq!(1, attribute.values.len());
+                                assert_eq!(
+                                    "true",
+                                    attribute.values.first().as_ref().unwrap().as_ref().unwrap()
+                                );
+                            }
+                            _ => panic!("Should only have 3 attributes"),
+                        }
+                    }
+                }
+                2 => {
+                    assert_eq!("Audio", &element.name);
+                    assert_eq!(2, element.attributes.len());
+                    for (j, attribute) in element.attributes.into_iter().enumerate() {
+                        match j {
+                            0 => {
+                                assert_eq!("Volume", attribute.name);
+                                assert_eq!(1, attribute.values.len());
+                                assert_eq!(
+                                    "100",
+                                    attribute.values.first().as_ref().unwrap().as_ref().unwrap()
+                                );
+                            }
+                            1 => {
+                                assert_eq!("Music", attribute.name);
+                                assert_eq!(1, attribute.values.len());
+                                assert_eq!(
+                                    "80",
+                                    attribute.values.first().as_ref().unwrap().as_ref().unwrap()
+                                );
+                            }
+                            _ => panic!("Should only have 2 values under audio"),
+                        }
+                    }
+                }
+                3 => {
+                    assert_eq!("Player", element.name);
+                    assert_eq!(1, element.attributes.len());
+                    let attr = element.attributes.first().unwrap();
+                    assert_eq!("Name", attr.name);
+                    assert_eq!(1, attr.values.len());
+                    assert_eq!(
+                        "Hero 123",
+                        attr.values.first().as_ref().unwrap().as_ref().unwrap()
+                    );
+                }
+                _ => panic!("Should only have 4 sub-elements"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_write() {
+        let input = include_str!("../example.txt");
+        println!(
+            "{}",
+            super::SMLWriter::new(super::parse(input).unwrap())
+                .align_columns(whitespacesv::ColumnAlignment::Right)
+                .indent_with(" ")
+                .unwrap()
+                .to_string()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn readme_example() {
+        use tree_iterators_rs::prelude::*;
+
+        // Build up our value set
+        let my_sml_values = TreeNode {
+            value: SMLElement {
+                name: "Configuration",
+                attributes: Vec::with_capacity(0),
+            },
+            children: vec![
+                TreeNode {
+                    value: SMLElement {
+                        name: "Video",
+                        attributes: vec![
+                            SMLAttribute {
+                                name: "Resolution",
+                                values: vec![Some("1280"), Some("720")],
+                            },
+                            SMLAttribute {
+                                name: "RefreshRate",
+                                values: vec![Some("60")],
+                            },
+                            SMLAttribute {
+                                name: "Fullscreen",
+                                values: vec![Some("true")],
+                            },
+                        ],
+                    },
+                    children: Vec::new(),
+                },
+                TreeNode {
+                    value: SMLElement {
+                        name: "Audio",
+                        attributes: vec![
+                            SMLAttribute {
+                                name: "Volume",
+                                values: vec![Some("100")],
+                            },
+                            SMLAttribute {
+                                name: "Music",
+                                values: vec![Some("80")],
+                            },
+                        ],
+                    },
+                    children: Vec::new(),
+                },
+                TreeNode {
+                    value: SMLElement {
+                        name: "Player",
+                        attributes: vec![SMLAttribute {
+                            name: "Name",
+                            values: vec![Some("Hero 123")],
+                        }],
+                    },
+                    children: Vec::new(),
+                },
+            ],
+        };
+
+        // actually write the values
+        let str = SMLWriter::new(my_sml_values)
+            // Setting up a custom end keyword
+            .with_end_keyword(Some("my_custom_end_keyword"))
+            // Using 8 spaces as the indent string. The default is 4 spaces.
+            .indent_with("        ")
+            .unwrap()
+            // Align the WSV tables to the right.
+            .align_columns(whitespacesv::ColumnAlignment::Right)
+            .to_string()
+            .unwrap();
+
+        // Result:
+        // Configuration
+        //         Video
+        //                  Resolution 1280 720
+        //                 RefreshRate   60
+        //                  Fullscreen true
+        //         my_custom_end_keyword
+        //         Audio
+        //                 Volume 100
+        //                  Music  80
+        //         my_custom_end_keyword
+        //         Player
+        //                 Name "Hero 123"
+        //         my_custom_end_keyword
+        // my_custom_end_keyword
+        println!("{}", str);
+    }
+
+    #[test]
+    fn parses_input_with_strange_end_keyword() {
+        let input = r#"
+        Configuration
+                Video
+                        Resolution 1280 720
+                        RefreshRate   60
+                        Fullscreen true
+                "End with spaces"
+                Audio
+                        Volume 100
+                        Music  80
+                "End with spaces"
+                Player
+                        Name "Hero 123"
+                "End with spaces"
+        "End with spaces""#;
+
+        println!("{:?}", super::parse(input).unwrap());
+    }
+
+    #[test]
+    fn parses_input_with_strange_end_keyword_owned() {
+        let input = r#"
+        Configuration
+                Video
+                        Resolution 1280 720
+                        RefreshRate   60
+                        Fullscreen true
+                "End with spaces"
+                Audio
+                        Volume 100
+                        Music  80
+                "End with spaces"
+                Player
+                        Name "Hero 123"
+                "End with spaces"
+        "End with spaces""#;
+
+        println!("{:?}", super::parse_owned(input).unwrap());
+    }
+
+    #[test]
+    fn parses_input_with_null_end_keyword() {
+        let input = r#"
+        Configuration
+                Video
+                        Resolution 1280 720
+                        RefreshRate   60
+                        Fullscreen true
+                -
+                Audio
+                        Volume 100
+                        Music  80
+                -
+                Player
+                        Name "Hero 123"
+                -
+        -"#;
+
+        println!("{:?}", super::parse(input).unwrap());
+    }
+
+    #[test]
+    fn doesnt_crash_bad_input() {
+        let str = r#"Value1
+            Configuration 2 0 2
+            Otherval 1
+            InnerEl
+        -"#;
+
+        super::parse(str).ok();
+    }
+
+    #[test]
+    fn debug_compact_summarizes_tree() {
+        let tree = super::parse(include_str!("../example.txt")).unwrap();
+        assert_eq!(
+            "Configuration { Video(3 attrs), Audio(2 attrs), Player(1 attr) }",
+            super::debug_compact(&tree)
+        );
+    }
+
+    #[test]
+    fn map_values_redacts_secrets() {
+        let mut tree = super::parse_owned(
+            r#"
+        Credentials
+            User admin
+            Password "my secret" -
+            Keys public secret_key
+        End"#,
+        )
+        .unwrap();
+
+        super::map_values(&mut tree, |value| match value {
+            None => Some("unset".to_string()),
+            Some(value) if value.contains("secret") => Some("***".to_string()),
+            Some(value) => Some(value.to_string()),
+        });
+
+        let attributes = &tree.value.attributes;
+        assert_eq!(vec![Some("admin".to_string())], attributes[0].values);
+        assert_eq!(
+            vec![Some("***".to_string()), Some("unset".to_string())],
+            attributes[1].values
+        );
+        assert_eq!(
+            vec![Some("public".to_string()), Some("***".to_string())],
+            attributes[2].values
+        );
+    }
+
+    #[test]
+    fn tab_width_expands_error_columns() {
+        let input = "Root\n\tChild\n\t\t- 5\n\tEnd\nEnd";
+
+        let err = match super::parse(input) {
+            Err(super::ParseError::SML(err)) => err,
+            _ => panic!("Expected an SML error"),
+        };
+        assert_eq!(
+            super::SMLErrorType::NullValueAsAttributeName,
+            err.err_type()
+        );
+        assert_eq!(2, err.line_num());
+        assert_eq!(3, err.column());
+
+        let options = super::ParseOptions::new().tab_width(4);
+        let err = match super::parse_with_options(input, &options) {
+            Err(super::ParseError::SML(err)) => err,
+            _ => panic!("Expected an SML error"),
+        };
+        assert_eq!(2, err.line_num());
+        assert_eq!(9, err.column());
+    }
+
+    #[test]
+    fn minimize_failing_input_shrinks_to_offending_line() {
+        let input = r#"Configuration
+    Video
+        Resolution 1280 720
+        - 60
+        Fullscreen true
+    End
+    Audio
+        Volume 100
+        Music  80
+    End
+    Player
+        Name "Hero 123"
+    End
+End"#;
+
+        let err = super::parse(input).unwrap_err();
+        let minimized = super::minimize_failing_input(input, &err);
+        assert_eq!("Configuration\n        - 60", minimized);
+
+        let minimized_err = match super::parse(&minimized) {
+            Err(super::ParseError::SML(err)) => err,
+            _ => panic!("Expected an SML error"),
+        };
+        assert_eq!(
+            super::SMLErrorType::NullValueAsAttributeName,
+            minimized_err.err_type()
+        );
+    }
+
+    #[test]
+    fn comment_column_aligns_trailing_comments() {
+        use super::{CommentTarget, SMLComments};
+
+        let mut comments = SMLComments::new();
+        comments.set_trailing(
+            CommentTarget::Attribute {
+                element: 1,
+                attribute: 0,
+            },
+            " pixels",
+        );
+        comments.set_trailing(
+            CommentTarget::Attribute {
+                element: 1,
+                attribute: 1,
+            },
+            " hz",
+        );
+        comments.set_trailing(
+            CommentTarget::Attribute {
+                element: 1,
+                attribute: 2,
+            },
+            " bool",
+        );
+
+        let tree = super::parse(include_str!("../example.txt")).unwrap();
+        let output = SMLWriter::new(tree)
+            .with_comments(comments)
+            .comment_column(26)
+            .to_string()
+            .unwrap();
+
+        assert!(output.starts_with(
+            "Configuration
+    Video
+        Resolution 1280 720 # pixels
+        RefreshRate 60   # hz
+        Fullscreen true  # bool
+    -
+"
+        ));
+
+        let reparsed = super::parse(&output).unwrap();
+        assert_eq!(
+            "Configuration { Video(3 attrs), Audio(2 attrs), Player(1 attr) }",
+            super::debug_compact(&reparsed)
+        );
+    }
+
+    #[test]
+    fn validate_indent_for_flags_line_break_indents() {
+        use tree_iterators_rs::prelude::TreeNode;
+
+        let tree = TreeNode {
+            value: SMLElement {
+                name: "Root",
+                attributes: vec![SMLAttribute {
+                    name: "Value",
+                    values: vec![Some("1")],
+                }],
+            },
+            children: Vec::new(),
+        };
+        let writer = SMLWriter::new(tree).indent_with("\u{2028}").unwrap();
+        assert_eq!(
+            Err(super::IndentError::LineBreakLike('\u{2028}')),
+            writer.validate_indent_for()
+        );
+
+        let writer = writer.indent_with("\t").unwrap();
+        assert_eq!(Ok(()), writer.validate_indent_for());
+
+        // A lone root never writes its indentation.
+        let lone_root = TreeNode {
+            value: SMLElement::<&str> {
+                name: "Root",
+                attributes: Vec::new(),
+            },
+            children: Vec::new(),
+        };
+        let writer = SMLWriter::new(lone_root).indent_with("\r").unwrap();
+        assert_eq!(Ok(()), writer.validate_indent_for());
+    }
+
+    #[test]
+    fn reject_non_ascii_fails_on_non_ascii_values() {
+        let input = "Root\n    Name \"Café\"\nEnd";
+        let result = SMLWriter::new(super::parse(input).unwrap())
+            .reject_non_ascii()
+            .to_string();
+        match result {
+            Err(super::SMLWriterError::NonAsciiValue { position }) => {
+                // "Root\n    Name Caf" is 17 bytes long
+                assert_eq!(17, position);
+            }
+            _ => panic!("Expected a NonAsciiValue error"),
+        }
+
+        let ascii = SMLWriter::new(super::parse("Root\n    Name Cafe\nEnd").unwrap())
+            .reject_non_ascii()
+            .to_string();
+        assert!(ascii.is_ok());
+    }
+
+    #[test]
+    fn parse_lenient_fragment_wraps_attributes() {
+        let result = super::parse_lenient_fragment("A 1\nB 2", "Frag").unwrap();
+        assert_eq!("Frag", result.value.name);
+        assert_eq!(2, result.value.attributes.len());
+        assert_eq!("A", result.value.attributes[0].name);
+        assert_eq!(vec![Some("1".into())], result.value.attributes[0].values);
+        assert_eq!("B", result.value.attributes[1].name);
+        assert_eq!(vec![Some("2".into())], result.value.attributes[1].values);
+        assert!(result.children.is_empty());
+    }
+
+    #[test]
+    fn element_name_histogram_counts_names() {
+        let input = r#"
+        Inventory
+            Item
+                Name Sword
+            End
+            Item
+                Name Shield
+            End
+            Bag
+                Item
+                    Name Potion
+                End
+            End
+        End"#;
+
+        let histogram = super::element_name_histogram(&super::parse(input).unwrap());
+        assert_eq!(3, histogram.len());
+        assert_eq!(Some(&1), histogram.get("Inventory"));
+        assert_eq!(Some(&3), histogram.get("Item"));
+        assert_eq!(Some(&1), histogram.get("Bag"));
+    }
+
+    #[test]
+    fn null_values_round_trip_distinct_from_dash_and_empty() {
+        use tree_iterators_rs::prelude::TreeNode;
+
+        let tree = TreeNode {
+            value: SMLElement {
+                name: "Root",
+                attributes: vec![SMLAttribute {
+                    name: "Values",
+                    values: vec![None, Some("-"), Some(""), Some("x")],
+                }],
+            },
+            children: Vec::new(),
+        };
+
+        // The default end keyword is also "-", which must not be confused
+        // with the null value.
+        let output = SMLWriter::new(tree).to_string().unwrap();
+        assert_eq!("Root\n    Values - \"-\" \"\" x \n-", output);
+
+        let reparsed = super::parse(&output).unwrap();
+        assert_eq!(
+            vec![None, Some("-".into()), Some("".into()), Some("x".into())],
+            reparsed.value.attributes[0].values
+        );
+    }
+
+    #[test]
+    fn iter_with_paths_yields_paths_in_preorder() {
+        let tree = super::parse(include_str!("../example.txt")).unwrap();
+        let paths = super::iter_with_paths(&tree)
+            .map(|(path, element)| {
+                assert!(path.ends_with(element.name.as_ref()));
+                path
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "Configuration",
+                "Configuration/Video",
+                "Configuration/Audio",
+                "Configuration/Player",
+            ],
+            paths
+        );
+    }
+
+    #[test]
+    fn reformat_preserving_keeps_value_spacing() {
+        let input = "Configuration
+    Video
+        Resolution   1280   720
+        RefreshRate\t60
+        Fullscreen true
+    End
+    Player
+        Name    \"Hero 123\"
+    End
+End";
+
+        assert_eq!(input, super::reformat_preserving(input).unwrap());
+
+        // Lines whose values changed fall back to the normal alignment.
+        let (mut tree, layout) = super::parse_with_layout(input).unwrap();
+        tree.children[0].value.attributes[0].values.pop();
+        let output = SMLWriter::new(tree)
+            .with_end_keyword(Some("End"))
+            .with_layout(layout)
+            .to_string()
+            .unwrap();
+        assert!(output.contains("\n        Resolution 1280 \n"));
+        assert!(output.contains("\n        RefreshRate\t60\n"));
+    }
+
+    #[test]
+    fn fold_children_into_attributes_flattens_lists() {
+        let input = r#"
+        Config
+            Tags
+                Kind list
+                Tag
+                    Value red
+                End
+                Tag
+                    Value green
+                End
+                Other
+                End
+                Tag
+                End
+            End
+        End"#;
+
+        let mut tree = super::parse(input).unwrap();
+        super::fold_children_into_attributes(&mut tree, "Tags", "Tag");
+
+        let tags = &tree.children[0];
+        assert_eq!("Tags(3 attrs) { Other, Tag }", super::debug_compact(tags));
+        assert_eq!("Kind", tags.value.attributes[0].name);
+        assert_eq!("Value", tags.value.attributes[1].name);
+        assert_eq!(vec![Some("red".into())], tags.value.attributes[1].values);
+        assert_eq!(vec![Some("green".into())], tags.value.attributes[2].values);
+    }
+
+    #[test]
+    fn quoted_attribute_names_round_trip() {
+        let input = "Player\n    \"Full Name\" \"Hero 123\"\nEnd";
+        let tree = super::parse(input).unwrap();
+        assert_eq!("Full Name", tree.value.attributes[0].name);
+
+        let output = SMLWriter::new(tree)
+            .with_end_keyword(Some("End"))
+            .to_string()
+            .unwrap();
+        assert!(output.contains("\n    \"Full Name\" \"Hero 123\" \n"));
+
+        let reparsed = super::parse(&output).unwrap();
+        assert_eq!("Full Name", reparsed.value.attributes[0].name);
+        assert_eq!(
+            vec![Some("Hero 123".into())],
+            reparsed.value.attributes[0].values
+        );
+    }
+
+    #[test]
+    fn extract_elements_yields_matching_subtrees() {
+        let mut input = String::from("Log\n");
+        for i in 0..500 {
+            input.push_str(&format!(
+                "    Info\n        Message \"entry {}\"\n    End\n",
+                i
+            ));
+            if i == 100 || i == 400 {
+                input.push_str(&format!(
+                    "    Error\n        Message \"failure {}\"\n        Detail\n            Code {}\n        End\n    End\n",
+                    i, i
+                ));
+            }
+        }
+        input.push_str("End");
+
+        let errors = super::extract_elements(&input, "Error")
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(2, errors.len());
+        assert_eq!("Error", errors[0].value.name);
+        assert_eq!(
+            Some("failure 100".to_string()),
+            errors[0].value.attributes[0].values[0]
+        );
+        assert_eq!("Detail", errors[0].children[0].value.name);
+        assert_eq!(
+            Some("400".to_string()),
+            errors[1].children[0].value.attributes[0].values[0]
+        );
+
+        let mut results = super::extract_elements("Root\n    Error\n    End\n", "Error");
+        assert!(results.next().unwrap().is_ok());
+        assert!(matches!(
+            results.next(),
+            Some(Err(super::ParseError::SML(super::SMLError {
+                err_type: super::SMLErrorType::RootNotClosed,
+                ..
+            })))
+        ));
+        assert!(results.next().is_none());
+
+        // Errors are the same ones parse finds.
+        let input = "Root\nEnd\nError\nEnd";
+        let mut results = super::extract_elements(input, "Error");
+        assert_eq!(
+            super::parse(input).unwrap_err().to_string(),
+            results.next().unwrap().unwrap_err().to_string()
+        );
+        assert!(results.next().is_none());
+    }
+
+    #[test]
+    fn empty_attributes_are_rejected_or_skipped() {
+        let tree = || super::TreeNode {
+            value: SMLElement {
+                name: "Root",
+                attributes: vec![
+                    SMLAttribute {
+                        name: "Empty",
+                        values: vec![],
+                    },
+                    SMLAttribute {
+                        name: "Null",
+                        values: vec![None],
+                    },
+                ],
+            },
+            children: Vec::new(),
+        };
+
+        assert!(matches!(
+            SMLWriter::new(tree()).to_string(),
+            Err(super::SMLWriterError::AttributeHasNoValues)
+        ));
+
+        let written = SMLWriter::new(tree())
+            .skip_empty_attributes(true)
+            .to_string()
+            .unwrap();
+        let reparsed = super::parse(&written).unwrap();
+        assert!(reparsed.children.is_empty());
+        assert_eq!(1, reparsed.value.attributes.len());
+        assert_eq!("Null", reparsed.value.attributes[0].name);
+        assert_eq!(vec![None], reparsed.value.attributes[0].values);
+    }
+
+    #[test]
+    fn sort_values_places_nulls_last() {
+        let mut attribute = SMLAttribute {
+            name: "Tags",
+            values: vec![Some("c"), None, Some("a")],
+        };
+        attribute.sort_values();
+        assert_eq!(vec![Some("a"), Some("c"), None], attribute.values);
+    }
+
+    #[test]
+    fn serializes_same_detects_changes() {
+        let mut tree = super::parse_owned(include_str!("../example.txt")).unwrap();
+        let writer = SMLWriter::new(super::parse(include_str!("../example.txt")).unwrap())
+            .with_end_keyword(Some("End"));
+        let options = writer.options();
+        let existing = SMLWriter::new(super::parse(include_str!("../example.txt")).unwrap())
+            .with_end_keyword(Some("End"))
+            .to_string()
+            .unwrap();
+
+        assert!(super::serializes_same(&tree, &existing, options).unwrap());
+
+        tree.children[0].value.attributes[0].values[0] = Some("1920".to_string());
+        assert!(!super::serializes_same(&tree, &existing, options).unwrap());
+    }
+
+    #[test]
+    fn section_headers_are_written_and_ignored_on_parse() {
+        let input = r#"
+        Root
+            Server
+                Host localhost
+                Port 8080
+                User admin
+                Password hunter2
+            End
+        End"#;
+
+        let mut comments = super::SMLComments::new();
+        comments.set_section("Root/Server", 0, "Network");
+        comments.set_section("Root/Server", 2, "Credentials");
+        let written = SMLWriter::new(super::parse(input).unwrap())
+            .with_end_keyword(Some("End"))
+            .with_comments(comments)
+            .to_string()
+            .unwrap();
+
+        assert_eq!(
+            "Root\n    Server\n        # --- Network ---\n        Host localhost \n        Port 8080 \n        # --- Credentials ---\n        User admin \n        Password hunter2 \n    End\nEnd",
+            written
+        );
+
+        let expected = SMLWriter::new(super::parse(input).unwrap())
+            .with_end_keyword(Some("End"))
+            .to_string()
+            .unwrap();
+        let reparsed = SMLWriter::new(super::parse(&written).unwrap())
+            .with_end_keyword(Some("End"))
+            .to_string()
+            .unwrap();
+        assert_eq!(expected, reparsed);
+    }
+
+    #[test]
+    fn max_attributes_per_element_is_enforced() {
+        let input = "Root\n    A 1\n    B 2\n    Child\n        C 3\n    End\n    D 4\nEnd";
+
+        let options = super::ParseOptions::new().max_attributes_per_element(3);
+        assert!(super::parse_with_options(input, &options).is_ok());
+
+        let options = super::ParseOptions::new().max_attributes_per_element(2);
+        match super::parse_with_options(input, &options) {
+            Err(super::ParseError::SML(err)) => {
+                assert_eq!(super::SMLErrorType::TooManyAttributes, err.err_type());
+                assert_eq!(6, err.line_num());
+            }
+            _ => panic!("Expected a TooManyAttributes error"),
+        }
+    }
+
+    #[test]
+    fn check_uniform_columns_finds_ragged_rows() {
+        let input = r#"
+        Table
+            Header
+                Title Scores
+            End
+            Row
+                Name Alice
+                Scores 1 2 3
+            End
+            Row
+                Name Bob
+                Scores 4 5 6
+            End
+        End"#;
+        let tree = super::parse(input).unwrap();
+        assert!(super::check_uniform_columns(&tree, "Row").is_ok());
+
+        let ragged = input.replace("Scores 4 5 6", "Scores 4 5");
+        let tree = super::parse(&ragged).unwrap();
+        let inconsistencies = super::check_uniform_columns(&tree, "Row").unwrap_err();
+        assert_eq!(1, inconsistencies.len());
+        assert_eq!(2, inconsistencies[0].child_index());
+        assert_eq!("Scores", inconsistencies[0].attribute_name());
+    }
+
+    #[test]
+    fn always_quote_quotes_every_value() {
+        let input = "Root\n    Size 1280 720\n    Title \"My Game\" -\nEnd";
+        let written = SMLWriter::new(super::parse(input).unwrap())
+            .with_end_keyword(Some("End"))
+            .always_quote()
+            .to_string()
+            .unwrap();
+        assert_eq!(
+            "Root\n    \"Size\" \"1280\" \"720\" \n    \"Title\" \"My Game\" - \nEnd",
+            written
+        );
+
+        let expected = SMLWriter::new(super::parse(input).unwrap())
+            .to_string()
+            .unwrap();
+        let reparsed = SMLWriter::new(super::parse(&written).unwrap())
+            .to_string()
+            .unwrap();
+        assert_eq!(expected, reparsed);
+    }
+
+    #[test]
+    fn check_name_shadowing_reports_collisions() {
+        let input = r#"
+        Configuration
+            Video
+                Resolution 1280 720
+                Mode windowed
+                Mode fullscreen
+                Mode
+                    Default windowed
+                End
+            End
+            Audio
+                Volume 100
+            End
+        End"#;
+        let tree = super::parse(input).unwrap();
+        let warnings = super::check_name_shadowing(&tree);
+        assert_eq!(1, warnings.len());
+        assert_eq!("Configuration/Video", warnings[0].path());
+        assert_eq!("Mode", warnings[0].name());
+
+        let tree = super::parse(include_str!("../example.txt")).unwrap();
+        assert!(super::check_name_shadowing(&tree).is_empty());
+    }
+
+    #[test]
+    fn parse_borrowed_slices_into_source() {
+        use tree_iterators_rs::prelude::BorrowedTreeNode;
+
+        let input = include_str!("../example.txt");
+        let tree = super::parse_borrowed(input).unwrap();
+        let source_range = input.as_bytes().as_ptr_range();
+        for element in tree.dfs_preorder_iter() {
+            assert!(source_range.contains(&element.name.as_ptr()));
+            for attribute in element.attributes.iter() {
+                assert!(source_range.contains(&attribute.name.as_ptr()));
+                for value in attribute.values.iter().flatten() {
+                    assert!(source_range.contains(&value.as_ptr()));
+                }
+            }
+        }
+
+        match super::parse_borrowed("Root\n    Title \"say \"\"hi\"\"\"\nEnd") {
+            Err(super::ParseError::SML(err)) => {
+                assert_eq!(super::SMLErrorType::ValueNeedsUnescaping, err.err_type());
+                assert_eq!(1, err.line_num());
+                assert_eq!(11, err.column());
+            }
+            _ => panic!("Expected a ValueNeedsUnescaping error"),
+        }
+
+        match super::parse_borrowed("Root\n    \"A\"/\"B\"\n    End\nEnd") {
+            Err(super::ParseError::SML(err)) => {
+                assert_eq!(super::SMLErrorType::ValueNeedsUnescaping, err.err_type());
+                assert_eq!(1, err.line_num());
+                assert_eq!(5, err.column());
+            }
+            _ => panic!("Expected a ValueNeedsUnescaping error"),
+        }
+    }
+
+    #[test]
+    fn parse_with_comments_captures_trailing_comments() {
+        let input = "Configuration # the root\n    # leading comment\n    Audio\n        Volume 100  # percent\n        Muted false\n    End # closes Audio\nEnd";
+        let (tree, comments) = super::parse_with_comments(input).unwrap();
+
+        assert_eq!(
+            Some(" the root"),
+            comments.trailing(super::CommentTarget::Element(0))
+        );
+        assert_eq!(None, comments.trailing(super::CommentTarget::Element(1)));
+        assert_eq!(
+            Some(" percent"),
+            comments.trailing(super::CommentTarget::Attribute {
+                element: 1,
+                attribute: 0
+            })
+        );
+        assert_eq!(
+            None,
+            comments.trailing(super::CommentTarget::Attribute {
+                element: 1,
+                attribute: 1
+            })
+        );
+
+        let written = SMLWriter::new(tree)
+            .with_end_keyword(Some("End"))
+            .with_comments(comments)
+            .to_string()
+            .unwrap();
+        assert!(written.contains("Volume 100 # percent\n"));
+    }
+
+    #[test]
+    fn substitute_env_replaces_variables() {
+        std::env::set_var("SIMPLEML_TEST_HOST", "example.com");
+        std::env::remove_var("SIMPLEML_TEST_UNDEFINED");
+
+        let input = r#"
+        Server
+            Url "https://${SIMPLEML_TEST_HOST}/api" -
+            Port ${SIMPLEML_TEST_UNDEFINED:-8080}
+        End"#;
+        let mut tree = super::parse_owned(input).unwrap();
+        super::substitute_env(&mut tree).unwrap();
+        assert_eq!(
+            vec![Some("https://example.com/api".to_string()), None],
+            tree.value.attributes[0].values
+        );
+        assert_eq!(
+            vec![Some("8080".to_string())],
+            tree.value.attributes[1].values
+        );
+
+        let mut tree = super::parse_owned(&input.replace(":-8080", "")).unwrap();
+        let err = super::substitute_env(&mut tree).unwrap_err();
+        assert_eq!("SIMPLEML_TEST_UNDEFINED", err.name());
+    }
+
+    #[test]
+    fn split_by_child_creates_standalone_documents() {
+        let tree = super::parse(include_str!("../example.txt")).unwrap();
+        let documents = super::split_by_child(&tree);
+        assert_eq!(
+            vec!["Video", "Audio", "Player"],
+            documents
+                .iter()
+                .map(|(key, _)| key.as_str())
+                .collect::<Vec<_>>()
+        );
+        for (key, document) in documents {
+            assert_eq!(key, document.value.name);
+            let written = SMLWriter::new(document).to_string().unwrap();
+            assert_eq!(key, super::parse(&written).unwrap().value.name);
+        }
+    }
+
+    #[test]
+    fn footer_stats_are_appended_as_a_comment() {
+        let written = SMLWriter::new(super::parse(include_str!("../example.txt")).unwrap())
+            .with_end_keyword(Some("End"))
+            .with_footer_stats(true)
+            .to_string()
+            .unwrap();
+        assert!(written.ends_with("\nEnd\n# generated: 4 elements, 6 attributes"));
+
+        let reparsed = super::parse(&written).unwrap();
+        assert_eq!("Configuration", reparsed.value.name);
+        assert_eq!(3, reparsed.children.len());
+    }
+
+    #[test]
+    fn align_decimal_lines_up_decimal_points() {
+        let input = "Prices\n    Apple 1.5\n    Banana 12.25\n    Cherry 100\n    Date n/a\nEnd";
+        let written = SMLWriter::new(super::parse(input).unwrap())
+            .with_end_keyword(Some("End"))
+            .align_decimal()
+            .to_string()
+            .unwrap();
+        assert_eq!(
+            "Prices\n    Apple    1.5 \n    Banana  12.25\n    Cherry 100   \n    Date      n/a\nEnd",
+            written
+        );
+        assert_eq!(
+            SMLWriter::new(super::parse(input).unwrap())
+                .to_string()
+                .unwrap(),
+            SMLWriter::new(super::parse(&written).unwrap())
+                .to_string()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn from_str_values_follows_wsv_rules() {
+        let attribute = SMLAttribute::from_str_values("Attr", "a \"b c\" - d").unwrap();
+        assert_eq!("Attr", attribute.name);
+        assert_eq!(
+            vec![
+                Some("a".to_string()),
+                Some("b c".to_string()),
+                None,
+                Some("d".to_string())
+            ],
+            attribute.values
+        );
+        assert!(SMLAttribute::from_str_values("Attr", "\"unclosed").is_err());
+    }
+
+    #[test]
+    fn warn_on_keyword_ambiguity_reports_suspicious_lines() {
+        // "end" is both the detected keyword and an attribute value.
+        let input = "Timeline\n    Clip\n        Markers start middle end\n    End\nEnd";
+        let options = super::ParseOptions::new();
+        let (_, warnings) = super::parse_with_warnings(input, &options).unwrap();
+        assert!(warnings.is_empty());
+
+        let options = options.warn_on_keyword_ambiguity(true);
+        let (tree, warnings) = super::parse_with_warnings(input, &options).unwrap();
+        assert_eq!("Timeline", tree.value.name);
+        assert_eq!(1, warnings.len());
+        assert_eq!(
+            super::ParseWarningType::AmbiguousEndKeyword,
+            warnings[0].warning_type()
+        );
+        assert_eq!(2, warnings[0].line_num());
+
+        let (_, warnings) =
+            super::parse_with_warnings(include_str!("../example.txt"), &options).unwrap();
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn value_pointers_address_every_value() {
+        let tree = super::parse(include_str!("../example.txt")).unwrap();
+        let pointers = super::value_pointers(&tree);
+        assert_eq!(7, pointers.len());
+        assert_eq!(
+            ("/Configuration/Video/Resolution/1".to_string(), Some("720")),
+            pointers[1]
+        );
+        assert_eq!(
+            ("/Configuration/Player/Name/0".to_string(), Some("Hero 123")),
+            pointers[6]
+        );
+
+        let tree = super::parse("Root\n    \"a/b~c\" -\nEnd").unwrap();
+        assert_eq!(
+            vec![("/Root/a~1b~0c/0".to_string(), None)],
+            super::value_pointers(&tree)
+        );
+    }
+
+    #[test]
+    fn apply_patch_sets_addressed_values() {
+        let mut tree = super::parse_owned(include_str!("../example.txt")).unwrap();
+        super::apply_patch(
+            &mut tree,
+            &[
+                (
+                    "/Configuration/Audio/Volume/0".to_string(),
+                    Some("50".to_string()),
+                ),
+                ("/Configuration/Audio/Music/0".to_string(), None),
+                (
+                    "/Configuration/Player/Name/1".to_string(),
+                    Some("x".to_string()),
+                ),
+            ],
+        )
+        .unwrap();
+
+        let audio = &tree.children[1].value;
+        assert_eq!(vec![Some("50".to_string())], audio.attributes[0].values);
+        assert_eq!(vec![None], audio.attributes[1].values);
+        assert_eq!(
+            vec![Some("Hero 123".to_string()), Some("x".to_string())],
+            tree.children[2].value.attributes[0].values
+        );
+
+        let err = super::apply_patch(
+            &mut tree,
+            &[
+                (
+                    "/Configuration/Audio/Volume/0".to_string(),
+                    Some("0".to_string()),
+                ),
+                (
+                    "/Configuration/Network/Port/0".to_string(),
+                    Some("80".to_string()),
+                ),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!("/Configuration/Network/Port/0", err.pointer());
+        assert_eq!(
+            Some("50".to_string()),
+            tree.children[1].value.attributes[0].values[0]
+        );
+    }
+
+    #[test]
+    fn apply_patch_rejects_indices_past_the_end() {
+        let mut tree = super::parse_owned("Root\n    Attr a\nEnd").unwrap();
+        let err = super::apply_patch(
+            &mut tree,
+            &[("/Root/Attr/4000000000".to_string(), Some("x".to_string()))],
+        )
+        .unwrap_err();
+        assert_eq!("/Root/Attr/4000000000", err.pointer());
+        assert_eq!(vec![Some("a".to_string())], tree.value.attributes[0].values);
+
+        let err = super::apply_patch(
+            &mut tree,
+            &[("/Root/New/1".to_string(), Some("x".to_string()))],
+        )
+        .unwrap_err();
+        assert_eq!("/Root/New/1", err.pointer());
+        assert_eq!(1, tree.value.attributes.len());
+
+        super::apply_patch(
+            &mut tree,
+            &[
+                ("/Root/Attr/1".to_string(), Some("b".to_string())),
+                ("/Root/Attr/2".to_string(), None),
+                ("/Root/New/0".to_string(), Some("c".to_string())),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            vec![Some("a".to_string()), Some("b".to_string()), None],
+            tree.value.attributes[0].values
+        );
+        assert_eq!(vec![Some("c".to_string())], tree.value.attributes[1].values);
+    }
+
+    #[test]
+    fn parse_markdown_blocks_parses_sml_fences() {
+        let md = r#"# Examples
+
+```sml
+Video
+    Resolution 1280 720
+End
+```
+
+Not SML:
+
+```rust
+fn main() {}
+```
+
+  ~~~~ sml title="broken"
+  Audio
+      Volume 100
+  ~~~~
+"#;
+        let results = super::parse_markdown_blocks(md);
+        assert_eq!(2, results.len());
+        assert_eq!("Video", results[0].as_ref().unwrap().value.name);
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn truncate_depth_drops_deep_elements() {
+        let tree = super::parse(include_str!("../example.txt")).unwrap();
+        let truncated = super::truncate_depth(&tree, 1);
+        assert_eq!("Configuration", truncated.value.name);
+        assert!(truncated.children.is_empty());
+
+        let truncated = super::truncate_depth(&tree, 2);
+        assert_eq!(3, truncated.children.len());
+        assert_eq!(3, truncated.children[0].value.attributes.len());
+    }
+
+    #[test]
+    fn layout_captures_per_value_quoting() {
+        let input = "Root\n    Row a \"b c\" d \"e\"\nEnd";
+        let (tree, layout) = super::parse_with_layout(input).unwrap();
+        let target = super::CommentTarget::Attribute {
+            element: 0,
+            attribute: 0,
+        };
+        assert_eq!(
+            vec![
+                (Some("a".to_string()), false),
+                (Some("b c".to_string()), true),
+                (Some("d".to_string()), false),
+                (Some("e".to_string()), true),
+            ],
+            layout.values_with_quote_flags(target, &tree.value.attributes[0])
+        );
+
+        assert_eq!(input, super::reformat_preserving(input).unwrap());
+    }
+
+    #[test]
+    fn parse_with_layout_reports_parse_errors() {
+        for input in [
+            "Root\n    Child\nEnd",
+            "Root a\nEnd",
+            "Root\n    a \"b\nEnd",
+            "",
+        ] {
+            assert_eq!(
+                format!("{:?}", super::parse(input).unwrap_err()),
+                format!("{:?}", super::parse_with_layout(input).unwrap_err())
+            );
+        }
+    }
+
+    #[test]
+    fn parse_with_comments_reports_parse_errors() {
+        for input in ["Root # a\n    Child\nEnd", "# a\nRoot a\nEnd", ""] {
+            assert_eq!(
+                format!("{:?}", super::parse(input).unwrap_err()),
+                format!("{:?}", super::parse_with_comments(input).unwrap_err())
+            );
+        }
+    }
+
+    #[test]
+    fn max_output_bytes_stops_writing() {
+        let full = SMLWriter::new(super::parse(include_str!("../example.txt")).unwrap())
+            .to_string()
+            .unwrap();
+
+        let exact = SMLWriter::new(super::parse(include_str!("../example.txt")).unwrap())
+            .max_output_bytes(full.len())
+            .to_string()
+            .unwrap();
+        assert_eq!(full, exact);
+
+        assert!(matches!(
+            SMLWriter::new(super::parse(include_str!("../example.txt")).unwrap())
+                .max_output_bytes(32)
+                .to_string(),
+            Err(super::SMLWriterError::OutputTooLarge)
+        ));
+    }
+
+    #[test]
+    fn reformat_preserving_keeps_tab_indentation() {
+        let input = "Configuration\n\tVideo\n\t\tResolution 1280  720\n\t\tRefreshRate\t60\n\tEnd\n\tAudio\n\tEnd\nEnd";
+        assert_eq!(input, super::reformat_preserving(input).unwrap());
+    }
+
+    #[test]
+    fn reformat_preserving_keeps_space_indentation() {
+        let input = "Configuration\n  Video\n      Resolution 1280 720\n  End\n  Audio\n     Volume   100\n   End\nEnd";
+        assert_eq!(input, super::reformat_preserving(input).unwrap());
+    }
+
+    #[test]
+    fn detect_end_keyword_reads_last_line() {
+        assert_eq!(None, super::detect_end_keyword("Root\n    Name Hero\n-\n"));
+        assert_eq!(
+            Some("End".to_string()),
+            super::detect_end_keyword(include_str!("../example.txt"))
+        );
+        assert_eq!(
+            Some("Done".to_string()),
+            super::detect_end_keyword("Root\nDone # closes the root\n\n# trailing comment\n")
+        );
+        assert_eq!(None, super::detect_end_keyword("  \n# only a comment\n"));
+    }
+
+    #[test]
+    fn iter_leaves_yields_childless_elements() {
+        let tree = super::parse(include_str!("../example.txt")).unwrap();
+        assert_eq!(
+            vec!["Video", "Audio", "Player"],
+            super::iter_leaves(&tree)
+                .map(|leaf| leaf.value.name.as_ref())
+                .collect::<Vec<_>>()
+        );
+
+        let tree =
+            super::parse("Root\n    A\n        B\n        End\n    End\n    C\n    End\nEnd")
+                .unwrap();
+        assert_eq!(
+            vec!["B", "C"],
+            super::iter_leaves(&tree)
+                .map(|leaf| leaf.value.name.as_ref())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn blank_lines_between_top_level_children() {
+        let input = "Root\n    A\n        X\n        End\n        Y\n        End\n    End\n    B\n    End\nEnd";
+        let written = SMLWriter::new(super::parse(input).unwrap())
+            .with_end_keyword(Some("End"))
+            .blank_lines_between(super::BlankLinePolicy::TopLevelOnly)
+            .to_string()
+            .unwrap();
+        assert_eq!(
+            "Root\n    A\n        X\n        End\n        Y\n        End\n    End\n\n    B\n    End\nEnd",
+            written
+        );
+
+        let written = SMLWriter::new(super::parse(input).unwrap())
+            .with_end_keyword(Some("End"))
+            .blank_lines_between(super::BlankLinePolicy::AllLevels)
+            .to_string()
+            .unwrap();
+        assert_eq!(
+            "Root\n    A\n        X\n        End\n\n        Y\n        End\n    End\n\n    B\n    End\nEnd",
+            written
+        );
+        assert_eq!(2, super::parse(&written).unwrap().children.len());
+    }
+
+    #[test]
+    fn parse_rootless_wraps_sibling_elements() {
+        let input = "Video\n    Resolution 1280 720\nEnd\nAudio\n    Volume 100\nEnd";
+        let tree = super::parse_rootless(input, "Snippet").unwrap();
+        assert_eq!("Snippet", tree.value.name);
+        assert_eq!(2, tree.children.len());
+        assert_eq!("Video", tree.children[0].value.name);
+        assert_eq!("Audio", tree.children[1].value.name);
+        assert_eq!(1, tree.children[1].value.attributes.len());
+
+        match super::parse_rootless("Video\n    - 1\nEnd", "Snippet") {
+            Err(super::ParseError::SML(err)) => {
+                assert_eq!(
+                    super::SMLErrorType::NullValueAsAttributeName,
+                    err.err_type()
+                );
+                assert_eq!(1, err.line_num());
+                assert_eq!(5, err.column());
+            }
+            _ => panic!("Expected a NullValueAsAttributeName error"),
+        }
+    }
+
+    #[test]
+    fn normalize_nulls_follows_policy() {
+        let input = "Root\n    Values \"\" - a\nEnd";
+
+        let mut tree = super::parse_owned(input).unwrap();
+        super::normalize_nulls(&mut tree, super::NullPolicy::EmptyToNull);
+        assert_eq!(
+            vec![None, None, Some("a".to_string())],
+            tree.value.attributes[0].values
+        );
+
+        let mut tree = super::parse_owned(input).unwrap();
+        super::normalize_nulls(&mut tree, super::NullPolicy::NullToEmpty);
+        assert_eq!(
+            vec![
+                Some(String::new()),
+                Some(String::new()),
+                Some("a".to_string())
+            ],
+            tree.value.attributes[0].values
+        );
+
+        let mut tree = super::parse_owned(input).unwrap();
+        super::normalize_nulls(&mut tree, super::NullPolicy::Keep);
+        assert_eq!(
+            vec![Some(String::new()), None, Some("a".to_string())],
+            tree.value.attributes[0].values
+        );
+    }
+
+    #[test]
+    fn continuation_char_joins_lines() {
+        let input = "Root\n    Values 1 2 \\\n        3 4\\\n        \"5\\\"\n    Other a\nEnd";
+        let options = super::ParseOptions::new().continuation_char(Some('\\'));
+        let tree = super::parse_with_options(input, &options).unwrap();
+
+        assert_eq!(2, tree.value.attributes.len());
+        let values = &tree.value.attributes[0];
+        assert_eq!("Values", values.name);
+        assert_eq!(
+            vec![Some("1"), Some("2"), Some("3"), Some("4"), Some("5\\")],
+            values
+                .values
+                .iter()
+                .map(|val| val.as_deref())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!("Other", tree.value.attributes[1].name);
+
+        // Off by default, so the marker is an ordinary value.
+        let tree = super::parse(input);
+        assert!(tree.is_err());
+
+        // Element names and lone markers aren't attribute lines.
+        let input = "Root\n    Child\\\n        Size 1\n    End\n    Name \\\nEnd";
+        let tree = super::parse_with_options(input, &options).unwrap();
+        assert_eq!("Child\\", tree.children[0].value.name);
+        assert_eq!("Size", tree.children[0].value.attributes[0].name);
+        assert_eq!(
+            vec![Some("\\")],
+            tree.value.attributes[0]
+                .values
+                .iter()
+                .map(|val| val.as_deref())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn parses_crlf_line_endings() {
+        use tree_iterators_rs::prelude::BorrowedTreeNode;
+
+        let input = include_str!("../example.txt")
+            .replace("\r\n", "\n")
+            .replace('\n', "\r\n");
+        let tree = super::parse(&input).unwrap();
+
+        for element in tree.dfs_preorder_iter() {
+            assert!(!element.name.contains('\r'));
+            for attribute in element.attributes.iter() {
+                assert!(!attribute.name.contains('\r'));
+                for value in attribute.values.iter().flatten() {
+                    assert!(!value.contains('\r'));
+                }
+            }
+        }
+
+        assert_eq!("Configuration", tree.value.name);
+        let video = &tree.children[0].value;
+        assert_eq!("Video", video.name);
+        assert_eq!(
+            vec![Some("1280"), Some("720")],
+            video.attributes[0]
+                .values
+                .iter()
+                .map(|val| val.as_deref())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some("Hero 123"),
+            tree.children[2].value.attributes[0].values[0].as_deref()
+        );
+    }
+
+    #[test]
+    fn parse_reader_matches_parse_owned() {
+        let input = include_str!("../example.txt");
+        let tree = super::parse_reader(input.as_bytes()).unwrap();
+        let expected = super::parse_owned(input).unwrap();
+        assert_eq!(
+            SMLWriter::new(expected).to_string().unwrap(),
+            SMLWriter::new(tree).to_string().unwrap()
+        );
+
+        let tree = super::parse_reader("Ünïcödé\n    Näme \"✓ 1\"\nEnd".as_bytes()).unwrap();
+        assert_eq!("Ünïcödé", tree.value.name);
+        assert_eq!(Some("✓ 1"), tree.value.attributes[0].values[0].as_deref());
+
+        let input = "Root\n    Child\n    End\n    Child\nEnd";
+        let expected = match super::parse(input) {
+            Err(super::ParseError::SML(err)) => err,
+            _ => panic!("Expected an SML error"),
+        };
+        match super::parse_reader(input.as_bytes()) {
+            Err(super::ParseError::SML(err)) => {
+                assert_eq!(expected.err_type(), err.err_type());
+                assert_eq!(expected.line_num(), err.line_num());
+                assert_eq!(expected.column(), err.column());
+                assert_eq!(expected.byte_offset(), err.byte_offset());
+            }
+            _ => panic!("Expected an SML error"),
+        }
+
+        match super::parse_reader(&b"Root\n    Name \xFF\nEnd"[..]) {
+            Err(super::ParseError::IO(err)) => {
+                assert_eq!(std::io::ErrorKind::InvalidData, err.kind())
+            }
+            _ => panic!("Expected an IO error"),
+        }
+    }
+
+    #[test]
+    fn errors_report_byte_offset() {
+        let input =
+            "Root\n    Child\n        Name a\n        Size 1 2 - \n        - 5\n    End\nEnd";
+        let err = match super::parse(input) {
+            Err(super::ParseError::SML(err)) => err,
+            _ => panic!("Expected an SML error"),
+        };
+        assert_eq!(
+            super::SMLErrorType::NullValueAsAttributeName,
+            err.err_type()
+        );
+        assert_eq!(4, err.line_num());
+        assert_eq!(9, err.column());
+        assert_eq!(input.find("- 5").unwrap(), err.byte_offset());
+
+        let err = match super::parse_rootless("Name a\n  - 5\nEnd", "Root") {
+            Err(super::ParseError::SML(err)) => err,
+            _ => panic!("Expected an SML error"),
+        };
+        assert_eq!(1, err.line_num());
+        assert_eq!(9, err.byte_offset());
+    }
+
+    #[test]
+    fn parse_errors_work_with_std_error() {
+        use std::error::Error;
+
+        let err = super::parse("Root\n    Name \"unclosed\nEnd").unwrap_err();
+        assert!(matches!(err, super::ParseError::WSV(_)));
+        let source = err.source().unwrap();
+        assert!(source.is::<whitespacesv::WSVError>());
+        assert_eq!(source.to_string(), err.to_string());
+
+        let err = super::parse("Root\n    - 5\nEnd").unwrap_err();
+        assert!(err.source().is_none());
+        assert_eq!("(line: 1) Null Value as Attribute Name", err.to_string());
+
+        let boxed: Box<dyn Error> = Box::new(err);
+        assert!(boxed.downcast_ref::<super::ParseError>().is_some());
+    }
+
+    #[test]
+    fn dedup_values_keeps_first_occurrence_and_nulls() {
+        let mut attribute = SMLAttribute {
+            name: "Tags",
+            values: vec![Some("a"), Some("b"), Some("a")],
+        };
+        attribute.dedup_values();
+        assert_eq!(vec![Some("a"), Some("b")], attribute.values);
+
+        let mut attribute = SMLAttribute {
+            name: "Tags",
+            values: vec![None, Some("b"), None, Some("c"), Some("b")],
+        };
+        attribute.dedup_values();
+        assert_eq!(vec![None, Some("b"), None, Some("c")], attribute.values);
+    }
+
+    #[test]
+    fn get_attribute_finds_by_name() {
+        let tree = super::parse("Root\n    Tag a\n    Size 1\n    Tag b\n    tag c\nEnd").unwrap();
+
+        let size = tree.value.get_attribute("Size").unwrap();
+        assert_eq!(
+            vec![Some("1")],
+            size.values
+                .iter()
+                .map(|val| val.as_deref())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some("a"),
+            tree.value.get_attribute("Tag").unwrap().values[0].as_deref()
+        );
+        assert!(tree.value.get_attribute("Missing").is_none());
+
+        let tags = tree
+            .value
+            .get_attributes("Tag")
+            .map(|attr| attr.values[0].as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![Some("a"), Some("b")], tags);
+    }
+
+    #[test]
+    fn annotate_pairs_elements_with_metadata() {
+        let tree = super::parse(include_str!("../example.txt")).unwrap();
+        let mut annotated = super::annotate(&tree, |depth, _, _| depth);
+        assert_eq!(0, annotated.annotation);
+        assert_eq!(3, annotated.children.len());
+        assert_eq!(
+            vec![
+                ("Configuration", 0),
+                ("Video", 1),
+                ("Audio", 1),
+                ("Player", 1)
+            ],
+            annotated
+                .iter()
+                .map(|(element, depth)| (element.name.as_ref(), *depth))
+                .collect::<Vec<_>>()
+        );
+
+        for (_, depth) in annotated.iter_mut() {
+            *depth += 10;
+        }
+        assert_eq!(11, annotated.children[2].annotation);
+        assert_eq!("Player", annotated.children[2].element.name);
+
+        // Elements that share a name still get their own depth and path.
+        let tree = super::parse(
+            "Item\n    Item\n        Item\n        End\n    End\n    Item\n    End\nEnd",
+        )
+        .unwrap();
+        let annotated = super::annotate(&tree, |depth, path, _| (depth, path.to_string()));
+        assert_eq!(
+            vec![
+                (0, "Item".to_string()),
+                (1, "Item/Item".to_string()),
+                (2, "Item/Item/Item".to_string()),
+                (1, "Item/Item".to_string()),
+            ],
+            annotated
+                .iter()
+                .map(|(_, annotation)| annotation.clone())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn get_attribute_ignore_case_folds_names() {
+        let tree = super::parse("Root\n    REFRESHRATE 60\n    Straße a\n    refreshrate 30\nEnd")
+            .unwrap();
+
+        let attribute = tree.value.get_attribute_ignore_case("RefreshRate").unwrap();
+        assert_eq!("REFRESHRATE", attribute.name);
+        assert!(tree.value.get_attribute("RefreshRate").is_none());
+
+        assert_eq!(
+            "Straße",
+            tree.value.get_attribute_ignore_case("STRAßE").unwrap().name
+        );
+        assert!(tree.value.get_attribute_ignore_case("Missing").is_none());
+    }
+
+    #[test]
+    fn find_child_ignore_case_folds_names() {
+        let tree = super::parse(include_str!("../example.txt")).unwrap();
+
+        let audio = super::find_child_ignore_case(&tree, "AUDIO").unwrap();
+        assert_eq!("Audio", audio.value.name);
+        assert!(super::find_child(&tree, |element| element.name == "AUDIO").is_none());
+        assert!(super::find_child_ignore_case(&tree, "Network").is_none());
+    }
+
+    #[test]
+    fn check_unique_siblings_finds_duplicates() {
+        let tree = super::parse(include_str!("../example.txt")).unwrap();
+        assert!(super::check_unique_siblings(&tree).is_ok());
+
+        let input = r#"
+        Configuration
+            Video
+                Resolution 1280 720
+            End
+            Video
+                Resolution 640 480
+            End
+            Player
+                Slot
+                End
+                Slot
+                End
+                Slot
+                End
+            End
+        End"#;
+        let tree = super::parse(input).unwrap();
+        let duplicates = super::check_unique_siblings(&tree).unwrap_err();
+        assert_eq!(2, duplicates.len());
+        assert_eq!("Configuration", duplicates[0].path());
+        assert_eq!("Video", duplicates[0].name());
+        assert_eq!("Configuration/Player", duplicates[1].path());
+        assert_eq!("Slot", duplicates[1].name());
+    }
+
+    #[test]
+    fn compact_simple_leaves_outdents_leaves_and_drops_padding() {
+        let input = "Settings\n    Volume\n        Level 80\n    End\n    Name\n        Value \"Hero 123\"\n    End\n    Size\n        Value 1 2\n    End\nEnd";
+        let written = SMLWriter::new(super::parse(input).unwrap())
+            .with_end_keyword(Some("End"))
+            .compact_simple_leaves()
+            .to_string()
+            .unwrap();
+
+        assert_eq!(
+            "Settings\n    Volume\n    Level 80\n    End\n    Name\n    Value \"Hero 123\"\n    End\n    Size\n        Value 1 2\n    End\nEnd",
+            written
+        );
+        let default = SMLWriter::new(super::parse(input).unwrap())
+            .with_end_keyword(Some("End"))
+            .to_string()
+            .unwrap();
+        assert!(written.len() < default.len());
+
+        let expected = SMLWriter::new(super::parse(input).unwrap())
+            .to_string()
+            .unwrap();
+        let reparsed = SMLWriter::new(super::parse(&written).unwrap())
+            .to_string()
+            .unwrap();
+        assert_eq!(expected, reparsed);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serializable_tree_round_trips_through_json() {
+        let tree = super::parse_owned(
+            "Root\n    Values a - \"b c\"\n    Child\n        Size 1\n    End\nEnd",
+        )
+        .unwrap();
+        let json = serde_json::to_string(&super::SerializableTree::from(tree)).unwrap();
+        assert_eq!(
+            r#"{"name":"Root","attributes":[{"name":"Values","values":["a",null,"b c"]}],"children":[{"name":"Child","attributes":[{"name":"Size","values":["1"]}],"children":[]}]}"#,
+            json
+        );
+
+        let tree: super::TreeNode<SMLElement<String>> =
+            serde_json::from_str::<super::SerializableTree<String>>(&json)
+                .unwrap()
+                .into();
+        assert_eq!("Child", tree.children[0].value.name);
+        assert_eq!(
+            vec![Some("a".to_string()), None, Some("b c".to_string())],
+            tree.value.attributes[0].values
+        );
+    }
+
+    #[test]
+    fn parse_lines_matches_parse() {
+        let input = include_str!("../example.txt");
+        let lines = input.lines().collect::<Vec<_>>();
+        let tree = super::parse_lines(lines.into_iter()).unwrap();
+        assert_eq!(
+            SMLWriter::new(super::parse(input).unwrap())
+                .to_string()
+                .unwrap(),
+            SMLWriter::new(tree).to_string().unwrap()
+        );
+
+        let lines = vec![
+            "Root",
+            "    Child",
+            "        Name \"a\"\"b\" -",
+            "",
+            "    End",
+            "End",
+        ];
+        let tree = super::parse_lines(lines.into_iter()).unwrap();
+        assert_eq!(
+            vec![Some("a\"b"), None],
+            tree.children[0].value.attributes[0]
+                .values
+                .iter()
+                .map(|val| val.as_deref())
+                .collect::<Vec<_>>()
+        );
+
+        let lines = vec!["Root", "    Child", "        - 5", "    End", "End"];
+        let expected = match super::parse(&lines.join("\n")) {
+            Err(super::ParseError::SML(err)) => err,
+            _ => panic!("Expected an SML error"),
+        };
+        match super::parse_lines(lines.into_iter()) {
+            Err(super::ParseError::SML(err)) => {
+                assert_eq!(expected.err_type(), err.err_type());
+                assert_eq!(2, err.line_num());
+                assert_eq!(expected.column(), err.column());
+                assert_eq!(expected.byte_offset(), err.byte_offset());
+            }
+            _ => panic!("Expected an SML error"),
+        }
+
+        let lines = vec!["Root", "    Name \"unclosed", "End"];
+        match super::parse_lines(lines.into_iter()) {
+            Err(super::ParseError::WSV(err)) => assert_eq!(2, err.location().line()),
+            _ => panic!("Expected a WSV error"),
+        }
+    }
+
+    #[test]
+    fn find_child_and_find_element_match_predicates() {
+        let tree = super::parse(include_str!("../example.txt")).unwrap();
+
+        let video = super::find_child(&tree, |element| {
+            element.get_attribute("Fullscreen").is_some()
+        })
+        .unwrap();
+        assert_eq!("Video", video.value.name);
+        assert!(super::find_child(&tree, |element| element.name == "Configuration").is_none());
+
+        let input = "Root\n    Group\n        Item\n            Id 41\n        End\n        Item\n            Id 42\n        End\n    End\nEnd";
+        let tree = super::parse(input).unwrap();
+        let found = super::find_element(&tree, |element| {
+            element
+                .get_attribute("Id")
+                .is_some_and(|id| id.values[0].as_deref() == Some("42"))
+        })
+        .unwrap();
+        assert!(std::ptr::eq(found, &tree.children[0].children[1]));
+        assert!(super::find_element(&tree, |element| element.name == "Root").is_some());
+        assert!(super::find_element(&tree, |element| element.name == "Missing").is_none());
+    }
+
+    #[test]
+    fn deterministic_output_ignores_order() {
+        let inputs = [
+            "Root\n    B 2\n    A 1 -\n    Item\n        Id 2\n    End\n    Item\n        Id 1\n    End\n    Audio\n    End\nEnd",
+            "Root\n    A 1 -\n    B 2\n    Audio\n    End\n    Item\n        Id 1\n    End\n    Item\n        Id 2\n    End\nEnd",
+            "Root\n    Item\n        Id 1\n    End\n    B 2\n    Audio\n    End\n    A 1 -\n    Item\n        Id 2\n    End\nEnd",
+        ];
+
+        let written = inputs
+            .iter()
+            .map(|input| {
+                SMLWriter::new(super::parse(input).unwrap())
+                    .with_end_keyword(Some("End"))
+                    .align_columns(whitespacesv::ColumnAlignment::Right)
+                    .deterministic()
+                    .to_string()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            "Root\n    A 1 -\n    B 2\n    Audio\n    End\n    Item\n        Id 1\n    End\n    Item\n        Id 2\n    End\nEnd",
+            written[0]
+        );
+        assert!(written.iter().all(|output| output == &written[0]));
+    }
+
+    #[test]
+    fn deterministic_keeps_other_options_and_drops_comments() {
+        let input = "Root\n    B 2 # second\n    A 1\nEnd";
+        let (tree, comments) = super::parse_with_comments(input).unwrap();
+        let before = SMLWriter::new(super::borrow_tree(&tree))
+            .with_comments(comments.clone())
+            .line_ending(super::LineEnding::CrLf)
+            .header_comment("generated")
+            .deterministic()
+            .to_string()
+            .unwrap();
+        let after = SMLWriter::new(super::borrow_tree(&tree))
+            .deterministic()
+            .with_comments(comments)
+            .line_ending(super::LineEnding::CrLf)
+            .header_comment("generated")
+            .to_string()
+            .unwrap();
+        assert_eq!("# generated\r\nRoot\r\n    A 1 \r\n    B 2 \r\n-", before);
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn parse_with_comments_round_trips_line_comments() {
+        let input = "# Game settings\nConfiguration\n    # Display\n    Video\n        # width height\n        Resolution 1280 720 # pixels\n        # Fullscreen true\n    End\n\n    # Sound\n    Audio\n        Volume 100\n    End\nEnd";
+        let (tree, comments) = super::parse_with_comments(input).unwrap();
+
+        let leading = comments.leading(super::CommentTarget::Element(0));
+        assert_eq!(1, leading.len());
+        assert_eq!(" Game settings", leading[0].text());
+        assert_eq!(Some(0), leading[0].line_num());
+        let target = super::CommentTarget::Attribute {
+            element: 1,
+            attribute: 0,
+        };
+        assert_eq!(" width height", comments.leading(target)[0].text());
+        assert_eq!(Some(4), comments.leading(target)[0].line_num());
+        assert_eq!(Some(" pixels"), comments.trailing(target));
+        assert_eq!(" Fullscreen true", comments.before_end(1)[0].text());
+        assert_eq!(
+            " Sound",
+            comments.leading(super::CommentTarget::Element(2))[0].text()
+        );
+
+        let written = SMLWriter::new(tree)
+            .with_end_keyword(Some("End"))
+            .with_comments(comments)
+            .to_string()
+            .unwrap();
+        assert_eq!(
+            "# Game settings\nConfiguration\n    # Display\n    Video\n        # width height\n        Resolution 1280 720 # pixels\n        # Fullscreen true\n    End\n    # Sound\n    Audio\n        Volume 100 \n    End\nEnd",
+            written
+        );
+
+        let mut comments = super::SMLComments::new();
+        comments.push_leading(super::CommentTarget::Element(0), " added");
+        assert_eq!(
+            None,
+            comments.leading(super::CommentTarget::Element(0))[0].line_num()
+        );
+    }
+
+    #[test]
+    fn trailing_newline_appends_one_line_break() {
+        let input = "Root\n    Size 1\nEnd";
+        let written = SMLWriter::new(super::parse(input).unwrap())
+            .with_end_keyword(Some("End"))
+            .trailing_newline(true)
+            .trailing_newline(true)
+            .to_string()
+            .unwrap();
+        assert_eq!("Root\n    Size 1 \nEnd\n", written);
+
+        let written = SMLWriter::new(super::parse(input).unwrap())
+            .with_end_keyword(Some("End"))
+            .trailing_newline(true)
+            .trailing_newline(false)
+            .to_string()
+            .unwrap();
+        assert_eq!("Root\n    Size 1 \nEnd", written);
+    }
+
+    #[test]
+    fn line_ending_controls_line_breaks() {
+        let input = "# header\nRoot\n    Size 1 # note\n    Child\n    End\nEnd";
+        let write = |ending| {
+            let (tree, comments) = super::parse_with_comments(input).unwrap();
+            SMLWriter::new(tree)
+                .with_end_keyword(Some("End"))
+                .with_comments(comments)
+                .blank_lines_between(super::BlankLinePolicy::AllLevels)
+                .with_footer_stats(true)
+                .trailing_newline(true)
+                .line_ending(ending)
+                .to_string()
+                .unwrap()
+        };
+
+        let lf = "# header\nRoot\n    Size 1 # note\n    Child\n    End\nEnd\n# generated: 2 elements, 1 attribute\n";
+        assert_eq!(lf.as_bytes(), write(super::LineEnding::Lf).as_bytes());
+        assert_eq!(
+            lf.replace('\n', "\r\n").as_bytes(),
+            write(super::LineEnding::CrLf).as_bytes()
+        );
+    }
+
+    #[test]
+    fn indent_with_exact_requires_homogeneous_indent() {
+        let tree = || super::parse("Root\n    Child\n    End\nEnd").unwrap();
+
+        let written = SMLWriter::new(tree())
+            .indent_with_exact("\t")
+            .unwrap()
+            .to_string()
+            .unwrap();
+        assert_eq!("Root\n\tChild\n\t-\n-", written);
+        assert!(SMLWriter::new(tree()).indent_with_exact("  ").is_some());
+        assert!(SMLWriter::new(tree()).indent_with_exact("").is_some());
+
+        assert!(SMLWriter::new(tree()).indent_with_exact("\t  ").is_none());
+        assert!(SMLWriter::new(tree())
+            .indent_with_exact("\u{3000}")
+            .is_none());
+        assert!(SMLWriter::new(tree()).indent_with_exact("ab").is_none());
+        assert!(SMLWriter::new(tree()).indent_with("\t  ").is_some());
+    }
+
+    #[test]
+    fn iter_with_depth_yields_preorder_depths() {
+        let tree = super::parse(include_str!("../example.txt")).unwrap();
+        assert_eq!(
+            vec![
+                (0, "Configuration"),
+                (1, "Video"),
+                (1, "Audio"),
+                (1, "Player")
+            ],
+            super::iter_with_depth(&tree)
+                .map(|(depth, element)| (depth, element.name.as_ref()))
+                .collect::<Vec<_>>()
+        );
+
+        let tree =
+            super::parse("A\n    B\n        C\n        End\n    End\n    D\n    End\nEnd").unwrap();
+        assert_eq!(
+            vec![0, 1, 2, 1],
+            super::iter_with_depth(&tree)
+                .map(|(depth, _)| depth)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn is_end_keyword_matches_to_lowercase() {
+        for (value, keyword) in [
+            ("END", "end"),
+            ("eNd", "end"),
+            ("Ende", "end"),
+            ("İ", "i̇"),
+            ("ΟΔΟΣ", "οδος"),
+            ("ΟΔΟΣ", "οδoς"),
+            ("ΟΔΟΣ", "οδος".replace('ς', "σ").as_str()),
+        ] {
+            assert_eq!(
+                value.to_lowercase() == keyword,
+                super::is_end_keyword(Some(value), Some(keyword))
+            );
+        }
+        assert!(super::is_end_keyword(None, None));
+        assert!(!super::is_end_keyword(Some("end"), None));
+        assert!(!super::is_end_keyword(None, Some("end")));
+
+        let tree = super::parse("Root\n    Child\n    ΤΕΛΟΣ\nτελος").unwrap();
+        assert_eq!("Child", tree.children[0].value.name);
+    }
+
+    #[test]
+    fn element_builder_builds_example() {
+        use super::SMLElementBuilder;
+
+        let tree = SMLElementBuilder::new("Configuration")
+            .child(
+                SMLElementBuilder::new("Video")
+                    .attr("Resolution", ["1280", "720"])
+                    .attr("RefreshRate", [String::from("60")])
+                    .attr("Fullscreen", ["true"]),
+            )
+            .child(
+                SMLElementBuilder::new("Audio")
+                    .attr("Volume", ["100"])
+                    .attr("Music", ["80"]),
+            )
+            .child(
+                SMLElementBuilder::new("Player")
+                    .attr("Name", ["Hero 123"])
+                    .build(),
+            )
+            .build();
+
+        let expected = super::parse_owned(include_str!("../example.txt")).unwrap();
+        assert_eq!(super::to_json(&expected), super::to_json(&tree));
+    }
+
+    #[test]
+    fn element_builder_writes_nulls() {
+        use super::SMLElementBuilder;
+
+        let tree = SMLElementBuilder::new("Root")
+            .attr_with_nulls("Values", [Some("a"), None])
+            .build();
+        assert_eq!(
+            "Root\n    Values a - \n-",
+            SMLWriter::new(tree).to_string().unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_many_splits_documents() {
+        let input =
+            "First\n    Value 1\nEnd\n\nSecond\n    Child\n        Value 2\n    -\n-\nThird\nEND";
+        let trees = super::parse_many(input).unwrap();
+        assert_eq!(3, trees.len());
+        assert_eq!("First", trees[0].value.name);
+        assert_eq!("Second", trees[1].value.name);
+        assert_eq!("Child", trees[1].children[0].value.name);
+        assert_eq!("Value", trees[1].children[0].value.attributes[0].name);
+        assert_eq!("Third", trees[2].value.name);
+    }
+
+    #[test]
+    fn parse_many_matches_parse_for_single_document() {
+        let input = include_str!("../example.txt");
+        let trees = super::parse_many(input).unwrap();
+        assert_eq!(1, trees.len());
+        assert_eq!(
+            super::to_json(&super::parse(input).unwrap()),
+            super::to_json(&trees[0])
+        );
+
+        // Child could close Root on line 1 as well, but the latest line wins.
+        let trees = super::parse_many("Root\n    Child\n    End\nEnd").unwrap();
+        assert_eq!(1, trees.len());
+        assert_eq!("Child", trees[0].children[0].value.name);
+    }
+
+    #[test]
+    fn parse_many_reports_errors_at_source_lines() {
+        let input = "First\nEnd\nSecond\n    Child\nEnd\n    - a\nEnd";
+        match super::parse_many(input) {
+            Err(super::ParseError::SML(err)) => {
+                assert_eq!(super::SMLErrorType::NullValueAsAttributeName, err.err_type);
+                assert_eq!(5, err.line_num);
+            }
+            other => panic!("Expected an SML error, got {:?}", other),
+        }
+
+        match super::parse_many("First\nEnd\nSecond\n    Child a") {
+            Err(super::ParseError::SML(err)) => {
+                assert_eq!(super::SMLErrorType::RootNotClosed, err.err_type);
+            }
+            other => panic!("Expected an SML error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_collect_errors_collects_recoverable_errors() {
+        use super::{ParseError, SMLErrorType};
+
+        let input = "Root\n    - a\n    -\n        Value 1\n    End\n    Name b\nEnd\nExtra c\nEnd";
+        let (tree, errors) = super::parse_collect_errors(input);
+        let errors = errors
+            .into_iter()
+            .map(|err| match err {
+                ParseError::SML(err) => (err.err_type, err.line_num),
+                other => panic!("Expected an SML error, got {:?}", other),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (SMLErrorType::NullValueAsAttributeName, 1),
+                (SMLErrorType::NullValueAsElementName, 2),
+                (SMLErrorType::OnlyOneRootElementAllowed, 7),
+                (SMLErrorType::OnlyOneRootElementAllowed, 8),
+            ],
+            errors
+        );
+
+        let tree = tree.unwrap();
+        assert_eq!("Root", tree.value.name);
+        assert_eq!("Name", tree.value.attributes[0].name);
+        assert_eq!("", tree.children[0].value.name);
+        assert_eq!("Value", tree.children[0].value.attributes[0].name);
+    }
+
+    #[test]
+    fn parse_collect_errors_stops_at_unrecoverable_errors() {
+        let (tree, errors) = super::parse_collect_errors("Root\n    - a\n    Child\nEnd");
+        assert!(tree.is_none());
+        assert_eq!(2, errors.len());
+        assert!(errors[1].is_same_kind(&super::parse("Root\nChild\nEnd").unwrap_err()));
+
+        let (tree, errors) = super::parse_collect_errors(include_str!("../example.txt"));
+        assert!(tree.is_some());
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn parse_reports_null_element_names() {
+        match super::parse("Root\n    -\nEnd") {
+            Err(super::ParseError::SML(err)) => {
+                assert_eq!(super::SMLErrorType::NullValueAsElementName, err.err_type);
+                assert_eq!(1, err.line_num);
+            }
+            other => panic!("Expected an SML error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn always_quote_values_quotes_values_only() {
+        let input = "Root\n    Size 1280 720\n    Title \"Say \"\"Hi\"\"\" -\nEnd";
+        let written = SMLWriter::new(super::parse(input).unwrap())
+            .with_end_keyword(Some("End"))
+            .always_quote_values(true)
+            .to_string()
+            .unwrap();
+        assert_eq!(
+            "Root\n    Size \"1280\" \"720\" \n    Title \"Say \"\"Hi\"\"\" - \nEnd",
+            written
+        );
+        let reparsed = super::parse(&written).unwrap();
+        assert_eq!(
+            "Say \"Hi\"",
+            reparsed.value.attributes[1].values[0].as_deref().unwrap()
+        );
+        assert_eq!(None, reparsed.value.attributes[1].values[1]);
+
+        let unquoted = SMLWriter::new(super::parse(input).unwrap())
+            .always_quote_values(true)
+            .always_quote_values(false)
+            .to_string()
+            .unwrap();
+        assert_eq!(
+            SMLWriter::new(super::parse(input).unwrap())
+                .to_string()
+                .unwrap(),
+            unquoted
+        );
+    }
+
+    #[test]
+    fn parse_with_metadata_captures_end_keyword() {
+        let input = "Root\n    Child\n        Value 1\n    FIN\nFin\n";
+        let (tree, metadata) = super::parse_with_metadata(input).unwrap();
+        assert_eq!(Some("Fin"), metadata.end_keyword());
+        assert_eq!(6, metadata.line_count());
+
+        let written = SMLWriter::new(tree)
+            .with_end_keyword(metadata.end_keyword())
+            .to_string()
+            .unwrap();
+        assert_eq!("Root\n    Child\n        Value 1 \n    Fin\nFin", written);
+
+        let (_, metadata) = super::parse_with_metadata("Root\n-").unwrap();
+        assert_eq!(None, metadata.end_keyword());
+        assert_eq!(2, metadata.line_count());
+    }
+
+    #[test]
+    fn non_null_values_skips_nulls() {
+        let tree = super::parse("Root\n    Values a - \"b c\" -\nEnd").unwrap();
+        let attribute = &tree.value.attributes[0];
+        assert_eq!(
+            vec!["a", "b c"],
+            attribute.non_null_values().collect::<Vec<_>>()
+        );
+        assert_eq!(4, attribute.value_count());
+        assert_eq!(2, attribute.null_count());
+    }
+
+    #[test]
+    fn typed_getters_parse_values() {
+        let tree = super::parse(include_str!("../example.txt")).unwrap();
+        let video = &tree.children[0].value;
+        let resolution = video.get_attribute("Resolution").unwrap();
+        assert_eq!(Some(Ok(720)), resolution.get_i64(1));
+        assert_eq!(None, resolution.get_i64(2));
+        let refresh_rate = video.get_attribute("RefreshRate").unwrap();
+        assert_eq!(Some(Ok(60.0)), refresh_rate.get_f64(0));
+        let fullscreen = video.get_attribute("Fullscreen").unwrap();
+        assert_eq!(Some(Ok(true)), fullscreen.get_bool(0));
+        assert!(matches!(fullscreen.get_i64(0), Some(Err(_))));
+
+        let tree = super::parse("Root\n    Flags FALSE True - yes\nEnd").unwrap();
+        let flags = &tree.value.attributes[0];
+        assert_eq!(Some(Ok(false)), flags.get_bool(0));
+        assert_eq!(Some(Ok(true)), flags.get_bool(1));
+        assert_eq!(None, flags.get_bool(2));
+        assert!(matches!(flags.get_bool(3), Some(Err(_))));
+    }
+
+    #[test]
+    fn writer_escapes_element_names() {
+        let tree = super::SMLElementBuilder::new("Root")
+            .child(super::SMLElementBuilder::new("Two\nLines").attr("Line\nBreak", ["a\nb"]))
+            .child(super::SMLElementBuilder::new("Has Space"))
+            .child(super::SMLElementBuilder::new("-"))
+            .build();
+        let written = SMLWriter::new(tree)
+            .with_end_keyword(Some("End"))
+            .to_string()
+            .unwrap();
+        assert_eq!(
+            "Root\n    \"Two\"/\"Lines\"\n        \"Line\"/\"Break\" \"a\"/\"b\" \n    End\n    \"Has Space\"\n    End\n    \"-\"\n    End\nEnd",
+            written
+        );
+
+        let reparsed = super::parse_owned(&written).unwrap();
+        assert_eq!("Two\nLines", reparsed.children[0].value.name);
+        assert_eq!("Line\nBreak", reparsed.children[0].value.attributes[0].name);
+        assert_eq!("Has Space", reparsed.children[1].value.name);
+        assert_eq!("-", reparsed.children[2].value.name);
+    }
+
+    #[test]
+    fn blank_line_between_siblings_respects_threshold() {
+        let input = "Root\n    A\n        X\n        End\n        Y\n        End\n    End\n    B\n    End\nEnd";
+        let write = |threshold| {
+            SMLWriter::new(super::parse(input).unwrap())
+                .with_end_keyword(Some("End"))
+                .blank_line_between_siblings(threshold)
+                .to_string()
+                .unwrap()
+        };
+
+        assert_eq!(
+            SMLWriter::new(super::parse(input).unwrap())
+                .with_end_keyword(Some("End"))
+                .to_string()
+                .unwrap(),
+            write(0)
+        );
+        assert_eq!(
+            "Root\n    A\n        X\n        End\n        Y\n        End\n    End\n\n    B\n    End\nEnd",
+            write(1)
+        );
+        let written = write(2);
+        assert_eq!(
+            "Root\n    A\n        X\n        End\n\n        Y\n        End\n    End\n\n    B\n    End\nEnd",
+            written
+        );
+        assert_eq!(
+            SMLWriter::new(super::parse(&written).unwrap())
+                .with_end_keyword(Some("End"))
+                .to_string()
+                .unwrap(),
+            write(0)
+        );
+    }
+
+    #[test]
+    fn parse_events_reports_structure() {
+        use super::SmlEvent;
+        use std::borrow::Cow;
+        use std::ops::ControlFlow;
+
+        let mut events = Vec::new();
+        super::parse_events("Root\n    Name a -\n    Child\n    End\nEnd", |event| {
+            events.push(event);
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert_eq!(
+            vec![
+                SmlEvent::ElementStart(Cow::Borrowed("Root")),
+                SmlEvent::Attribute(Cow::Borrowed("Name"), vec![Some(Cow::Borrowed("a")), None]),
+                SmlEvent::ElementStart(Cow::Borrowed("Child")),
+                SmlEvent::ElementEnd,
+                SmlEvent::ElementEnd,
+            ],
+            events
+        );
+    }
+
+    #[test]
+    fn parse_events_stops_early() {
+        use std::ops::ControlFlow;
+
+        let mut count = 0;
+        let input = include_str!("../example.txt");
+        super::parse_events(input, |event| {
+            count += 1;
+            match event {
+                super::SmlEvent::ElementStart(name) if name == "Audio" => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            }
+        })
+        .unwrap();
+        assert_eq!(7, count);
+
+        // Nothing after the break is checked.
+        super::parse_events("Root\n    Stop\n    - a\nEnd", |event| match event {
+            super::SmlEvent::ElementStart(name) if name == "Stop" => ControlFlow::Break(()),
+            _ => ControlFlow::Continue(()),
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn parse_events_matches_parse_errors() {
+        for input in [
+            "",
+            "Root\n    - a\nEnd",
+            "Root\n    Value \"unclosed\nEnd",
+            "Root\n    Child\nEnd",
+            "Root a\nEnd",
+        ] {
+            let expected = super::parse(input).unwrap_err();
+            let actual =
+                super::parse_events(input, |_| std::ops::ControlFlow::Continue(())).unwrap_err();
+            assert_eq!(format!("{}", expected), format!("{}", actual));
+        }
+    }
+
+    #[test]
+    fn truncated_document_reports_root_not_closed() {
+        let input = "Configuration\n    Video\n        Resolution 1280 720\n    End\n    Audio\n        Volume 100\n    End\n";
+        for result in [
+            super::parse(input).map(|_| ()),
+            super::parse_events(input, |_| std::ops::ControlFlow::Continue(())),
+        ] {
+            match result {
+                Err(super::ParseError::SML(err)) => {
+                    assert_eq!(super::SMLErrorType::RootNotClosed, err.err_type());
+                    assert_eq!(6, err.line_num());
+                    assert_eq!(5, err.column());
                 }
-                3 => {
-                    assert_eq!("Player", element.name);
-                    assert_eq!(1, element.attributes.len());
-                    let attr = element.attributes.get(0).unwrap();
-                    assert_eq!("Name", attr.name);
-                    assert_eq!(1, attr.values.len());
-                    assert_eq!(
-                        "Hero 123",
-                        attr.values.get(0).as_ref().unwrap().as_ref().unwrap()
-                    );
+                other => panic!("Expected an SML error, got {:?}", other),
+            }
+        }
+
+        let (tree, errors) = super::parse_collect_errors(input);
+        assert!(tree.is_none());
+        assert_eq!(1, errors.len());
+    }
+
+    #[test]
+    fn single_token_documents_are_errors() {
+        for (input, err_type) in [
+            ("End", super::SMLErrorType::RootNotClosed),
+            ("  End  ", super::SMLErrorType::RootNotClosed),
+            ("\n\nEnd\n", super::SMLErrorType::RootNotClosed),
+            ("-", super::SMLErrorType::NullValueAsElementName),
+            ("\"\"", super::SMLErrorType::RootNotClosed),
+            ("End End", super::SMLErrorType::InvalidRootElementStart),
+            ("#End", super::SMLErrorType::EndKeywordNotDetected),
+        ] {
+            match super::parse(input) {
+                Err(super::ParseError::SML(err)) => {
+                    assert_eq!(err_type, err.err_type(), "{:?}", input)
                 }
-                _ => panic!("Should only have 4 sub-elements"),
+                other => panic!("Expected an SML error for {:?}, got {:?}", input, other),
             }
         }
     }
 
     #[test]
-    fn test_write() {
-        let input = include_str!("../example.txt");
-        println!(
-            "{}",
-            super::SMLWriter::new(super::parse(input).unwrap())
-                .align_columns(whitespacesv::ColumnAlignment::Right)
-                .indent_with(" ")
-                .unwrap()
-                .to_string()
-                .unwrap()
+    fn max_depth_rejects_deep_trees() {
+        let deep = || {
+            let mut tree = super::SMLElementBuilder::new("Leaf").attr("Value", ["1"]);
+            for _ in 0..3 {
+                tree = super::SMLElementBuilder::new("Level").child(tree);
+            }
+            tree.build()
+        };
+
+        let expected = SMLWriter::new(deep()).to_string().unwrap();
+        assert_eq!(
+            expected,
+            SMLWriter::new(deep()).max_depth(3).to_string().unwrap()
         );
+        assert!(matches!(
+            SMLWriter::new(deep()).max_depth(2).to_string(),
+            Err(super::SMLWriterError::MaxDepthExceeded)
+        ));
+        assert!(matches!(
+            SMLWriter::new(deep())
+                .max_depth(2)
+                .deterministic()
+                .to_string(),
+            Err(super::SMLWriterError::MaxDepthExceeded)
+        ));
     }
 
     #[test]
-    fn readme_example() {
-        use tree_iterators_rs::prelude::*;
+    fn parse_with_limits_rejects_large_documents() {
+        use super::{parse_with_limits, ParseError, ParseLimits, SMLErrorType};
 
-        // Build up our value set
-        let my_sml_values = TreeNode {
-            value: SMLElement {
-                name: "Configuration",
-                attributes: Vec::with_capacity(0),
-            },
-            children: vec![
-                TreeNode {
-                    value: SMLElement {
-                        name: "Video",
-                        attributes: vec![
-                            SMLAttribute {
-                                name: "Resolution",
-                                values: vec![Some("1280"), Some("720")],
-                            },
-                            SMLAttribute {
-                                name: "RefreshRate",
-                                values: vec![Some("60")],
-                            },
-                            SMLAttribute {
-                                name: "Fullscreen",
-                                values: vec![Some("true")],
-                            },
-                        ],
-                    },
-                    children: Vec::new(),
-                },
-                TreeNode {
-                    value: SMLElement {
-                        name: "Audio",
-                        attributes: vec![
-                            SMLAttribute {
-                                name: "Volume",
-                                values: vec![Some("100")],
-                            },
-                            SMLAttribute {
-                                name: "Music",
-                                values: vec![Some("80")],
-                            },
-                        ],
-                    },
-                    children: Vec::new(),
-                },
-                TreeNode {
-                    value: SMLElement {
-                        name: "Player",
-                        attributes: vec![SMLAttribute {
-                            name: "Name",
-                            values: vec![Some("Hero 123")],
-                        }],
-                    },
-                    children: Vec::new(),
-                },
-            ],
+        let input = "Root\n    A\n        B\n        End\n    End\n    C\n    End\nEnd";
+        let unlimited = parse_with_limits(input, ParseLimits::default()).unwrap();
+        let expected = super::to_json(&super::parse(input).unwrap());
+        assert_eq!(expected, super::to_json(&unlimited));
+
+        let fits = ParseLimits {
+            max_depth: Some(2),
+            max_elements: Some(4),
         };
+        assert_eq!(
+            expected,
+            super::to_json(&parse_with_limits(input, fits).unwrap())
+        );
 
-        // actually write the values
-        let str = SMLWriter::new(my_sml_values)
-            // Setting up a custom end keyword
-            .with_end_keyword(Some("my_custom_end_keyword"))
-            // Using 8 spaces as the indent string. The default is 4 spaces.
-            .indent_with("        ")
-            .unwrap()
-            // Align the WSV tables to the right.
+        let expect_error = |limits, err_type, line_num| match parse_with_limits(input, limits) {
+            Err(ParseError::SML(err)) => {
+                assert_eq!(err_type, err.err_type());
+                assert_eq!(line_num, err.line_num());
+            }
+            other => panic!("expected {:?}, got {:?}", err_type, other),
+        };
+        expect_error(
+            ParseLimits {
+                max_depth: Some(1),
+                max_elements: None,
+            },
+            SMLErrorType::MaxDepthExceeded,
+            2,
+        );
+        expect_error(
+            ParseLimits {
+                max_depth: None,
+                max_elements: Some(3),
+            },
+            SMLErrorType::MaxElementsExceeded,
+            5,
+        );
+        expect_error(
+            ParseLimits {
+                max_depth: None,
+                max_elements: Some(0),
+            },
+            SMLErrorType::MaxElementsExceeded,
+            0,
+        );
+    }
+
+    #[test]
+    fn minified_writes_minimal_whitespace() {
+        let input = include_str!("../example.txt");
+        let minified = SMLWriter::new(super::parse(input).unwrap())
             .align_columns(whitespacesv::ColumnAlignment::Right)
+            .blank_lines_between(super::BlankLinePolicy::AllLevels)
+            .minified()
             .to_string()
             .unwrap();
+        assert_eq!(
+            "Configuration\nVideo\nResolution 1280 720\nRefreshRate 60\nFullscreen true\n-\nAudio\nVolume 100\nMusic 80\n-\nPlayer\nName \"Hero 123\"\n-\n-",
+            minified
+        );
+        assert_eq!(
+            super::to_json(&super::parse(input).unwrap()),
+            super::to_json(&super::parse(&minified).unwrap())
+        );
 
-        /// Result:
-        /// Configuration
-        ///         Video
-        ///                  Resolution 1280 720
-        ///                 RefreshRate   60
-        ///                  Fullscreen true
-        ///         my_custom_end_keyword
-        ///         Audio
-        ///                 Volume 100
-        ///                  Music  80
-        ///         my_custom_end_keyword
-        ///         Player
-        ///                 Name "Hero 123"
-        ///         my_custom_end_keyword
-        /// my_custom_end_keyword
-        println!("{}", str);
+        let end_first = SMLWriter::new(super::parse(input).unwrap())
+            .with_end_keyword(Some("End"))
+            .minified()
+            .to_string()
+            .unwrap();
+        let end_last = SMLWriter::new(super::parse(input).unwrap())
+            .minified()
+            .with_end_keyword(Some("End"))
+            .to_string()
+            .unwrap();
+        assert_eq!(end_first, end_last);
+        assert_eq!(minified.replace('-', "End"), end_first);
     }
 
     #[test]
-    fn parses_input_with_strange_end_keyword() {
-        let input = r#"
-        Configuration
-                Video
-                        Resolution 1280 720
-                        RefreshRate   60
-                        Fullscreen true
-                "End with spaces"
-                Audio
-                        Volume 100
-                        Music  80
-                "End with spaces"
-                Player
-                        Name "Hero 123"
-                "End with spaces"
-        "End with spaces""#;
+    fn header_comment_is_written_before_root() {
+        let input = "Root\n    Value 1\nEnd";
+        let written = SMLWriter::new(super::parse(input).unwrap())
+            .header_comment("Generated by my-tool\n\ndo not edit")
+            .line_ending(super::LineEnding::CrLf)
+            .to_string()
+            .unwrap();
+        assert_eq!(
+            "# Generated by my-tool\r\n#\r\n# do not edit\r\nRoot\r\n    Value 1 \r\n-",
+            written
+        );
+        assert_eq!(
+            super::to_json(&super::parse(input).unwrap()),
+            super::to_json(&super::parse(&written).unwrap())
+        );
+    }
 
-        println!("{:?}", super::parse(input).unwrap());
+    #[test]
+    fn select_walks_element_and_attribute_paths() {
+        use super::{select, Selection};
+
+        let input = "Root\n    Item\n        Id 1\n    End\n    Other\n    End\n    Item\n        Id 2\n        Id 3\n    End\nEnd";
+        let tree = super::parse(input).unwrap();
+
+        let items = select(&tree, "Root/Item");
+        assert_eq!(2, items.len());
+        assert!(items
+            .iter()
+            .all(|item| matches!(item, Selection::Element(element) if element.name == "Item")));
+
+        let ids = select(&tree, "Root/Item@Id")
+            .into_iter()
+            .map(|selection| match selection {
+                Selection::Attribute(attribute) => attribute.values[0].as_deref(),
+                Selection::Element(_) => panic!("expected an attribute"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(vec![Some("1"), Some("2"), Some("3")], ids);
+
+        assert!(matches!(
+            select(&tree, "Root").as_slice(),
+            [Selection::Element(_)]
+        ));
+        assert!(select(&tree, "root/Item").is_empty());
+        assert!(select(&tree, "Root/Missing").is_empty());
+        assert!(select(&tree, "Root/Other@Id").is_empty());
     }
 
     #[test]
-    fn parses_input_with_strange_end_keyword_owned() {
-        let input = r#"
-        Configuration
-                Video
-                        Resolution 1280 720
-                        RefreshRate   60
-                        Fullscreen true
-                "End with spaces"
-                Audio
-                        Volume 100
-                        Music  80
-                "End with spaces"
-                Player
-                        Name "Hero 123"
-                "End with spaces"
-        "End with spaces""#;
+    fn set_and_remove_attribute_edit_in_place() {
+        let mut tree = super::parse_owned("Root\n    A 1\n    B 2\n    A 3\nEnd").unwrap();
+        let root = &mut tree.value;
 
-        println!("{:?}", super::parse_owned(input).unwrap());
+        root.set_attribute("A", vec![Some("x".to_string()), None]);
+        root.set_attribute("C", vec![Some("4".to_string())]);
+        let attributes = root
+            .attributes
+            .iter()
+            .map(|attr| (attr.name.as_str(), attr.values.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("A", vec![Some("x".to_string()), None]),
+                ("B", vec![Some("2".to_string())]),
+                ("A", vec![Some("3".to_string())]),
+                ("C", vec![Some("4".to_string())]),
+            ],
+            attributes
+        );
+
+        assert!(root.remove_attribute("A"));
+        assert!(!root.remove_attribute("b"));
+        let names = root
+            .attributes
+            .iter()
+            .map(|attr| attr.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["B", "A", "C"], names);
     }
 
     #[test]
-    fn parses_input_with_null_end_keyword() {
-        let input = r#"
-        Configuration
-                Video
-                        Resolution 1280 720
-                        RefreshRate   60
-                        Fullscreen true
-                -
-                Audio
-                        Volume 100
-                        Music  80
-                -
-                Player
-                        Name "Hero 123"
-                -
-        -"#;
+    fn trim_values_trims_non_null_values() {
+        let input = "Root\n    \" Name \" \" a b \" \"\u{3000}\" - c\nEnd";
+        let written = SMLWriter::new(super::parse(input).unwrap())
+            .trim_values(true)
+            .to_string()
+            .unwrap();
+        assert_eq!("Root\n    \" Name \" \"a b\" \"\" - c \n-", written);
 
-        println!("{:?}", super::parse(input).unwrap());
+        let untrimmed = SMLWriter::new(super::parse(input).unwrap())
+            .to_string()
+            .unwrap();
+        assert_eq!(
+            "Root\n    \" Name \" \" a b \" \"\u{3000}\" - c \n-",
+            untrimmed
+        );
     }
 
     #[test]
-    fn doesnt_crash_bad_input() {
-        let str = r#"Value1
-            Configuration 2 0 2
-            Otherval 1
-            InnerEl
-        -"#;
+    fn writer_options_are_reusable() {
+        let options = super::SMLWriterOptions::default()
+            .with_end_keyword(Some("End"))
+            .indent_with("  ")
+            .unwrap();
+        let input = include_str!("../example.txt");
 
-        super::parse(str).ok();
+        let first = SMLWriter::with_options(super::parse(input).unwrap(), options.clone())
+            .to_string()
+            .unwrap();
+        let second = SMLWriter::with_options(super::parse(input).unwrap(), options)
+            .to_string()
+            .unwrap();
+        let configured = SMLWriter::new(super::parse(input).unwrap())
+            .with_end_keyword(Some("End"))
+            .indent_with("  ")
+            .unwrap();
+        let copied = configured.options().clone();
+        assert_eq!(first, configured.to_string().unwrap());
+        assert_eq!(first, second);
+        assert_eq!(
+            first,
+            SMLWriter::with_options(super::parse(input).unwrap(), copied)
+                .to_string()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn template_renders_same_output_as_writer() {
+        let input = include_str!("../example.txt");
+        let (tree, comments) = super::parse_with_comments(input).unwrap();
+        let writer = || {
+            SMLWriter::new(super::borrow_tree(&tree))
+                .with_comments(comments.clone())
+                .with_end_keyword(Some("End"))
+                .align_columns(whitespacesv::ColumnAlignment::Left)
+                .with_footer_stats(true)
+        };
+        let expected = writer().to_string().unwrap();
+
+        let template = super::SmlTemplate::new(writer()).unwrap();
+        for _ in 0..3 {
+            assert_eq!(expected, template.render().unwrap());
+        }
+    }
+
+    #[test]
+    fn template_substitutes_and_realigns_values() {
+        let tree = super::parse(
+            "Root\n    Size 1 2\n    Name Hero\n    Child\n        Id 7\n    End\nEnd",
+        )
+        .unwrap();
+        let template = super::SmlTemplate::new(
+            SMLWriter::new(tree).align_columns(whitespacesv::ColumnAlignment::Left),
+        )
+        .unwrap();
+        assert_eq!(4, template.value_count());
+
+        let values = [Some("1024"), Some("768"), Some("Jane Doe"), None];
+        let expected = SMLWriter::new(
+            super::parse("Root\n    Size 1024 768\n    Name \"Jane Doe\"\n    Child\n        Id -\n    End\nEnd")
+                .unwrap(),
+        )
+        .align_columns(whitespacesv::ColumnAlignment::Left)
+        .to_string()
+        .unwrap();
+        assert_eq!(expected, template.render_with(&values).unwrap());
+
+        assert!(matches!(
+            template.render_with(&[Some("1")]),
+            Err(super::SMLWriterError::TemplateValueCount {
+                expected: 4,
+                found: 1
+            })
+        ));
     }
 }
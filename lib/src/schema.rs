@@ -0,0 +1,363 @@
+use std::{collections::BTreeMap, error::Error, fmt::Display};
+use tree_iterators_rs::prelude::TreeNode;
+
+use crate::{parse_owned, ParseError, SMLElement};
+
+/// Parses the Simple Markup Language text and validates it against the
+/// schema in a single call. On success, the returned [ValidatedTree]
+/// proves that the document conformed to the schema.
+pub fn parse_validated(
+    source_text: &str,
+    schema: &SmlSchema,
+) -> Result<ValidatedTree, ParseOrSchemaError> {
+    let tree = match parse_owned(source_text) {
+        Err(err) => return Err(ParseOrSchemaError::Parse(err)),
+        Ok(tree) => tree,
+    };
+
+    match schema.validate(&tree) {
+        Err(errors) => Err(ParseOrSchemaError::Schema(errors)),
+        Ok(()) => Ok(ValidatedTree { tree }),
+    }
+}
+
+/// Checks that every element named element_name has all of the given
+/// attributes. This is a shortcut for an [SmlSchema] with a single
+/// [ElementSchema::require_attribute] rule per attribute.
+pub fn require_attributes<StrAsRef>(
+    tree: &TreeNode<SMLElement<StrAsRef>>,
+    element_name: &str,
+    attributes: &[&str],
+) -> Result<(), Vec<MissingAttribute>>
+where
+    StrAsRef: AsRef<str>,
+{
+    let element = attributes
+        .iter()
+        .fold(ElementSchema::new(), |element, name| {
+            element.require_attribute(name)
+        });
+    let schema = SmlSchema::new().element(element_name, element);
+
+    schema.validate(tree).map_err(|errors| {
+        errors
+            .into_iter()
+            .filter_map(|err| match err.err_type {
+                SchemaErrorType::MissingAttribute(name) => Some(MissingAttribute {
+                    path: err.path,
+                    name,
+                }),
+                _ => None,
+            })
+            .collect()
+    })
+}
+
+/// An attribute that was missing from an element during
+/// [require_attributes].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingAttribute {
+    path: String,
+    name: String,
+}
+
+impl MissingAttribute {
+    /// The slash-separated path of the element missing the attribute.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// A description of the structure an SML document is expected to have.
+/// Elements that the schema has no rules for are unconstrained.
+#[derive(Debug, Clone, Default)]
+pub struct SmlSchema {
+    root: Option<String>,
+    elements: BTreeMap<String, ElementSchema>,
+}
+
+impl SmlSchema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requires the root element to have the given name.
+    pub fn root(mut self, name: &str) -> Self {
+        self.root = Some(name.to_string());
+        self
+    }
+
+    /// Sets the rules for every element with the given name.
+    pub fn element(mut self, name: &str, element: ElementSchema) -> Self {
+        self.elements.insert(name.to_string(), element);
+        self
+    }
+
+    /// Validates the tree against this schema, returning every
+    /// violation that was found.
+    pub fn validate<StrAsRef>(
+        &self,
+        tree: &TreeNode<SMLElement<StrAsRef>>,
+    ) -> Result<(), Vec<SchemaError>>
+    where
+        StrAsRef: AsRef<str>,
+    {
+        let mut errors = Vec::new();
+        if let Some(root) = self.root.as_ref() {
+            if tree.value.name.as_ref() != root {
+                errors.push(SchemaError {
+                    path: tree.value.name.as_ref().to_string(),
+                    err_type: SchemaErrorType::UnexpectedRoot(root.clone()),
+                });
+            }
+        }
+
+        self.validate_element(tree, tree.value.name.as_ref().to_string(), &mut errors);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn validate_element<StrAsRef>(
+        &self,
+        tree: &TreeNode<SMLElement<StrAsRef>>,
+        path: String,
+        errors: &mut Vec<SchemaError>,
+    ) where
+        StrAsRef: AsRef<str>,
+    {
+        let rules = self.elements.get(tree.value.name.as_ref());
+        if let Some(rules) = rules {
+            for required in rules.required_attributes.iter() {
+                if !tree
+                    .value
+                    .attributes
+                    .iter()
+                    .any(|attr| attr.name.as_ref() == required)
+                {
+                    errors.push(SchemaError {
+                        path: path.clone(),
+                        err_type: SchemaErrorType::MissingAttribute(required.clone()),
+                    });
+                }
+            }
+        }
+
+        let allowed_children = rules.and_then(|rules| rules.allowed_children.as_ref());
+        for child in tree.children.iter() {
+            let child_path = path.clone() + "/" + child.value.name.as_ref();
+            if let Some(allowed_children) = allowed_children {
+                if !allowed_children
+                    .iter()
+                    .any(|allowed| allowed == child.value.name.as_ref())
+                {
+                    errors.push(SchemaError {
+                        path: child_path.clone(),
+                        err_type: SchemaErrorType::UnexpectedChild(
+                            child.value.name.as_ref().to_string(),
+                        ),
+                    });
+                }
+            }
+            self.validate_element(child, child_path, errors);
+        }
+    }
+}
+
+/// The rules for a single element name within an [SmlSchema].
+#[derive(Debug, Clone, Default)]
+pub struct ElementSchema {
+    required_attributes: Vec<String>,
+    allowed_children: Option<Vec<String>>,
+}
+
+impl ElementSchema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requires the element to have at least one attribute with this name.
+    pub fn require_attribute(mut self, name: &str) -> Self {
+        self.required_attributes.push(name.to_string());
+        self
+    }
+
+    /// Allows the element to have children with this name. Once any child
+    /// is allowed, children with names that weren't allowed are errors.
+    /// If this is never called, any children are allowed.
+    pub fn allow_child(mut self, name: &str) -> Self {
+        self.allowed_children
+            .get_or_insert_with(Vec::new)
+            .push(name.to_string());
+        self
+    }
+}
+
+/// A parsed tree that is known to conform to the [SmlSchema] it was
+/// validated against. The only way to construct one is through
+/// [parse_validated], so functions that take a &ValidatedTree can rely
+/// on the document being valid.
+#[derive(Debug)]
+pub struct ValidatedTree {
+    tree: TreeNode<SMLElement<String>>,
+}
+
+impl ValidatedTree {
+    /// Gets the validated tree.
+    pub fn tree(&self) -> &TreeNode<SMLElement<String>> {
+        &self.tree
+    }
+
+    /// Consumes the wrapper, returning the validated tree.
+    pub fn into_tree(self) -> TreeNode<SMLElement<String>> {
+        self.tree
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaError {
+    path: String,
+    err_type: SchemaErrorType,
+}
+
+impl SchemaError {
+    /// The slash-separated path of the offending element.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    pub fn err_type(&self) -> &SchemaErrorType {
+        &self.err_type
+    }
+}
+
+impl Error for SchemaError {}
+impl Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "(path: {}) ", self.path)?;
+        match &self.err_type {
+            SchemaErrorType::UnexpectedRoot(expected) => {
+                write!(f, "Unexpected Root (expected {})", expected)?
+            }
+            SchemaErrorType::MissingAttribute(name) => write!(f, "Missing Attribute {}", name)?,
+            SchemaErrorType::UnexpectedChild(name) => write!(f, "Unexpected Child {}", name)?,
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaErrorType {
+    /// The root element's name didn't match. Contains the expected name.
+    UnexpectedRoot(String),
+    /// A required attribute was missing. Contains the attribute name.
+    MissingAttribute(String),
+    /// A child element's name wasn't allowed. Contains the child's name.
+    UnexpectedChild(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum ParseOrSchemaError {
+    Parse(ParseError),
+    Schema(Vec<SchemaError>),
+}
+
+impl Error for ParseOrSchemaError {}
+impl Display for ParseOrSchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseOrSchemaError::Parse(err) => err.fmt(f)?,
+            ParseOrSchemaError::Schema(errors) => {
+                for (i, err) in errors.iter().enumerate() {
+                    if i != 0 {
+                        writeln!(f)?;
+                    }
+                    err.fmt(f)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_validated, require_attributes, ElementSchema, ParseOrSchemaError, SchemaErrorType,
+        SmlSchema,
+    };
+
+    fn example_schema() -> SmlSchema {
+        SmlSchema::new()
+            .root("Configuration")
+            .element(
+                "Configuration",
+                ElementSchema::new()
+                    .allow_child("Video")
+                    .allow_child("Audio")
+                    .allow_child("Player"),
+            )
+            .element(
+                "Video",
+                ElementSchema::new()
+                    .require_attribute("Resolution")
+                    .require_attribute("RefreshRate"),
+            )
+    }
+
+    #[test]
+    fn parse_validated_accepts_conformant_document() {
+        let validated = parse_validated(include_str!("../example.txt"), &example_schema()).unwrap();
+        assert_eq!("Configuration", validated.tree().value.name);
+        assert_eq!(3, validated.into_tree().children.len());
+    }
+
+    #[test]
+    fn parse_validated_rejects_nonconformant_document() {
+        let input = r#"
+        Configuration
+            Video
+                Resolution 1280 720
+            End
+            Network
+            End
+        End"#;
+
+        match parse_validated(input, &example_schema()) {
+            Err(ParseOrSchemaError::Schema(errors)) => {
+                assert_eq!(2, errors.len());
+                assert_eq!("Configuration/Video", errors[0].path());
+                assert_eq!(
+                    &SchemaErrorType::MissingAttribute("RefreshRate".to_string()),
+                    errors[0].err_type()
+                );
+                assert_eq!("Configuration/Network", errors[1].path());
+                assert_eq!(
+                    &SchemaErrorType::UnexpectedChild("Network".to_string()),
+                    errors[1].err_type()
+                );
+            }
+            _ => panic!("Expected schema errors"),
+        }
+    }
+
+    #[test]
+    fn require_attributes_reports_missing() {
+        let input = include_str!("../example.txt");
+        let required = ["Resolution", "RefreshRate", "Fullscreen"];
+        let tree = crate::parse(input).unwrap();
+        assert!(require_attributes(&tree, "Video", &required).is_ok());
+
+        let input = input.replace("Fullscreen true", "");
+        let tree = crate::parse(&input).unwrap();
+        let missing = require_attributes(&tree, "Video", &required).unwrap_err();
+        assert_eq!(1, missing.len());
+        assert_eq!("Configuration/Video", missing[0].path());
+        assert_eq!("Fullscreen", missing[0].name());
+    }
+}
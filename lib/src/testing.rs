@@ -0,0 +1,219 @@
+use std::{error::Error, fmt::Display};
+
+use tree_iterators_rs::prelude::TreeNode;
+
+use crate::{borrow_tree, parse_with_metadata, ParseError, SMLElement, SMLWriter, SMLWriterError};
+
+/// Checks that the source text survives a round trip. The text is parsed,
+/// written back out with [SMLWriter] using the end keyword it was written
+/// with, and parsed again. The two trees must then be identical, with
+/// every element, attribute, and value in the same order. Only available
+/// with the "testing" feature.
+pub fn assert_roundtrips(source_text: &str) -> Result<(), RoundtripDiff> {
+    let (original, metadata) = parse_with_metadata(source_text).map_err(|err| RoundtripDiff {
+        path: Vec::new(),
+        kind: Box::new(RoundtripDiffKind::Parse(err)),
+    })?;
+
+    let written = SMLWriter::new(borrow_tree(&original))
+        .with_end_keyword(metadata.end_keyword())
+        .to_string()
+        .map_err(|err| RoundtripDiff {
+            path: Vec::new(),
+            kind: Box::new(RoundtripDiffKind::Write(err)),
+        })?;
+
+    let rewritten = crate::parse_owned(&written).map_err(|err| RoundtripDiff {
+        path: Vec::new(),
+        kind: Box::new(RoundtripDiffKind::Reparse { written, err }),
+    })?;
+
+    let mut path = Vec::new();
+    match compare(&original, &rewritten, &mut path) {
+        None => Ok(()),
+        Some(kind) => Err(RoundtripDiff {
+            path,
+            kind: Box::new(kind),
+        }),
+    }
+}
+
+/// Compares the trees in preorder, returning the first difference. path
+/// is left holding the names of the elements leading to it.
+fn compare<StrAsRef>(
+    original: &TreeNode<SMLElement<StrAsRef>>,
+    rewritten: &TreeNode<SMLElement<String>>,
+    path: &mut Vec<String>,
+) -> Option<RoundtripDiffKind>
+where
+    StrAsRef: AsRef<str>,
+{
+    path.push(original.value.name.as_ref().to_string());
+    if original.value.name.as_ref() != rewritten.value.name {
+        return Some(RoundtripDiffKind::ElementName {
+            rewritten: rewritten.value.name.clone(),
+        });
+    }
+
+    let original_attributes = &original.value.attributes;
+    let rewritten_attributes = &rewritten.value.attributes;
+    for i in 0..original_attributes.len().max(rewritten_attributes.len()) {
+        let (original, rewritten) = match (original_attributes.get(i), rewritten_attributes.get(i))
+        {
+            (Some(original), Some(rewritten)) => (original, rewritten),
+            _ => {
+                return Some(RoundtripDiffKind::AttributeCount {
+                    original: original_attributes.len(),
+                    rewritten: rewritten_attributes.len(),
+                })
+            }
+        };
+        let original_values = original
+            .values
+            .iter()
+            .map(|value| value.as_ref().map(|value| value.as_ref().to_string()))
+            .collect::<Vec<_>>();
+        if original.name.as_ref() != rewritten.name || original_values != rewritten.values {
+            return Some(RoundtripDiffKind::Attribute {
+                index: i,
+                original: (original.name.as_ref().to_string(), original_values),
+                rewritten: (rewritten.name.clone(), rewritten.values.clone()),
+            });
+        }
+    }
+
+    if original.children.len() != rewritten.children.len() {
+        return Some(RoundtripDiffKind::ChildCount {
+            original: original.children.len(),
+            rewritten: rewritten.children.len(),
+        });
+    }
+    for (original, rewritten) in original.children.iter().zip(rewritten.children.iter()) {
+        if let Some(kind) = compare(original, rewritten, path) {
+            return Some(kind);
+        }
+    }
+
+    path.pop();
+    None
+}
+
+/// The first difference found by [assert_roundtrips].
+#[derive(Debug, Clone)]
+pub struct RoundtripDiff {
+    path: Vec<String>,
+    kind: Box<RoundtripDiffKind>,
+}
+
+impl RoundtripDiff {
+    /// Gets the names of the elements from the root to the element that
+    /// differs. This is empty if the text couldn't be parsed or written.
+    pub fn path(&self) -> &[String] {
+        &self.path
+    }
+
+    /// Gets how the element differs.
+    pub fn kind(&self) -> &RoundtripDiffKind {
+        &self.kind
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum RoundtripDiffKind {
+    /// The source text couldn't be parsed.
+    Parse(ParseError),
+    /// The parsed tree couldn't be written.
+    Write(SMLWriterError),
+    /// The written text couldn't be parsed.
+    Reparse { written: String, err: ParseError },
+    /// The element's name changed.
+    ElementName { rewritten: String },
+    /// The element has a different number of attributes.
+    AttributeCount { original: usize, rewritten: usize },
+    /// The attribute at index has a different name or values.
+    #[allow(clippy::type_complexity)]
+    Attribute {
+        index: usize,
+        original: (String, Vec<Option<String>>),
+        rewritten: (String, Vec<Option<String>>),
+    },
+    /// The element has a different number of children.
+    ChildCount { original: usize, rewritten: usize },
+}
+
+impl Error for RoundtripDiff {}
+impl Display for RoundtripDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.path.is_empty() {
+            write!(f, "{}: ", self.path.join("/"))?;
+        }
+        match self.kind.as_ref() {
+            RoundtripDiffKind::Parse(err) => write!(f, "Failed to parse: {}", err),
+            RoundtripDiffKind::Write(err) => write!(f, "Failed to write: {}", err),
+            RoundtripDiffKind::Reparse { written, err } => {
+                write!(f, "Failed to parse the written text: {}\n{}", err, written)
+            }
+            RoundtripDiffKind::ElementName { rewritten } => {
+                write!(f, "Element was renamed to {:?}", rewritten)
+            }
+            RoundtripDiffKind::AttributeCount {
+                original,
+                rewritten,
+            } => write!(f, "Expected {} attributes, found {}", original, rewritten),
+            RoundtripDiffKind::Attribute {
+                index,
+                original,
+                rewritten,
+            } => write!(
+                f,
+                "Attribute {} changed from {:?} to {:?}",
+                index, original, rewritten
+            ),
+            RoundtripDiffKind::ChildCount {
+                original,
+                rewritten,
+            } => write!(f, "Expected {} children, found {}", original, rewritten),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assert_roundtrips, RoundtripDiffKind};
+
+    #[test]
+    fn example_roundtrips() {
+        assert_roundtrips(include_str!("../example.txt")).unwrap();
+    }
+
+    #[test]
+    fn tricky_values_roundtrip() {
+        for input in [
+            "Root\n    Empty \"\"\n    Dash \"-\"\n    Null -\nEnd",
+            "Root\n    Quote \"a\"\"b\" \"c d\"\n    Hash \"#1\"\nEnd",
+            "Root\n    Newline \"a\"/\"b\"\n    Unicode ÄÖÜ \"\u{3000}\"\nEnd",
+            "Root\n    Child\n    -\n-",
+            "\"Root Element\"\n    \"Child\"/\"Element\"\n    End\nEnd",
+        ] {
+            if let Err(err) = assert_roundtrips(input) {
+                panic!("{:?} didn't round trip: {}", input, err);
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrip_reports_parse_errors() {
+        let err = assert_roundtrips("Root\n    - a\nEnd").unwrap_err();
+        assert!(err.path().is_empty());
+        assert!(matches!(err.kind(), RoundtripDiffKind::Parse(_)));
+    }
+
+    #[test]
+    fn roundtrip_reports_write_errors() {
+        let err = assert_roundtrips("Root\n    \"End\" 1\nEnd").unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            RoundtripDiffKind::Write(crate::SMLWriterError::AttributeHasEndKeywordName)
+        ));
+    }
+}
@@ -0,0 +1,207 @@
+use tree_iterators_rs::prelude::TreeNode;
+
+use crate::{SMLAttribute, SMLElement};
+
+/// Converts the tree to XML, with one tag per line indented by 4 spaces
+/// per level. Each element becomes a tag with the element's name.
+/// Attributes with a single non-null value become XML attributes, in
+/// order. All other attributes become child tags named after the
+/// attribute (written before the element's children, in order) holding
+/// one `<value>` tag per value, or `<null/>` for a null value. An
+/// attribute name that appears more than once on an element is always
+/// written as child tags, since XML doesn't allow duplicate attributes.
+///
+/// Characters that aren't allowed in XML names are escaped as `_xHHHH_`,
+/// where HHHH is the character's hexadecimal code point (ex. "Max Speed"
+/// becomes "Max_x0020_Speed").
+pub fn to_xml<StrAsRef>(tree: &TreeNode<SMLElement<StrAsRef>>) -> String
+where
+    StrAsRef: AsRef<str>,
+{
+    let mut result = String::new();
+    write_element(tree, 0, &mut result);
+    result
+}
+
+fn write_element<StrAsRef>(tree: &TreeNode<SMLElement<StrAsRef>>, depth: usize, buf: &mut String)
+where
+    StrAsRef: AsRef<str>,
+{
+    let (inline, nested): (Vec<_>, Vec<_>) = tree.value.attributes.iter().partition(|attribute| {
+        let name = attribute.name.as_ref();
+        matches!(attribute.values.as_slice(), [Some(_)])
+            && tree
+                .value
+                .attributes
+                .iter()
+                .filter(|other| other.name.as_ref() == name)
+                .count()
+                == 1
+    });
+
+    push_indent(depth, buf);
+    buf.push('<');
+    push_name(tree.value.name.as_ref(), buf);
+    for attribute in inline {
+        buf.push(' ');
+        push_name(attribute.name.as_ref(), buf);
+        buf.push_str("=\"");
+        if let Some(Some(value)) = attribute.values.first() {
+            push_escaped(value.as_ref(), buf);
+        }
+        buf.push('"');
+    }
+
+    if nested.is_empty() && tree.children.is_empty() {
+        buf.push_str("/>");
+        return;
+    }
+    buf.push('>');
+
+    for attribute in nested {
+        buf.push('\n');
+        write_nested_attribute(attribute, depth + 1, buf);
+    }
+    for child in tree.children.iter() {
+        buf.push('\n');
+        write_element(child, depth + 1, buf);
+    }
+
+    buf.push('\n');
+    push_indent(depth, buf);
+    buf.push_str("</");
+    push_name(tree.value.name.as_ref(), buf);
+    buf.push('>');
+}
+
+fn write_nested_attribute<StrAsRef>(
+    attribute: &SMLAttribute<StrAsRef>,
+    depth: usize,
+    buf: &mut String,
+) where
+    StrAsRef: AsRef<str>,
+{
+    push_indent(depth, buf);
+    buf.push('<');
+    push_name(attribute.name.as_ref(), buf);
+    if attribute.values.is_empty() {
+        buf.push_str("/>");
+        return;
+    }
+    buf.push('>');
+
+    for value in attribute.values.iter() {
+        buf.push('\n');
+        push_indent(depth + 1, buf);
+        match value {
+            None => buf.push_str("<null/>"),
+            Some(value) => {
+                buf.push_str("<value>");
+                push_escaped(value.as_ref(), buf);
+                buf.push_str("</value>");
+            }
+        }
+    }
+
+    buf.push('\n');
+    push_indent(depth, buf);
+    buf.push_str("</");
+    push_name(attribute.name.as_ref(), buf);
+    buf.push('>');
+}
+
+fn push_indent(depth: usize, buf: &mut String) {
+    for _ in 0..depth {
+        buf.push_str("    ");
+    }
+}
+
+/// Pushes the name, escaping every character that isn't allowed in an
+/// XML name. Only ASCII letters, digits, '_', '-', and '.' (and letters
+/// or '_' at the start) are kept, so the result is also a valid name
+/// without namespaces.
+fn push_name(name: &str, buf: &mut String) {
+    if name.is_empty() {
+        buf.push('_');
+        return;
+    }
+
+    for (i, ch) in name.chars().enumerate() {
+        let allowed = if i == 0 {
+            ch.is_ascii_alphabetic() || ch == '_'
+        } else {
+            ch.is_ascii_alphanumeric() || matches!(ch, '_' | '-' | '.')
+        };
+        if allowed {
+            buf.push(ch);
+        } else {
+            buf.push_str(&format!("_x{:04X}_", ch as u32));
+        }
+    }
+}
+
+/// Pushes the text, escaping it for use in XML content or a double
+/// quoted attribute.
+fn push_escaped(value: &str, buf: &mut String) {
+    for ch in value.chars() {
+        match ch {
+            '&' => buf.push_str("&amp;"),
+            '<' => buf.push_str("&lt;"),
+            '>' => buf.push_str("&gt;"),
+            '"' => buf.push_str("&quot;"),
+            '\'' => buf.push_str("&apos;"),
+            '\n' => buf.push_str("&#xA;"),
+            '\r' => buf.push_str("&#xD;"),
+            '\t' => buf.push_str("&#x9;"),
+            ch => buf.push(ch),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_xml;
+
+    #[test]
+    fn to_xml_converts_example() {
+        let tree = crate::parse(include_str!("../example.txt")).unwrap();
+        assert_eq!(
+            r#"<Configuration>
+    <Video RefreshRate="60" Fullscreen="true">
+        <Resolution>
+            <value>1280</value>
+            <value>720</value>
+        </Resolution>
+    </Video>
+    <Audio Volume="100" Music="80"/>
+    <Player Name="Hero 123"/>
+</Configuration>"#,
+            to_xml(&tree)
+        );
+    }
+
+    #[test]
+    fn to_xml_escapes_names_and_values() {
+        let input =
+            "\"1st Element\"\n    Tag a\n    Tag b\n    Note \"<a & 'b'>\" -\n    Empty -\nEnd";
+        let tree = crate::parse(input).unwrap();
+        assert_eq!(
+            r#"<_x0031_st_x0020_Element>
+    <Tag>
+        <value>a</value>
+    </Tag>
+    <Tag>
+        <value>b</value>
+    </Tag>
+    <Note>
+        <value>&lt;a &amp; &apos;b&apos;&gt;</value>
+        <null/>
+    </Note>
+    <Empty>
+        <null/>
+    </Empty>
+</_x0031_st_x0020_Element>"#,
+            to_xml(&tree)
+        );
+    }
+}
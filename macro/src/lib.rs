@@ -1,4 +1,3 @@
-#![feature(proc_macro_span)]
 #![doc = include_str!("../README.md")]
 
 use std::borrow::Cow;
@@ -7,7 +6,7 @@ use proc_macro::{
     token_stream::IntoIter, Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream,
     TokenTree,
 };
-use simpleml::{parse, SMLElement};
+use simpleml::{parse, ParseError, SMLElement};
 use tree_iterators_rs::prelude::TreeNode;
 
 extern crate proc_macro;
@@ -17,9 +16,32 @@ static DEBUG: bool = false;
 /// Handles parsing and converting the SML into a Rust-based
 /// TreeNode representation for better portability between
 /// SML files.
+///
+/// Invalid SML is reported as a compile error on the offending tokens:
+///
+/// ```compile_fail
+/// use simpleml_macro::sml;
+///
+/// let tree = sml! {
+///     Configuration
+///         Player
+///     End
+/// };
+/// ```
 #[proc_macro]
 pub fn sml(stream: TokenStream) -> TokenStream {
-    let converted_string = reconstruct_source_whitespace(stream.into_iter());
+    sml_internal(stream, false)
+}
+
+/// Equivalent to [sml], but every name and value is a String instead
+/// of a &'static str, so the tree can be modified before it's written.
+#[proc_macro]
+pub fn sml_owned(stream: TokenStream) -> TokenStream {
+    sml_internal(stream, true)
+}
+
+fn sml_internal(stream: TokenStream, owned: bool) -> TokenStream {
+    let converted_string = reconstruct_source_whitespace(stream.clone().into_iter());
     #[cfg(debug_assertions)]
     if DEBUG {
         #[cfg(debug_assertions)]
@@ -27,7 +49,7 @@ pub fn sml(stream: TokenStream) -> TokenStream {
     }
     match parse(&converted_string) {
         Ok(tree) => {
-            let rust = convert_sml_to_rust(tree);
+            let rust = convert_sml_to_rust(tree, owned);
             #[cfg(debug_assertions)]
             if DEBUG {
                 #[cfg(debug_assertions)]
@@ -41,11 +63,390 @@ pub fn sml(stream: TokenStream) -> TokenStream {
                 #[cfg(debug_assertions)]
                 println!("{}", err);
             }
-            panic!("{}", err);
+            let span = match &err {
+                ParseError::SML(err) => find_token_span(stream, err.line_num(), err.column()),
+                _ => None,
+            };
+            compile_error(&err.to_string(), span.unwrap_or_else(Span::call_site))
         }
     }
 }
 
+/// Equivalent to [sml], but accepts a single raw string literal
+/// containing the SML text instead of inline tokens. The contents
+/// of the literal are parsed exactly as written, so this avoids any
+/// issues with reconstructing the source whitespace from the token
+/// stream (tabs, apostrophes, unbalanced brackets, etc.).
+#[proc_macro]
+pub fn sml_raw(stream: TokenStream) -> TokenStream {
+    let span = stream
+        .clone()
+        .into_iter()
+        .next()
+        .map_or_else(Span::call_site, |token| token.span());
+    let source = match extract_raw_string_literal(stream.into_iter()) {
+        Ok(source) => source,
+        Err((message, span)) => return compile_error(&message, span),
+    };
+    match parse(&source) {
+        Ok(tree) => convert_sml_to_rust(tree, false),
+        Err(err) => compile_error(&err.to_string(), span),
+    }
+}
+
+/// Builds a compile_error! invocation with the given message, reported
+/// at span.
+fn compile_error(message: &str, span: Span) -> TokenStream {
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+    let mut message = Literal::string(message);
+    message.set_span(span);
+    let mut args = Group::new(
+        Delimiter::Parenthesis,
+        TokenStream::from(TokenTree::Literal(message)),
+    );
+    args.set_span(span);
+
+    TokenStream::from_iter([
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct(bang),
+        TokenTree::Group(args),
+    ])
+}
+
+/// Finds the token at the given 0-based line and 1-based column of the
+/// text built by [reconstruct_source_whitespace]. If no token starts at
+/// that column, the closest token before it on the same line is used.
+fn find_token_span(stream: TokenStream, line_num: usize, column: usize) -> Option<Span> {
+    fn collect_starts(stream: TokenStream, starts: &mut Vec<(usize, usize, Span)>) {
+        for token_tree in stream {
+            let start = token_tree.span().start();
+            starts.push((start.line(), start.column(), token_tree.span()));
+            if let TokenTree::Group(group) = token_tree {
+                collect_starts(group.stream(), starts);
+            }
+        }
+    }
+
+    let mut starts = Vec::new();
+    collect_starts(stream, &mut starts);
+    let first_line = starts.first()?.0;
+    let on_line = starts
+        .into_iter()
+        .filter(|(line, _, _)| *line == first_line + line_num)
+        .collect::<Vec<_>>();
+    on_line
+        .iter()
+        .filter(|(_, col, _)| *col <= column)
+        .max_by_key(|(_, col, _)| *col)
+        .or_else(|| on_line.first())
+        .map(|(_, _, span)| *span)
+}
+
+/// Asserts that a tree of SMLElements matches an expected structure.
+/// The first argument is an expression evaluating to the tree (or a
+/// reference to it). The second is a brace-wrapped description of the
+/// expected root element:
+///
+/// ```
+/// use simpleml_macro::assert_sml_eq;
+///
+/// let tree = simpleml::parse(include_str!("../../lib/example.txt")).unwrap();
+/// assert_sml_eq!(tree, {
+///     Configuration {
+///         Video {
+///             Resolution: ["1280", "720"],
+///             RefreshRate: [60],
+///             Fullscreen: [true],
+///         }
+///         Audio {
+///             Volume: [100],
+///             Music: [80],
+///         }
+///         Player {
+///             Name: ["Hero 123"],
+///         }
+///     }
+/// });
+/// ```
+///
+/// An element is written as its name followed by braces containing its
+/// attributes and children. An attribute is written as its name, a colon,
+/// and a bracketed list of values, where None is a null value and any
+/// other identifier or non-string literal is compared as its source
+/// text. Names may be either identifiers or string literals. Every
+/// element must match exactly: the same attributes in the same order and
+/// the same children in the same order.
+#[proc_macro]
+pub fn assert_sml_eq(stream: TokenStream) -> TokenStream {
+    match expand_assert_sml_eq(stream) {
+        Ok(result) => result,
+        Err((message, span)) => compile_error(&message, span),
+    }
+}
+
+fn expand_assert_sml_eq(stream: TokenStream) -> Result<TokenStream, (String, Span)> {
+    const EXPECTED: &str = "assert_sml_eq! expects a tree expression followed by a { ... } block";
+    let mut tokens = stream.into_iter().collect::<Vec<_>>();
+    let expected = match tokens.pop() {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => group,
+        Some(other) => return Err((EXPECTED.to_string(), other.span())),
+        None => return Err((EXPECTED.to_string(), Span::call_site())),
+    };
+    match tokens.pop() {
+        Some(TokenTree::Punct(punct)) if punct.as_char() == ',' => {}
+        Some(other) => return Err((EXPECTED.to_string(), other.span())),
+        None => return Err((EXPECTED.to_string(), expected.span())),
+    }
+
+    let mut expected_tokens = expected.stream().into_iter().peekable();
+    let root = parse_expected_element(&mut expected_tokens, expected.span_close())?;
+    if let Some(extra) = expected_tokens.next() {
+        return Err((
+            "assert_sml_eq! expects exactly one root element".to_string(),
+            extra.span(),
+        ));
+    }
+
+    let mut assertions = String::new();
+    write_element_assertions(&root, &root.name, &mut assertions);
+
+    let mut result = TokenStream::new();
+    result.extend("let __sml_node = &".parse::<TokenStream>().unwrap());
+    result.extend([TokenTree::Group(Group::new(
+        Delimiter::Parenthesis,
+        TokenStream::from_iter(tokens),
+    ))]);
+    result.extend(";".parse::<TokenStream>().unwrap());
+    result.extend(assertions.parse::<TokenStream>().unwrap());
+    Ok(TokenStream::from(TokenTree::Group(Group::new(
+        Delimiter::Brace,
+        result,
+    ))))
+}
+
+/// An element in the expected structure of [assert_sml_eq].
+struct ExpectedElement {
+    name: String,
+    /// The attribute names and the Rust source of each value.
+    attributes: Vec<(String, Vec<String>)>,
+    children: Vec<ExpectedElement>,
+}
+
+/// Parses the next element from tokens. end is the span reported if the
+/// tokens run out (the closing brace around them).
+fn parse_expected_element(
+    tokens: &mut std::iter::Peekable<IntoIter>,
+    end: Span,
+) -> Result<ExpectedElement, (String, Span)> {
+    let name_token = tokens.next();
+    let name_span = name_token.as_ref().map_or(end, TokenTree::span);
+    let name = parse_expected_name(name_token, end)?;
+    let body = match tokens.next() {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => group,
+        other => {
+            return Err((
+                format!("assert_sml_eq! expected {{ ... }} after element {}", name),
+                other.map_or(name_span, |other| other.span()),
+            ))
+        }
+    };
+
+    let mut element = ExpectedElement {
+        name,
+        attributes: Vec::new(),
+        children: Vec::new(),
+    };
+
+    let body_end = body.span_close();
+    let mut body = body.stream().into_iter().peekable();
+    while body.peek().is_some() {
+        match body.peek() {
+            Some(TokenTree::Punct(punct)) if punct.as_char() == ',' => {
+                body.next();
+                continue;
+            }
+            _ => {}
+        }
+
+        let mut lookahead = body.clone();
+        lookahead.next();
+        match lookahead.next() {
+            Some(TokenTree::Punct(punct)) if punct.as_char() == ':' => {
+                let name = parse_expected_name(body.next(), body_end)?;
+                let colon_span = body.next().map_or(body_end, |colon| colon.span());
+                let values = match body.next() {
+                    Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Bracket => {
+                        parse_expected_values(group.stream())?
+                    }
+                    other => {
+                        return Err((
+                            format!("assert_sml_eq! expected [ ... ] after attribute {}:", name),
+                            other.map_or(colon_span, |other| other.span()),
+                        ))
+                    }
+                };
+                element.attributes.push((name, values));
+            }
+            _ => element
+                .children
+                .push(parse_expected_element(&mut body, body_end)?),
+        }
+    }
+
+    Ok(element)
+}
+
+/// Parses an element or attribute name. end is the span reported if
+/// there is no token.
+fn parse_expected_name(token: Option<TokenTree>, end: Span) -> Result<String, (String, Span)> {
+    match token {
+        Some(TokenTree::Ident(ident)) => Ok(ident.to_string()),
+        Some(TokenTree::Literal(literal)) => {
+            let text = literal.to_string();
+            let raw = text.strip_prefix('r').map(|raw| raw.trim_matches('#'));
+            match raw.unwrap_or(&text).strip_prefix('"') {
+                Some(quoted) if raw.is_some() || !quoted.contains('\\') => {
+                    Ok(quoted[..quoted.len() - 1].to_string())
+                }
+                _ => Err((
+                    format!(
+                        "assert_sml_eq! expected a name without escape sequences, but found {}",
+                        text
+                    ),
+                    literal.span(),
+                )),
+            }
+        }
+        Some(other) => Err((
+            format!("assert_sml_eq! expected a name, but found {}", other),
+            other.span(),
+        )),
+        None => Err(("assert_sml_eq! expected a name".to_string(), end)),
+    }
+}
+
+fn parse_expected_values(stream: TokenStream) -> Result<Vec<String>, (String, Span)> {
+    let mut values = Vec::new();
+    let mut tokens = stream.into_iter();
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Punct(punct) if punct.as_char() == ',' => {}
+            TokenTree::Ident(ident) if ident.to_string() == "None" => {
+                values.push("::core::option::Option::None".to_string())
+            }
+            TokenTree::Ident(ident) => values.push(format!(
+                "::core::option::Option::Some({:?})",
+                ident.to_string()
+            )),
+            TokenTree::Literal(literal) => {
+                let literal = literal.to_string();
+                if literal.starts_with('"') || literal.starts_with('r') {
+                    values.push(format!("::core::option::Option::Some({})", literal));
+                } else {
+                    values.push(format!("::core::option::Option::Some({:?})", literal));
+                }
+            }
+            // A negative number is a '-' followed by its literal.
+            TokenTree::Punct(punct) if punct.as_char() == '-' => match tokens.next() {
+                Some(TokenTree::Literal(literal))
+                    if !literal.to_string().starts_with(['"', 'r', 'b', '\'']) =>
+                {
+                    values.push(format!("::core::option::Option::Some(\"-{}\")", literal))
+                }
+                other => {
+                    return Err((
+                        "assert_sml_eq! expected a number after -".to_string(),
+                        other.map_or(punct.span(), |other| other.span()),
+                    ))
+                }
+            },
+            other => {
+                return Err((
+                    format!("assert_sml_eq! expected a value, but found {}", other),
+                    other.span(),
+                ))
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Writes the assertions for the element bound to __sml_node.
+fn write_element_assertions(element: &ExpectedElement, path: &str, buf: &mut String) {
+    use std::fmt::Write;
+
+    let _ = write!(
+        buf,
+        "assert_eq!({:?}, ::core::convert::AsRef::<str>::as_ref(&__sml_node.value.name), \"element name mismatch at {{}}\", {:?});",
+        element.name, path
+    );
+    let _ = write!(
+        buf,
+        "assert_eq!({}, __sml_node.value.attributes.len(), \"attribute count mismatch at {{}}\", {:?});",
+        element.attributes.len(),
+        path
+    );
+    for (i, (name, values)) in element.attributes.iter().enumerate() {
+        let attribute_path = format!("{}:{}", path, name);
+        let _ = write!(
+            buf,
+            "{{ let __sml_attribute = &__sml_node.value.attributes[{}]; \
+            assert_eq!({:?}, ::core::convert::AsRef::<str>::as_ref(&__sml_attribute.name), \"attribute name mismatch at {{}}\", {:?}); \
+            let __sml_expected: ::std::vec::Vec<::core::option::Option<&str>> = ::std::vec![{}]; \
+            let __sml_actual = __sml_attribute.values.iter() \
+                .map(|value| value.as_ref().map(|value| ::core::convert::AsRef::<str>::as_ref(value))) \
+                .collect::<::std::vec::Vec<::core::option::Option<&str>>>(); \
+            assert_eq!(__sml_expected, __sml_actual, \"attribute values mismatch at {{}}\", {:?}); }}",
+            i,
+            name,
+            attribute_path,
+            values.join(", "),
+            attribute_path
+        );
+    }
+
+    let _ = write!(
+        buf,
+        "assert_eq!({}, __sml_node.children.len(), \"child count mismatch at {{}}\", {:?});",
+        element.children.len(),
+        path
+    );
+    for (i, child) in element.children.iter().enumerate() {
+        let _ = write!(buf, "{{ let __sml_node = &__sml_node.children[{}];", i);
+        write_element_assertions(child, &format!("{}/{}", path, child.name), buf);
+        buf.push('}');
+    }
+}
+
+/// Gets the contents of the single raw string literal in the stream, or
+/// an error message and the span of the token it applies to.
+fn extract_raw_string_literal(mut stream: IntoIter) -> Result<String, (String, Span)> {
+    const EXPECTED: &str = "sml_raw! expects a single raw string literal (ex. r#\"...\"#)";
+    let literal = match stream.next() {
+        Some(TokenTree::Literal(literal)) => literal,
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::None => {
+            return extract_raw_string_literal(group.stream().into_iter());
+        }
+        Some(token) => return Err((EXPECTED.to_string(), token.span())),
+        None => return Err((EXPECTED.to_string(), Span::call_site())),
+    };
+    if let Some(extra) = stream.next() {
+        return Err((EXPECTED.to_string(), extra.span()));
+    }
+
+    let span = literal.span();
+    let literal = literal.to_string();
+    let without_prefix = match literal.strip_prefix('r') {
+        None => return Err((EXPECTED.to_string(), span)),
+        Some(without_prefix) => without_prefix,
+    };
+    let hashes = without_prefix.chars().take_while(|ch| *ch == '#').count();
+    // The literal is guaranteed by the compiler to be well-formed, so the
+    // content sits between the opening r#" and the closing "# delimiters.
+    Ok(without_prefix[hashes + 1..without_prefix.len() - hashes - 1].to_string())
+}
+
 fn reconstruct_source_whitespace(stream: IntoIter) -> String {
     let mut result = String::new();
     reconstruct_source_whitespace_internal(stream, &mut result, None);
@@ -63,28 +464,34 @@ fn reconstruct_source_whitespace_internal(
         let token_end = token_tree.span().end();
         end_position = Some((token_end.line(), token_end.column()));
 
+        // Spans aren't guaranteed to be consistent (rust-analyzer reports
+        // different columns than rustc), so none of the arithmetic below
+        // may underflow. Tokens that appear to move backwards are still
+        // separated by at least one line break or space.
         match previous_token_end {
             None => {
-                for _ in 0..token_start.column() - 1 {
+                for _ in 0..token_start.column().saturating_sub(1) {
                     builder.push(' ');
                 }
             }
             Some(end) => {
                 // insert a new line to match the token stream
                 if end.0 != token_start.line() {
-                    for _ in 0..token_start.line() - end.0 {
+                    for _ in 0..token_start.line().saturating_sub(end.0).max(1) {
                         builder.push('\n');
                     }
 
-                    for _ in 0..token_start.column() - 1 {
+                    for _ in 0..token_start.column().saturating_sub(1) {
                         builder.push(' ');
                     }
 
                     // insert a space to match the token stream
-                } else if end.1 != token_start.column() {
-                    for _ in 0..token_start.column() - end.1 {
+                } else if end.1 < token_start.column() {
+                    for _ in 0..token_start.column().saturating_sub(end.1) {
                         builder.push(' ');
                     }
+                } else if end.1 > token_start.column() {
+                    builder.push(' ');
                 }
             }
         }
@@ -113,7 +520,7 @@ fn reconstruct_source_whitespace_internal(
                         let bracket_end = token_start;
                         if line != bracket_end.line() {
                             builder.push('\n');
-                        } else if col != bracket_end.column() - 1 {
+                        } else if col != bracket_end.column().saturating_sub(1) {
                             builder.push(' ');
                         }
                     }
@@ -137,7 +544,25 @@ fn reconstruct_source_whitespace_internal(
     end_position
 }
 
-fn convert_sml_to_rust(tree: TreeNode<SMLElement<Cow<'_, str>>>) -> TokenStream {
+/// Converts the string to a string literal token, wrapped in a call to
+/// String::from if owned is set.
+fn string_token(value: &str, owned: bool) -> TokenTree {
+    let literal = TokenTree::Literal(Literal::string(value));
+    if !owned {
+        return literal;
+    }
+
+    let mut tokens = "::std::string::String::from"
+        .parse::<TokenStream>()
+        .unwrap();
+    tokens.extend([TokenTree::Group(Group::new(
+        Delimiter::Parenthesis,
+        TokenStream::from(literal),
+    ))]);
+    TokenTree::Group(Group::new(Delimiter::None, tokens))
+}
+
+fn convert_sml_to_rust(tree: TreeNode<SMLElement<Cow<'_, str>>>, owned: bool) -> TokenStream {
     TokenStream::from_iter([
         TokenTree::Ident(Ident::new("tree_iterators_rs", Span::call_site())),
         TokenTree::Punct(Punct::new(':', Spacing::Joint)),
@@ -160,7 +585,7 @@ fn convert_sml_to_rust(tree: TreeNode<SMLElement<Cow<'_, str>>>) -> TokenStream
                     TokenStream::from_iter([
                         TokenTree::Ident(Ident::new("name", Span::call_site())),
                         TokenTree::Punct(Punct::new(':', Spacing::Alone)),
-                        TokenTree::Literal(Literal::string(&tree.value.name)),
+                        string_token(&tree.value.name, owned),
                         TokenTree::Punct(Punct::new(',', Spacing::Alone)),
                         TokenTree::Ident(Ident::new("attributes", Span::call_site())),
                         TokenTree::Punct(Punct::new(':', Spacing::Alone)),
@@ -179,7 +604,7 @@ fn convert_sml_to_rust(tree: TreeNode<SMLElement<Cow<'_, str>>>) -> TokenStream
                                         TokenStream::from_iter([
                                             TokenTree::Ident(Ident::new("name", Span::call_site())),
                                             TokenTree::Punct(Punct::new(':', Spacing::Alone)),
-                                            TokenTree::Literal(Literal::string(&attr.name)),
+                                            string_token(&attr.name, owned),
                                             TokenTree::Punct(Punct::new(',', Spacing::Alone)),
                                             TokenTree::Ident(Ident::new(
                                                 "values",
@@ -211,10 +636,9 @@ fn convert_sml_to_rust(tree: TreeNode<SMLElement<Cow<'_, str>>>) -> TokenStream
                                                                     Group::new(
                                                                         Delimiter::Parenthesis,
                                                                         TokenStream::from(
-                                                                            TokenTree::Literal(
-                                                                                Literal::string(
-                                                                                    str.as_ref(),
-                                                                                ),
+                                                                            string_token(
+                                                                                str.as_ref(),
+                                                                                owned,
                                                                             ),
                                                                         ),
                                                                     ),
@@ -248,7 +672,9 @@ fn convert_sml_to_rust(tree: TreeNode<SMLElement<Cow<'_, str>>>) -> TokenStream
                 TokenTree::Group(Group::new(
                     Delimiter::Bracket,
                     TokenStream::from_iter(tree.children.into_iter().flat_map(|child| {
-                        let mut stream = convert_sml_to_rust(child).into_iter().collect::<Vec<_>>();
+                        let mut stream = convert_sml_to_rust(child, owned)
+                            .into_iter()
+                            .collect::<Vec<_>>();
                         stream.push(TokenTree::Punct(Punct::new(',', Spacing::Alone)));
                         stream
                     })),
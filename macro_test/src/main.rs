@@ -1,4 +1,4 @@
-use simpleml_macro::sml;
+use simpleml_macro::{assert_sml_eq, sml, sml_owned, sml_raw};
 
 fn main() {
     let tree = sml!{
@@ -17,5 +17,99 @@ fn main() {
             my_custom_end_keyword
         my_custom_end_keyword
     };
-    println!("{tree:?}")
+    println!("{tree:?}");
+
+    // Tabs, apostrophes, comments, and unbalanced brackets would all
+    // confuse the token stream reconstruction of sml!
+    let raw_tree = sml_raw!(r#"
+Configuration
+	Player
+		Name "Hero ""The Great"" 123" # quoted, with escaped quotes
+		Motto Don't-panic
+		Inventory [sword shield
+	End
+End"#);
+    assert_eq!("Player", raw_tree.children[0].value.name);
+    assert_eq!(
+        Some("Hero \"The Great\" 123"),
+        raw_tree.children[0].value.attributes[0].values[0]
+    );
+    assert_eq!(
+        Some("Don't-panic"),
+        raw_tree.children[0].value.attributes[1].values[0]
+    );
+    println!("{raw_tree:?}");
+
+    // Compare a parsed document against its expected structure without
+    // drilling through the tree by hand.
+    let parsed = simpleml::parse(
+        "Configuration\n  Video\n    Resolution 1280 720\n    RefreshRate 60\n  End\n  Player\n    \"Display Name\" \"Hero 123\" -\n  End\nEnd",
+    )
+    .unwrap();
+    assert_sml_eq!(parsed, {
+        Configuration {
+            Video {
+                Resolution: ["1280", "720"],
+                RefreshRate: [60],
+            }
+            Player {
+                "Display Name": ["Hero 123", None],
+            }
+        }
+    });
+    assert_sml_eq!(&raw_tree, {
+        Configuration {
+            Player {
+                Name: ["Hero \"The Great\" 123"],
+                Motto: ["Don't-panic"],
+                Inventory: ["[sword", "shield"],
+            }
+        }
+    });
+
+    // A bare - is the null end keyword, or a null value on an attribute
+    // line.
+    let null_tree = sml!{
+        Configuration
+            Video
+                Resolution 1280 720
+                Title - "My Game" -
+            -
+            Audio
+                Offset -5
+            -
+        -
+    };
+    assert_sml_eq!(null_tree, {
+        Configuration {
+            Video {
+                Resolution: ["1280", "720"],
+                Title: [None, "My Game", None],
+            }
+            Audio {
+                Offset: [-5],
+            }
+        }
+    });
+    println!("{null_tree:?}");
+
+    // sml_owned! builds Strings, so the tree can be changed before it's
+    // written.
+    let mut owned_tree = sml_owned!{
+        Configuration
+            Player
+                Name "Hero 123"
+            End
+        End
+    };
+    owned_tree.children[0].value.attributes[0].values[0] = Some("Villain 456".to_string());
+    owned_tree.value.name.push_str("Backup");
+    assert_sml_eq!(owned_tree, {
+        ConfigurationBackup {
+            Player {
+                Name: ["Villain 456"],
+            }
+        }
+    });
+    println!("{owned_tree:?}");
 }